@@ -0,0 +1,292 @@
+// Raymarches a distance-estimated fractal inside the cube mesh it is drawn on.
+//
+// The estimators mirror `src/distance_estimators.rs` line for line, and the parameter structs
+// are the same Rust structs uploaded as uniforms, so the GPU and CPU versions agree.
+
+#import bevy_pbr::mesh_vertex_output MeshVertexOutput
+#import bevy_pbr::mesh_bindings mesh
+#import bevy_pbr::mesh_view_bindings view
+#import bevy_pbr::pbr_functions as pbr_functions
+#import bevy_core_pipeline::tonemapping tone_mapping
+
+struct RaymarchSettings {
+    base_color: vec4<f32>,
+    kind: u32,
+    max_steps: u32,
+    hit_epsilon: f32,
+    bounds: f32,
+    perceptual_roughness: f32,
+    metallic: f32,
+};
+
+struct Mandelbulb {
+    power: f32,
+    iterations: u32,
+    bailout: f32,
+};
+
+struct Mandelbox {
+    scale: f32,
+    min_radius: f32,
+    fixed_radius: f32,
+    folding_limit: f32,
+    iterations: u32,
+    bailout: f32,
+};
+
+struct QuaternionJulia {
+    c: vec4<f32>,
+    iterations: u32,
+    bailout: f32,
+};
+
+struct MengerSponge {
+    half_size: f32,
+    iterations: u32,
+};
+
+struct SierpinskiTetrahedron {
+    half_size: f32,
+    iterations: u32,
+};
+
+@group(1) @binding(100) var<uniform> settings: RaymarchSettings;
+@group(1) @binding(101) var<uniform> mandelbulb: Mandelbulb;
+@group(1) @binding(102) var<uniform> mandelbox: Mandelbox;
+@group(1) @binding(103) var<uniform> julia: QuaternionJulia;
+@group(1) @binding(104) var<uniform> menger: MengerSponge;
+@group(1) @binding(105) var<uniform> sierpinski: SierpinskiTetrahedron;
+
+const INTERIOR: f32 = -1e-4;
+
+fn mandelbulb_distance(p: vec3<f32>) -> f32 {
+    var z = p;
+    var dr = 1.0;
+    var r = length(z);
+    for (var i = 0u; i < mandelbulb.iterations; i += 1u) {
+        if r > mandelbulb.bailout {
+            return 0.5 * log(r) * r / dr;
+        }
+
+        // `pow` is undefined at zero, where the CPU version simply gets zero back
+        let safe_r = max(r, 1e-20);
+        let theta = acos(clamp(z.z / safe_r, -1.0, 1.0)) * mandelbulb.power;
+        let phi = atan2(z.y, z.x) * mandelbulb.power;
+        dr = pow(safe_r, mandelbulb.power - 1.0) * mandelbulb.power * dr + 1.0;
+        let zr = pow(safe_r, mandelbulb.power);
+        z = zr * vec3(sin(theta) * cos(phi), sin(phi) * sin(theta), cos(theta)) + p;
+        r = length(z);
+    }
+    if r > mandelbulb.bailout {
+        return 0.5 * log(r) * r / dr;
+    }
+    return INTERIOR;
+}
+
+fn mandelbox_distance(p: vec3<f32>) -> f32 {
+    let min_radius2 = mandelbox.min_radius * mandelbox.min_radius;
+    let fixed_radius2 = mandelbox.fixed_radius * mandelbox.fixed_radius;
+    var z = p;
+    var dr = 1.0;
+    for (var i = 0u; i < mandelbox.iterations; i += 1u) {
+        z = clamp(z, vec3(-mandelbox.folding_limit), vec3(mandelbox.folding_limit)) * 2.0 - z;
+
+        let r2 = dot(z, z);
+        if r2 < min_radius2 {
+            let factor = fixed_radius2 / min_radius2;
+            z *= factor;
+            dr *= factor;
+        } else if r2 < fixed_radius2 {
+            let factor = fixed_radius2 / r2;
+            z *= factor;
+            dr *= factor;
+        }
+
+        z = z * mandelbox.scale + p;
+        dr = dr * abs(mandelbox.scale) + 1.0;
+        if length(z) > mandelbox.bailout {
+            return length(z) / abs(dr);
+        }
+    }
+    return INTERIOR;
+}
+
+fn quaternion_square(q: vec4<f32>) -> vec4<f32> {
+    return vec4(
+        q.x * q.x - q.y * q.y - q.z * q.z - q.w * q.w,
+        2.0 * q.x * q.y,
+        2.0 * q.x * q.z,
+        2.0 * q.x * q.w,
+    );
+}
+
+fn quaternion_multiply(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    return vec4(
+        a.x * b.x - a.y * b.y - a.z * b.z - a.w * b.w,
+        a.x * b.y + a.y * b.x + a.z * b.w - a.w * b.z,
+        a.x * b.z - a.y * b.w + a.z * b.x + a.w * b.y,
+        a.x * b.w + a.y * b.z - a.z * b.y + a.w * b.x,
+    );
+}
+
+fn julia_distance(p: vec3<f32>) -> f32 {
+    var q = vec4(p, 0.0);
+    var dq = vec4(1.0, 0.0, 0.0, 0.0);
+    for (var i = 0u; i < julia.iterations; i += 1u) {
+        dq = 2.0 * quaternion_multiply(q, dq);
+        q = quaternion_square(q) + julia.c;
+        let r = length(q);
+        if r > julia.bailout {
+            return 0.5 * r * log(r) / length(dq);
+        }
+    }
+    return INTERIOR;
+}
+
+fn menger_distance(position: vec3<f32>) -> f32 {
+    let p = position / menger.half_size;
+    let q = abs(p) - vec3(1.0);
+    var d = length(max(q, vec3(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+
+    var s = 1.0;
+    for (var i = 0u; i < menger.iterations; i += 1u) {
+        let scaled = p * s;
+        let a = scaled - 2.0 * floor(scaled / 2.0) - 1.0;
+        s *= 3.0;
+        let r = abs(vec3(1.0) - 3.0 * abs(a));
+        let da = max(r.x, r.y);
+        let db = max(r.y, r.z);
+        let dc = max(r.z, r.x);
+        let c = (min(da, min(db, dc)) - 1.0) / s;
+        d = max(d, c);
+    }
+    return d * menger.half_size;
+}
+
+fn sierpinski_distance(p: vec3<f32>) -> f32 {
+    var z = p / sierpinski.half_size;
+    var scale = 1.0;
+    for (var i = 0u; i < sierpinski.iterations; i += 1u) {
+        if z.x + z.y < 0.0 {
+            z = vec3(-z.y, -z.x, z.z);
+        }
+        if z.x + z.z < 0.0 {
+            z = vec3(-z.z, z.y, -z.x);
+        }
+        if z.y + z.z < 0.0 {
+            z = vec3(z.x, -z.z, -z.y);
+        }
+        z = z * 2.0 - vec3(1.0);
+        scale *= 2.0;
+    }
+
+    let tetrahedron = max(
+        max(-z.x - z.y - z.z, z.x + z.y - z.z),
+        max(-z.x + z.y + z.z, z.x - z.y + z.z),
+    );
+    return (tetrahedron - 1.0) / sqrt(3.0) / scale * sierpinski.half_size;
+}
+
+// Cases follow the order of the `Estimator` variants
+fn distance(p: vec3<f32>) -> f32 {
+    switch settings.kind {
+        case 0u: {
+            return mandelbulb_distance(p);
+        }
+        case 1u: {
+            return mandelbox_distance(p);
+        }
+        case 2u: {
+            return julia_distance(p);
+        }
+        case 3u: {
+            return menger_distance(p);
+        }
+        default: {
+            return sierpinski_distance(p);
+        }
+    }
+}
+
+fn estimate_normal(p: vec3<f32>) -> vec3<f32> {
+    let e = settings.hit_epsilon;
+    return normalize(vec3(
+        distance(p + vec3(e, 0.0, 0.0)) - distance(p - vec3(e, 0.0, 0.0)),
+        distance(p + vec3(0.0, e, 0.0)) - distance(p - vec3(0.0, e, 0.0)),
+        distance(p + vec3(0.0, 0.0, e)) - distance(p - vec3(0.0, 0.0, e)),
+    ));
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fragment(in: MeshVertexOutput) -> FragmentOutput {
+    // Only the cube's back faces are drawn, so this runs once per pixel whether the camera is
+    // outside the cube or inside it
+    let is_orthographic = view.projection[3].w == 1.0;
+    let direction_world = -pbr_functions::calculate_view(in.world_position, is_orthographic);
+
+    // March in the fractal's own space, where the cube spans `-bounds..bounds`
+    let world_to_local = transpose(mesh.inverse_transpose_model);
+    let direction = normalize((world_to_local * vec4(direction_world, 0.0)).xyz);
+    var origin = (world_to_local * vec4(view.world_position, 1.0)).xyz;
+    if is_orthographic {
+        // Parallel rays have no common origin; start each one well in front of the cube
+        origin = (world_to_local * in.world_position).xyz - direction * 4.0 * settings.bounds;
+    }
+
+    let t0 = (vec3(-settings.bounds) - origin) / direction;
+    let t1 = (vec3(settings.bounds) - origin) / direction;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    let exit = min(far.x, min(far.y, far.z));
+    var t = max(max(near.x, max(near.y, near.z)), 0.0);
+
+    var hit = false;
+    var steps = 0u;
+    for (; steps < settings.max_steps; steps += 1u) {
+        let d = distance(origin + direction * t);
+        if d < settings.hit_epsilon {
+            hit = true;
+            break;
+        }
+        t += d;
+        if t > exit {
+            break;
+        }
+    }
+    if !hit {
+        discard;
+    }
+
+    let local_hit = origin + direction * t;
+    let world_hit = vec4((mesh.model * vec4(local_hit, 1.0)).xyz, 1.0);
+    let world_normal = normalize((mesh.inverse_transpose_model * vec4(estimate_normal(local_hit), 0.0)).xyz);
+    let clip = view.view_proj * world_hit;
+    let depth = clip.z / clip.w;
+
+    var pbr_input = pbr_functions::pbr_input_new();
+    pbr_input.material.base_color = settings.base_color;
+    pbr_input.material.perceptual_roughness = settings.perceptual_roughness;
+    pbr_input.material.metallic = settings.metallic;
+    // Crevices take many steps to reach, which makes a cheap stand-in for ambient occlusion
+    pbr_input.occlusion = vec3(1.0 - f32(steps) / f32(settings.max_steps));
+    pbr_input.frag_coord = vec4(in.position.xy, depth, 1.0);
+    pbr_input.world_position = world_hit;
+    pbr_input.world_normal = world_normal;
+    pbr_input.N = world_normal;
+    pbr_input.V = pbr_functions::calculate_view(world_hit, is_orthographic);
+    pbr_input.is_orthographic = is_orthographic;
+    pbr_input.flags = mesh.flags;
+
+    var out: FragmentOutput;
+    out.color = pbr_functions::pbr(pbr_input);
+#ifdef TONEMAP_IN_SHADER
+    out.color = tone_mapping(out.color, view.color_grading);
+#endif
+    out.depth = depth;
+    return out;
+}
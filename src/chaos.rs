@@ -0,0 +1,169 @@
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use bevy::render::mesh::PrimitiveTopology;
+
+use crate::ifs::Ifs;
+
+/// Steps taken before points are recorded so the walk has settled onto the attractor
+const BURN_IN: usize = 32;
+
+/// Small deterministic generator so a given seed always gives the same cloud
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// How likely each map is to be picked: the IFS's own weights if it has them, otherwise in
+/// proportion to how much volume each map keeps so the cloud comes out evenly dense
+pub fn map_probabilities(ifs: &Ifs) -> Vec<f32> {
+    let weights: Vec<f32> = match ifs.weights() {
+        Some(weights) => weights.to_vec(),
+        None => ifs
+            .maps
+            .iter()
+            .map(|map| map.matrix3.determinant().abs().max(1e-6))
+            .collect(),
+    };
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+/// Plays the chaos game on `ifs`, returning `count` points on its attractor together with the
+/// index of the map that produced each one
+pub fn chaos_game(ifs: &Ifs, root: Affine3A, count: usize, seed: u64) -> Vec<(Vec3, usize)> {
+    if ifs.maps.is_empty() {
+        return Vec::new();
+    }
+
+    let cumulative: Vec<f32> = map_probabilities(ifs)
+        .iter()
+        .scan(0.0, |sum, p| {
+            *sum += p;
+            Some(*sum)
+        })
+        .collect();
+    let mut rng = SplitMix64::new(seed);
+    let mut point = Vec3::ZERO;
+    let mut points = Vec::with_capacity(count);
+
+    for step in 0..count + BURN_IN {
+        let roll = rng.next_f32();
+        let index = cumulative
+            .iter()
+            .position(|&c| roll < c)
+            .unwrap_or(ifs.maps.len() - 1);
+        point = ifs.maps[index].transform_point3(point);
+        if step >= BURN_IN {
+            points.push((root.transform_point3(point), index));
+        }
+    }
+    points
+}
+
+/// Builds a point-list mesh from the chaos game, each point coloured by the last map applied
+pub fn chaos_game_mesh(ifs: &Ifs, root: Affine3A, count: usize, seed: u64) -> Mesh {
+    let palette: Vec<[f32; 4]> = (0..ifs.maps.len())
+        .map(|i| {
+            Color::hsl(360.0 * i as f32 / ifs.maps.len() as f32, 0.8, 0.6).as_linear_rgba_f32()
+        })
+        .collect();
+
+    let (positions, colors): (Vec<[f32; 3]>, Vec<[f32; 4]>) = chaos_game(ifs, root, count, seed)
+        .into_iter()
+        .map(|(point, index)| (point.to_array(), palette[index]))
+        .unzip();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::mesh::VertexAttributeValues;
+
+    use super::*;
+
+    /// The Cantor set along x: map 0 lands in `[0, 0.4]` and map 1 in `[0.6, 1]`
+    fn cantor() -> Ifs {
+        Ifs::new(vec![
+            Affine3A::from_scale(Vec3::splat(0.4)),
+            Affine3A::from_scale_rotation_translation(
+                Vec3::splat(0.4),
+                Quat::IDENTITY,
+                Vec3::X * 0.6,
+            ),
+        ])
+    }
+
+    #[test]
+    fn same_seed_same_cloud() {
+        let ifs = Ifs::sierpinski_tetrahedron();
+        let root = Affine3A::from_scale(Vec3::splat(2.0));
+        let cloud = chaos_game(&ifs, root, 500, 7);
+        assert_eq!(cloud.len(), 500);
+        assert_eq!(cloud, chaos_game(&ifs, root, 500, 7));
+        assert_ne!(cloud, chaos_game(&ifs, root, 500, 8));
+    }
+
+    #[test]
+    fn weights_are_normalised() {
+        assert_eq!(
+            map_probabilities(&cantor().with_weights(vec![1.0, 3.0])),
+            [0.25, 0.75]
+        );
+        // Without weights, equal-sized maps are equally likely
+        let menger = map_probabilities(&Ifs::menger());
+        assert!(menger.iter().all(|&p| (p - 0.05).abs() < 1e-6));
+        let tree: f32 = map_probabilities(&Ifs::spiral_tree()).iter().sum();
+        assert!((tree - 1.0).abs() < 1e-6);
+
+        let cloud = chaos_game(
+            &cantor().with_weights(vec![1.0, 3.0]),
+            Affine3A::IDENTITY,
+            10_000,
+            1,
+        );
+        let second = cloud.iter().filter(|(_, index)| *index == 1).count();
+        assert!((7200..=7800).contains(&second), "{second}");
+    }
+
+    #[test]
+    fn points_take_the_colour_of_their_last_map() {
+        let mesh = chaos_game_mesh(&cantor(), Affine3A::IDENTITY, 1000, 3);
+        let (
+            Some(VertexAttributeValues::Float32x3(positions)),
+            Some(VertexAttributeValues::Float32x4(colors)),
+        ) = (
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+            mesh.attribute(Mesh::ATTRIBUTE_COLOR),
+        )
+        else {
+            panic!("missing attributes");
+        };
+        assert_eq!(positions.len(), 1000);
+        let left = colors[positions.iter().position(|p| p[0] <= 0.4).unwrap()];
+        let right = colors[positions.iter().position(|p| p[0] >= 0.6).unwrap()];
+        assert_ne!(left, right);
+        for (position, &color) in positions.iter().zip(colors) {
+            assert_eq!(color, if position[0] <= 0.4 { left } else { right });
+        }
+    }
+}
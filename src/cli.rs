@@ -0,0 +1,669 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use image::RgbImage;
+
+use crate::export::{export, ExportFormat, ExportMaterial, ExportOptions, ExportScene};
+use crate::generator::{
+    FractalGeometry, FractalRegistry, ParamKind, ParamSpec, ParamValue, ParamValues, SeedShape,
+};
+use crate::path_tracer::{PathTraceSettings, Scene, TraceCamera};
+use crate::rule_sponge::SpongePattern;
+use crate::vox::MAX_VOX_RESOLUTION;
+
+pub const USAGE: &str = "\
+Usage:
+    bevy_3d_fractals
+        Opens the interactive viewer
+    bevy_3d_fractals list
+        Lists every fractal with its parameters
+    bevy_3d_fractals export --fractal <name> [--depth <n>] [--param <name>=<value>]...
+                            [--format <format>] [--weld] [--scale <mm>] [--min-feature <mm>]
+                            [--hierarchy] [--resolution <n>] <output>
+        Generates a fractal and writes it to <output> without opening a window; the format
+        defaults to the one matching the output's extension. STL exports are checked for
+        printing at --scale millimetres per unit, against a printer that cannot make details
+        under --min-feature millimetres; --weld merges touching parts into one surface where
+        their faces match exactly, leaving contacts between faces of different sizes as they
+        are.
+        glTF exports hold one merged mesh, or with --hierarchy a node per sub-structure of
+        the recursion, every copy of the seed sharing one mesh; --hierarchy sets
+        merged=false unless it is passed. VOX exports fill a grid of
+        --resolution voxels along each side, by default one voxel per cell of the grid the
+        fractal is built on
+    bevy_3d_fractals render --fractal <name> [--depth <n>] [--param <name>=<value>]...
+                            [--camera <x,y,z>] [--look-at <x,y,z>] [--fov <degrees>]
+                            [--light <x,y,z>] [--size <width>x<height>] [--samples <n>]
+                            [--bounces <n>] <output.png>
+        Generates a fractal at the origin and path traces it on the CPU to a PNG, without a
+        window or GPU. The camera sits at --camera looking at --look-at, and one point light
+        at --light; point clouds have no surface to render";
+
+/// Exported fractals get the colour the viewer draws them in
+const FRACTAL_COLOR: Color = Color::rgb(0.8, 0.7, 0.6);
+/// Rendered fractals are lit like the viewer's, by a point light this bright
+const LIGHT_INTENSITY: f32 = 9000.0;
+
+/// What the command line asked for, when it asked for more than the viewer
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    List,
+    Export(ExportCommand),
+    Render(RenderCommand),
+}
+
+/// The options naming a fractal and its parameters, shared by `export` and `render`
+#[derive(Default)]
+struct FractalArgs {
+    fractal: Option<String>,
+    depth: Option<u32>,
+    params: Vec<(String, String)>,
+}
+
+impl FractalArgs {
+    /// Takes `arg` if it is one of these options, reading its value with `value`
+    fn take(
+        &mut self,
+        arg: &str,
+        value: impl FnOnce() -> Result<String, String>,
+    ) -> Result<bool, String> {
+        match arg {
+            "--fractal" => self.fractal = Some(value()?),
+            "--depth" => {
+                let text = value()?;
+                let parsed = text
+                    .parse()
+                    .map_err(|_| format!("`--depth` expects a whole number, got `{text}`"))?;
+                self.depth = Some(parsed);
+            }
+            "--param" => {
+                let text = value()?;
+                let (name, value) = text
+                    .split_once('=')
+                    .ok_or_else(|| format!("`--param` expects <name>=<value>, got `{text}`"))?;
+                self.params.push((name.to_string(), value.to_string()));
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Options of the `export` command
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct ExportCommand {
+    pub fractal: String,
+    pub depth: Option<u32>,
+    /// `--param` values as written, parsed once the generator's schema is known
+    pub params: Vec<(String, String)>,
+    pub format: ExportFormat,
+    pub options: ExportOptions,
+    pub output: PathBuf,
+}
+
+/// Options of the `render` command
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderCommand {
+    pub fractal: String,
+    pub depth: Option<u32>,
+    /// `--param` values as written, parsed once the generator's schema is known
+    pub params: Vec<(String, String)>,
+    /// Where the camera sits
+    pub camera: Vec3,
+    /// The point the camera looks at
+    pub look_at: Vec3,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+    /// Where the point light sits
+    pub light: Vec3,
+    pub settings: PathTraceSettings,
+    pub output: PathBuf,
+}
+
+/// The values of a generator accepting `schema`, from the command line's depth and `--param`s
+fn param_values(
+    fractal: &str,
+    depth: Option<u32>,
+    params: &[(String, String)],
+    schema: &[ParamSpec],
+) -> Result<ParamValues, String> {
+    let mut values = ParamValues::default();
+    if let Some(depth) = depth {
+        values.set("depth", ParamValue::Int(depth as i64));
+    }
+    for (name, text) in params {
+        let spec = schema
+            .iter()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = schema.iter().map(|spec| spec.name).collect();
+                format!(
+                    "`{fractal}` has no parameter `{name}`, expected one of: {}",
+                    names.join(", ")
+                )
+            })?;
+        values.set(name.as_str(), parse_value(spec, text)?);
+    }
+    Ok(values)
+}
+
+/// Parses the arguments after the program name; `None` means no command, so the viewer runs
+pub fn parse(args: &[String]) -> Result<Option<Command>, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(None);
+    };
+    let command = match command.as_str() {
+        "help" | "--help" | "-h" => Command::Help,
+        "list" => Command::List,
+        "export" => return parse_export(rest).map(|export| Some(Command::Export(export))),
+        "render" => return parse_render(rest).map(|render| Some(Command::Render(render))),
+        other => return Err(format!("unknown command `{other}`")),
+    };
+    match rest.first() {
+        Some(extra) => Err(format!("unexpected argument `{extra}`")),
+        None => Ok(Some(command)),
+    }
+}
+
+fn parse_export(args: &[String]) -> Result<ExportCommand, String> {
+    let mut fractal = FractalArgs::default();
+    let mut format = None;
+    let mut options = ExportOptions::default();
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("`{arg}` needs a value"))
+        };
+        if fractal.take(arg, &mut value)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--format" => {
+                let text = value()?;
+                let parsed = ExportFormat::from_name(&text)
+                    .ok_or_else(|| format!("unknown format `{text}`, expected {}", formats()))?;
+                format = Some(parsed);
+            }
+            "--weld" => options.weld = true,
+            "--hierarchy" => options.hierarchy = true,
+            "--resolution" => {
+                let text = value()?;
+                let parsed = text
+                    .parse()
+                    .ok()
+                    .filter(|voxels| (1..=MAX_VOX_RESOLUTION).contains(voxels))
+                    .ok_or_else(|| {
+                        format!("`--resolution` expects 1 to {MAX_VOX_RESOLUTION}, got `{text}`")
+                    })?;
+                options.voxel_resolution = Some(parsed);
+            }
+            "--scale" | "--min-feature" => {
+                let text = value()?;
+                let parsed = text
+                    .parse()
+                    .ok()
+                    .filter(|&millimetres: &f32| millimetres > 0.0)
+                    .ok_or_else(|| format!("`{arg}` expects a positive number, got `{text}`"))?;
+                if arg == "--scale" {
+                    options.scale = parsed;
+                } else {
+                    options.min_feature = parsed;
+                }
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option `{flag}`")),
+            path if output.is_none() => output = Some(PathBuf::from(path)),
+            path => {
+                return Err(format!(
+                    "unexpected argument `{path}`, only one output is written"
+                ))
+            }
+        }
+    }
+
+    let FractalArgs {
+        fractal,
+        depth,
+        params,
+    } = fractal;
+    let fractal = fractal.ok_or("`export` needs `--fractal <name>`")?;
+    let output: PathBuf = output.ok_or("`export` needs an output file")?;
+    let format = format
+        .or_else(|| ExportFormat::from_path(&output))
+        .ok_or_else(|| {
+            format!(
+                "cannot tell the format of `{}`, pass `--format` with one of {}",
+                output.display(),
+                formats()
+            )
+        })?;
+    Ok(ExportCommand {
+        fractal,
+        depth,
+        params,
+        format,
+        options,
+        output,
+    })
+}
+
+fn parse_render(args: &[String]) -> Result<RenderCommand, String> {
+    let mut fractal = FractalArgs::default();
+    let mut render = RenderCommand {
+        fractal: String::new(),
+        depth: None,
+        params: Vec::new(),
+        camera: Vec3::new(4.0, 3.0, 6.0),
+        look_at: Vec3::ZERO,
+        fov: PerspectiveProjection::default().fov.to_degrees(),
+        light: Vec3::new(8.0, 16.0, 8.0),
+        settings: PathTraceSettings::default(),
+        output: PathBuf::new(),
+    };
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("`{arg}` needs a value"))
+        };
+        if fractal.take(arg, &mut value)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--camera" => render.camera = parse_point(arg, &value()?)?,
+            "--look-at" => render.look_at = parse_point(arg, &value()?)?,
+            "--light" => render.light = parse_point(arg, &value()?)?,
+            "--fov" => {
+                let text = value()?;
+                render.fov = text
+                    .parse()
+                    .ok()
+                    .filter(|degrees| (1.0..180.0).contains(degrees))
+                    .ok_or_else(|| format!("`--fov` expects degrees below 180, got `{text}`"))?;
+            }
+            "--size" => {
+                let text = value()?;
+                let (width, height) = text
+                    .split_once('x')
+                    .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                    .filter(|&(width, height)| width > 0 && height > 0)
+                    .ok_or_else(|| format!("`--size` expects <width>x<height>, got `{text}`"))?;
+                render.settings.width = width;
+                render.settings.height = height;
+            }
+            "--samples" | "--bounces" => {
+                let text = value()?;
+                let parsed = text
+                    .parse()
+                    .map_err(|_| format!("`{arg}` expects a whole number, got `{text}`"))?;
+                if arg == "--samples" {
+                    render.settings.samples = parsed;
+                } else {
+                    render.settings.max_bounces = parsed;
+                }
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option `{flag}`")),
+            path if output.is_none() => output = Some(PathBuf::from(path)),
+            path => {
+                return Err(format!(
+                    "unexpected argument `{path}`, only one image is written"
+                ))
+            }
+        }
+    }
+
+    render.fractal = fractal.fractal.ok_or("`render` needs `--fractal <name>`")?;
+    render.depth = fractal.depth;
+    render.params = fractal.params;
+    render.output = output.ok_or("`render` needs an output image")?;
+    if render.camera == render.look_at {
+        return Err("`--camera` and `--look-at` must be different points".to_string());
+    }
+    Ok(render)
+}
+
+/// Reads a point written as `x,y,z`
+fn parse_point(arg: &str, text: &str) -> Result<Vec3, String> {
+    let coordinates: Option<Vec<f32>> = text.split(',').map(|c| c.trim().parse().ok()).collect();
+    match coordinates.as_deref() {
+        Some(&[x, y, z]) => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("`{arg}` expects <x>,<y>,<z>, got `{text}`")),
+    }
+}
+
+fn formats() -> String {
+    let names: Vec<_> = ExportFormat::ALL
+        .iter()
+        .map(|format| format.name())
+        .collect();
+    names.join(", ")
+}
+
+/// Reads `text` as a value of the parameter `spec` describes
+fn parse_value(spec: &ParamSpec, text: &str) -> Result<ParamValue, String> {
+    let invalid = |expected: &str| format!("`{}` expects {expected}, got `{text}`", spec.name);
+    match spec.kind {
+        ParamKind::Int(_) => text
+            .parse()
+            .map(ParamValue::Int)
+            .map_err(|_| invalid("a whole number")),
+        ParamKind::Float(_) => text
+            .parse()
+            .map(ParamValue::Float)
+            .map_err(|_| invalid("a number")),
+        ParamKind::Bool => text
+            .parse()
+            .map(ParamValue::Bool)
+            .map_err(|_| invalid("`true` or `false`")),
+        ParamKind::Seed => SeedShape::from_name(text)
+            .map(ParamValue::Seed)
+            .ok_or_else(|| {
+                let names: Vec<_> = SeedShape::ALL.iter().map(|shape| shape.name()).collect();
+                invalid(&format!("one of {}", names.join(", ")))
+            }),
+        // Either a preset's name or a pattern written out in full
+        ParamKind::Pattern => SpongePattern::preset(text)
+            .map_or_else(|| text.parse(), Ok)
+            .map(ParamValue::Pattern)
+            .map_err(|error| format!("`{}`: {error}", spec.name)),
+    }
+}
+
+fn describe(spec: &ParamSpec) -> String {
+    let default = match spec.default {
+        ParamValue::Int(v) => v.to_string(),
+        ParamValue::Float(v) => v.to_string(),
+        ParamValue::Bool(v) => v.to_string(),
+        ParamValue::Seed(v) => v.name().to_string(),
+        ParamValue::Pattern(_) => "the generator's own".to_string(),
+    };
+    let kind = match &spec.kind {
+        ParamKind::Int(range) => format!("whole number in {range:?}"),
+        ParamKind::Float(range) => format!("number in {range:?}"),
+        ParamKind::Bool => "true or false".to_string(),
+        ParamKind::Seed => "seed shape".to_string(),
+        ParamKind::Pattern => "pattern preset or layout".to_string(),
+    };
+    format!("{}: {kind}, default {default}", spec.name)
+}
+
+fn list(registry: &FractalRegistry) {
+    for name in registry.names() {
+        println!("{name}");
+        if let Some(generator) = registry.get(name) {
+            for spec in generator.params() {
+                println!("    {}", describe(&spec));
+            }
+        }
+    }
+}
+
+/// Generates `fractal` with the command line's depth and `--param` values
+fn generate(
+    registry: &FractalRegistry,
+    fractal: &str,
+    depth: Option<u32>,
+    params: &[(String, String)],
+) -> Result<FractalGeometry, String> {
+    let generator = registry.get(fractal).ok_or_else(|| {
+        let names: Vec<_> = registry.names().collect();
+        format!(
+            "unknown fractal `{fractal}`, expected one of: {}",
+            names.join(", ")
+        )
+    })?;
+    let values = param_values(fractal, depth, params, &generator.params())?;
+    Ok(registry
+        .generate(fractal, &values)
+        .expect("the generator was looked up above"))
+}
+
+/// Generates the fractal `command` asks for as a scene to export.
+///
+/// Merged geometry has no recursion left to turn into nodes, so `--hierarchy` switches
+/// generators that merge by default to instances unless `merged` was passed explicitly.
+fn export_scene(
+    registry: &FractalRegistry,
+    command: &ExportCommand,
+) -> Result<ExportScene, String> {
+    let mut params = command.params.clone();
+    let merges = registry
+        .get(&command.fractal)
+        .is_some_and(|generator| generator.params().iter().any(|spec| spec.name == "merged"));
+    if command.options.hierarchy && merges && params.iter().all(|(name, _)| name != "merged") {
+        params.push(("merged".into(), "false".into()));
+    }
+    let geometry = generate(registry, &command.fractal, command.depth, &params)?;
+    let material = ExportMaterial::new("fractal", &FRACTAL_COLOR.into());
+    Ok(ExportScene::from_geometry(
+        geometry,
+        &command.fractal,
+        material,
+    ))
+}
+
+/// Generates the requested fractal and writes it out
+fn export_fractal(
+    registry: Res<FractalRegistry>,
+    command: Res<ExportCommand>,
+) -> Result<(), String> {
+    let started = Instant::now();
+    let scene = export_scene(&registry, &command)?;
+    let report = export(&scene, command.format, &command.output, &command.options)
+        .map_err(|error| format!("could not write `{}`: {error}", command.output.display()))?;
+    println!(
+        "Wrote {} to {} in {:.2?}",
+        command.fractal,
+        command.output.display(),
+        started.elapsed()
+    );
+    if let Some(report) = report {
+        println!("{report}");
+    }
+    Ok(())
+}
+
+/// Generates the requested fractal at the origin and path traces it
+fn render_fractal(registry: &FractalRegistry, command: &RenderCommand) -> Result<RgbImage, String> {
+    let geometry = generate(registry, &command.fractal, command.depth, &command.params)?;
+    let mut scene = Scene::new(TraceCamera {
+        transform: Transform::from_translation(command.camera)
+            .looking_at(command.look_at, Vec3::Y)
+            .compute_affine(),
+        fov: command.fov.to_radians(),
+    });
+    scene.add_geometry(&geometry, &Affine3A::IDENTITY, FRACTAL_COLOR);
+    scene.add_point_light(
+        command.light,
+        &PointLight {
+            intensity: LIGHT_INTENSITY,
+            range: 100.0,
+            ..default()
+        },
+    );
+    Ok(scene.render(&command.settings))
+}
+
+fn exit_on_error(In(result): In<Result<(), String>>) {
+    if let Err(message) = result {
+        eprintln!("error: {message}");
+        std::process::exit(1);
+    }
+}
+
+/// Carries out `command` without opening a window
+pub fn run(command: Command) {
+    match command {
+        Command::Help => println!("{USAGE}"),
+        Command::List => list(&FractalRegistry::builtin()),
+        Command::Export(export) => {
+            App::new()
+                .add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_once()))
+                .insert_resource(FractalRegistry::builtin())
+                .insert_resource(export)
+                .add_systems(Startup, export_fractal.pipe(exit_on_error))
+                .run();
+        }
+        Command::Render(render) => {
+            let started = Instant::now();
+            let result = render_fractal(&FractalRegistry::builtin(), &render).and_then(|image| {
+                image.save(&render.output).map_err(|error| {
+                    format!("could not write `{}`: {error}", render.output.display())
+                })
+            });
+            exit_on_error(In(result));
+            println!(
+                "Rendered {} to {} in {:.2?}",
+                render.fractal,
+                render.output.display(),
+                started.elapsed()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gltf::Document;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_export() {
+        let command = parse(&args(
+            "export --fractal menger --depth 3 --param merged=false --weld --scale 10 out.stl",
+        ));
+        assert_eq!(
+            command,
+            Ok(Some(Command::Export(ExportCommand {
+                fractal: "menger".into(),
+                depth: Some(3),
+                params: vec![("merged".into(), "false".into())],
+                format: ExportFormat::Stl,
+                options: ExportOptions {
+                    weld: true,
+                    scale: 10.0,
+                    ..default()
+                },
+                output: "out.stl".into(),
+            })))
+        );
+        assert_eq!(parse(&[]), Ok(None));
+    }
+
+    #[test]
+    fn rejects_incomplete_exports() {
+        assert!(parse(&args("export --fractal menger out.xyz")).is_err());
+        assert!(parse(&args("export --fractal menger --depth")).is_err());
+        assert!(parse(&args("export out.stl")).is_err());
+        assert!(parse(&args("export --fractal menger a.stl b.stl")).is_err());
+        assert!(parse(&args("export --fractal menger --scale -1 out.stl")).is_err());
+        assert!(parse(&args("frobnicate")).is_err());
+    }
+
+    #[test]
+    fn parses_render() {
+        let Ok(Some(Command::Render(render))) = parse(&args(
+            "render --fractal menger --depth 2 --camera 1,2,3 --size 32x24 --samples 4 out.png",
+        )) else {
+            panic!("a valid render command");
+        };
+        assert_eq!(render.fractal, "menger");
+        assert_eq!(render.depth, Some(2));
+        assert_eq!(render.camera, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(render.look_at, Vec3::ZERO);
+        assert_eq!((render.settings.width, render.settings.height), (32, 24));
+        assert_eq!(render.settings.samples, 4);
+        assert_eq!(render.output, PathBuf::from("out.png"));
+
+        assert!(parse(&args("render --fractal menger --size 32 out.png")).is_err());
+        assert!(parse(&args("render --fractal menger --camera 1,2 out.png")).is_err());
+        assert!(parse(&args("render --fractal menger --camera 0,0,0 out.png")).is_err());
+        assert!(parse(&args("render out.png")).is_err());
+    }
+
+    #[test]
+    fn renders_without_a_window() {
+        let Ok(Some(Command::Render(render))) = parse(&args(
+            "render --fractal menger --depth 1 --size 24x16 --samples 1 --bounces 0 out.png",
+        )) else {
+            panic!("a valid render command");
+        };
+        let registry = FractalRegistry::builtin();
+        let image = render_fractal(&registry, &render).unwrap();
+        assert_eq!(image.dimensions(), (24, 16));
+        assert_eq!(image, render_fractal(&registry, &render).unwrap());
+
+        // The sponge fills the middle of the frame, lit, and the corners see the background
+        let background = image.get_pixel(0, 0).0;
+        assert_ne!(image.get_pixel(12, 8).0, background);
+        assert_eq!(image.get_pixel(23, 15).0, background);
+    }
+
+    #[test]
+    fn hierarchies_keep_the_recursion() {
+        let registry = FractalRegistry::builtin();
+        let gltf = |line: &str| {
+            let Ok(Some(Command::Export(export))) = parse(&args(line)) else {
+                panic!("`{line}` should parse");
+            };
+            let scene = export_scene(&registry, &export).unwrap();
+            Document::new(&scene, export.options.hierarchy).json(None)
+        };
+
+        // A group per first-level cube holding its 20 leaves, all sharing the seed's mesh
+        let nested = gltf("export --fractal menger --depth 2 --hierarchy x.gltf");
+        assert!(nested.contains("\"name\":\"menger_3\""));
+        assert!(nested.contains("\"name\":\"menger_3_5\""));
+        assert!(nested.contains("\"mesh_0\"") && !nested.contains("\"mesh_1\""));
+
+        // Asking for merged geometry still gets it
+        let merged =
+            gltf("export --fractal menger --depth 2 --hierarchy --param merged=true x.gltf");
+        assert!(!merged.contains("\"name\":\"menger_3_5\""));
+    }
+
+    #[test]
+    fn params_follow_the_schema() {
+        let command = ExportCommand {
+            fractal: "menger".into(),
+            depth: Some(2),
+            params: vec![("seed".into(), "octahedron".into())],
+            format: ExportFormat::Stl,
+            options: default(),
+            output: "out.stl".into(),
+        };
+        let schema = [
+            ParamSpec::int("depth", 0..=6, 3),
+            ParamSpec::seed("seed", SeedShape::Cube),
+        ];
+        let values =
+            param_values(&command.fractal, command.depth, &command.params, &schema).unwrap();
+        assert_eq!(values.get("depth"), Some(ParamValue::Int(2)));
+        assert_eq!(
+            values.get("seed"),
+            Some(ParamValue::Seed(SeedShape::Octahedron))
+        );
+
+        assert!(param_values(
+            &command.fractal,
+            command.depth,
+            &command.params,
+            &schema[..1]
+        )
+        .is_err());
+    }
+}
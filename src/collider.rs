@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::render::mesh::{PrimitiveTopology, VertexAttributeValues};
+use bevy_rapier3d::prelude::*;
+
+use crate::generator::{instance_path, FractalGeometry};
+use crate::mesh_builder::mesh_triangles;
+
+/// Asks for a fractal's geometry to collide, and how finely
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColliderSettings {
+    /// Most shapes an instanced fractal's compound collider may hold. Past it, the copies
+    /// made from one part a level up share a shape around all of them, going up levels until
+    /// the count fits.
+    pub max_colliders: usize,
+}
+
+impl Default for ColliderSettings {
+    fn default() -> Self {
+        Self {
+            max_colliders: 4096,
+        }
+    }
+}
+
+/// A collider matching `geometry`: instances of a box, like a Menger sponge's sub-cubes, become
+/// a compound of cuboids, other instances, like a Sierpinski tetrahedron's, a compound of
+/// convex hulls, and merged meshes a single trimesh. Point clouds have nothing to collide with.
+pub fn fractal_collider(
+    geometry: &FractalGeometry,
+    settings: &ColliderSettings,
+) -> Option<Collider> {
+    match geometry {
+        FractalGeometry::Merged(chunks) | FractalGeometry::MergedInstances { chunks, .. } => {
+            merged_collider(chunks)
+        }
+        FractalGeometry::Instanced {
+            seed,
+            instances,
+            branching,
+        } => {
+            let shapes = instance_colliders(seed, instances, *branching, settings.max_colliders);
+            (!shapes.is_empty()).then(|| Collider::compound(shapes))
+        }
+        FractalGeometry::Points(_) => None,
+    }
+}
+
+fn merged_collider(chunks: &[Mesh]) -> Option<Collider> {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for chunk in chunks {
+        for triangle in mesh_triangles(chunk) {
+            let base = vertices.len() as u32;
+            vertices.extend(triangle);
+            indices.push([base, base + 1, base + 2]);
+        }
+    }
+    (!indices.is_empty()).then(|| Collider::trimesh(vertices, indices))
+}
+
+/// The shapes of an instanced fractal's compound collider, at the deepest level of the
+/// recursion that fits in `max_colliders`
+fn instance_colliders(
+    seed: &Mesh,
+    instances: &[Transform],
+    branching: usize,
+    max_colliders: usize,
+) -> Vec<(Vec3, Quat, Collider)> {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        seed.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return Vec::new();
+    };
+    if seed.primitive_topology() != PrimitiveTopology::TriangleList || positions.is_empty() {
+        return Vec::new();
+    }
+    let corners: Vec<Vec3> = positions.iter().map(|&p| Vec3::from(p)).collect();
+    let (min, max) = corners
+        .iter()
+        .fold((corners[0], corners[0]), |(min, max), &c| {
+            (min.min(c), max.max(c))
+        });
+    // A box when every vertex sits in one of its bounding box's corners
+    let is_box = corners.iter().all(|&c| {
+        (0..3)
+            .all(|axis| (c[axis] - min[axis]).abs() <= 1e-5 || (c[axis] - max[axis]).abs() <= 1e-5)
+    });
+    let axis_aligned = instances
+        .iter()
+        .all(|instance| instance.rotation.abs_diff_eq(Quat::IDENTITY, 1e-5));
+
+    // Every instance's path through the recursion, cut short to the deepest level at which
+    // there are few enough groups of instances
+    let paths: Vec<_> = (0..instances.len())
+        .map(|index| instance_path(index, instances.len(), branching))
+        .collect();
+    let depth = paths.iter().map(Vec::len).max().unwrap_or(0);
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for level in (0..=depth).rev() {
+        let mut by_prefix: HashMap<&[u32], usize> = HashMap::new();
+        groups.clear();
+        for (index, path) in paths.iter().enumerate() {
+            let prefix = &path[..level.min(path.len())];
+            let group = *by_prefix.entry(prefix).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(index);
+        }
+        if groups.len() <= max_colliders.max(1) {
+            break;
+        }
+    }
+
+    groups
+        .iter()
+        .filter_map(|group| {
+            if is_box && group.len() == 1 {
+                // A lone box keeps its own orientation
+                let instance = instances[group[0]];
+                let half = (max - min) * instance.scale.abs() / 2.0;
+                let center = instance.transform_point((min + max) / 2.0);
+                return Some((
+                    center,
+                    instance.rotation,
+                    Collider::cuboid(half.x, half.y, half.z),
+                ));
+            }
+            let points: Vec<Vec3> = group
+                .iter()
+                .flat_map(|&index| {
+                    corners
+                        .iter()
+                        .map(move |&c| instances[index].transform_point(c))
+                })
+                .collect();
+            if is_box && axis_aligned {
+                let (low, high) = points
+                    .iter()
+                    .fold((points[0], points[0]), |(low, high), &p| {
+                        (low.min(p), high.max(p))
+                    });
+                let half = (high - low) / 2.0;
+                let shape = Collider::cuboid(half.x, half.y, half.z);
+                return Some(((low + high) / 2.0, Quat::IDENTITY, shape));
+            }
+            Collider::convex_hull(&points).map(|hull| (Vec3::ZERO, Quat::IDENTITY, hull))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{FractalRegistry, ParamValue, ParamValues};
+    use crate::menger::menger_cubes;
+
+    #[test]
+    fn deep_recursions_collide_coarsely() {
+        let cube = Mesh::from(shape::Cube::new(1.0));
+        let cubes = menger_cubes(2, 3.0);
+        assert_eq!(instance_colliders(&cube, &cubes, 20, 4096).len(), 400);
+        // Capped, each first-level cube collides as one
+        let coarse = instance_colliders(&cube, &cubes, 20, 100);
+        assert_eq!(coarse.len(), 20);
+        let (center, _, shape) = &coarse[0];
+        assert_eq!(*center, Vec3::splat(-1.0));
+        assert_eq!(shape.as_cuboid().unwrap().half_extents(), Vec3::splat(0.5));
+        assert_eq!(instance_colliders(&cube, &cubes, 20, 1).len(), 1);
+    }
+
+    #[test]
+    fn tetrahedra_collide_as_hulls() {
+        let mut values = ParamValues::default();
+        values.set("depth", ParamValue::Int(2));
+        values.set("merged", ParamValue::Bool(false));
+        let registry = FractalRegistry::builtin();
+        let Some(FractalGeometry::Instanced {
+            seed,
+            instances,
+            branching,
+        }) = registry.generate("sierpinski_tetrahedron", &values)
+        else {
+            panic!("unmerged tetrahedra are instanced");
+        };
+        let shapes = instance_colliders(&seed, &instances, branching, 4096);
+        assert_eq!(shapes.len(), 16);
+        assert!(shapes
+            .iter()
+            .all(|(_, _, shape)| shape.as_convex_polyhedron().is_some()));
+
+        let geometry = FractalGeometry::Merged(vec![seed]);
+        assert!(fractal_collider(&geometry, &default())
+            .is_some_and(|collider| collider.as_trimesh().is_some()));
+    }
+}
@@ -0,0 +1,427 @@
+// encase's `ShaderType` derive emits a never-called `check` function per field, beside the
+// struct rather than inside it, so only a file-wide allow reaches them
+#![allow(dead_code)]
+
+use bevy::prelude::*;
+use bevy::render::render_resource::ShaderType;
+
+/// Value reported for points whose orbit never escapes, where escape-time formulas have no
+/// meaningful distance; slightly negative so the sign still marks them as inside
+const INTERIOR: f32 = -1e-4;
+
+/// Step used for the central differences in `DistanceEstimator::normal`
+const NORMAL_EPSILON: f32 = 1e-4;
+
+/// A conservative estimate of the distance to a fractal's surface, positive outside and
+/// zero or negative inside
+pub trait DistanceEstimator: Send + Sync {
+    fn distance(&self, p: Vec3) -> f32;
+
+    /// Surface normal at `p`, taken from the gradient of the distance
+    fn normal(&self, p: Vec3) -> Vec3 {
+        let e = NORMAL_EPSILON;
+        Vec3::new(
+            self.distance(p + Vec3::X * e) - self.distance(p - Vec3::X * e),
+            self.distance(p + Vec3::Y * e) - self.distance(p - Vec3::Y * e),
+            self.distance(p + Vec3::Z * e) - self.distance(p - Vec3::Z * e),
+        )
+        .normalize_or_zero()
+    }
+}
+
+/// Any scalar field works as an estimator, e.g. `|p: Vec3| p.length() - 1.0` for a unit sphere
+impl<F: Fn(Vec3) -> f32 + Send + Sync> DistanceEstimator for F {
+    fn distance(&self, p: Vec3) -> f32 {
+        self(p)
+    }
+}
+
+// Each estimator's parameters double as the uniform block the raymarching shader reads, so
+// fields here have to stay in step with the structs in `assets/shaders/raymarch.wgsl`
+
+/// The power-`n` Mandelbulb
+#[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+pub struct Mandelbulb {
+    pub power: f32,
+    pub iterations: u32,
+    pub bailout: f32,
+}
+
+impl Default for Mandelbulb {
+    fn default() -> Self {
+        Self {
+            power: 8.0,
+            iterations: 12,
+            bailout: 2.0,
+        }
+    }
+}
+
+impl DistanceEstimator for Mandelbulb {
+    fn distance(&self, p: Vec3) -> f32 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+        for _ in 0..self.iterations {
+            if r > self.bailout {
+                return 0.5 * r.ln() * r / dr;
+            }
+
+            // Raise to the power in spherical coordinates, tracking the running derivative
+            let theta = polar_angle(z, r) * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z =
+                zr * Vec3::new(
+                    theta.sin() * phi.cos(),
+                    phi.sin() * theta.sin(),
+                    theta.cos(),
+                ) + p;
+            r = z.length();
+        }
+        if r > self.bailout {
+            0.5 * r.ln() * r / dr
+        } else {
+            INTERIOR
+        }
+    }
+}
+
+/// The angle between `z` and the z axis, given its length `r`. On the axis `r` can round below
+/// `|z.z|` once `z.z` squared underflows, so the cosine is clamped before it reaches `acos`
+fn polar_angle(z: Vec3, r: f32) -> f32 {
+    (z.z / r.max(f32::MIN_POSITIVE)).clamp(-1.0, 1.0).acos()
+}
+
+/// The Mandelbox, alternating box and sphere folds
+#[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+pub struct Mandelbox {
+    pub scale: f32,
+    pub min_radius: f32,
+    pub fixed_radius: f32,
+    pub folding_limit: f32,
+    pub iterations: u32,
+    pub bailout: f32,
+}
+
+impl Default for Mandelbox {
+    fn default() -> Self {
+        Self {
+            scale: 2.0,
+            min_radius: 0.5,
+            fixed_radius: 1.0,
+            folding_limit: 1.0,
+            iterations: 15,
+            bailout: 100.0,
+        }
+    }
+}
+
+impl DistanceEstimator for Mandelbox {
+    fn distance(&self, p: Vec3) -> f32 {
+        let min_radius2 = self.min_radius * self.min_radius;
+        let fixed_radius2 = self.fixed_radius * self.fixed_radius;
+        let mut z = p;
+        let mut dr = 1.0;
+        for _ in 0..self.iterations {
+            // Box fold: reflect anything past the folding limit back inside it
+            z = z.clamp(
+                Vec3::splat(-self.folding_limit),
+                Vec3::splat(self.folding_limit),
+            ) * 2.0
+                - z;
+
+            // Sphere fold: invert through the fixed sphere, linearly inside the minimum one
+            let r2 = z.length_squared();
+            if r2 < min_radius2 {
+                let factor = fixed_radius2 / min_radius2;
+                z *= factor;
+                dr *= factor;
+            } else if r2 < fixed_radius2 {
+                let factor = fixed_radius2 / r2;
+                z *= factor;
+                dr *= factor;
+            }
+
+            z = z * self.scale + p;
+            dr = dr * self.scale.abs() + 1.0;
+            if z.length() > self.bailout {
+                return z.length() / dr.abs();
+            }
+        }
+        INTERIOR
+    }
+}
+
+/// A 3D slice through a quaternion Julia set, `q → q² + c` with the fourth component at zero
+#[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+pub struct QuaternionJulia {
+    pub c: Vec4,
+    pub iterations: u32,
+    pub bailout: f32,
+}
+
+impl Default for QuaternionJulia {
+    fn default() -> Self {
+        Self {
+            c: Vec4::new(-0.291, -0.399, 0.339, 0.437),
+            iterations: 12,
+            bailout: 4.0,
+        }
+    }
+}
+
+impl QuaternionJulia {
+    fn square(q: Vec4) -> Vec4 {
+        // (a + v)² = a² - |v|² + 2av for real part `a` and vector part `v`
+        Vec4::new(
+            q.x * q.x - q.y * q.y - q.z * q.z - q.w * q.w,
+            2.0 * q.x * q.y,
+            2.0 * q.x * q.z,
+            2.0 * q.x * q.w,
+        )
+    }
+
+    fn multiply(a: Vec4, b: Vec4) -> Vec4 {
+        Vec4::new(
+            a.x * b.x - a.y * b.y - a.z * b.z - a.w * b.w,
+            a.x * b.y + a.y * b.x + a.z * b.w - a.w * b.z,
+            a.x * b.z - a.y * b.w + a.z * b.x + a.w * b.y,
+            a.x * b.w + a.y * b.z - a.z * b.y + a.w * b.x,
+        )
+    }
+}
+
+impl DistanceEstimator for QuaternionJulia {
+    fn distance(&self, p: Vec3) -> f32 {
+        let mut q = p.extend(0.0);
+        let mut dq = Vec4::new(1.0, 0.0, 0.0, 0.0);
+        for _ in 0..self.iterations {
+            dq = 2.0 * Self::multiply(q, dq);
+            q = Self::square(q) + self.c;
+            if q.length() > self.bailout {
+                let r = q.length();
+                return 0.5 * r * r.ln() / dq.length();
+            }
+        }
+        INTERIOR
+    }
+}
+
+/// The Menger sponge filling the cube `[-half_size, half_size]³`
+#[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+pub struct MengerSponge {
+    pub half_size: f32,
+    pub iterations: u32,
+}
+
+impl Default for MengerSponge {
+    fn default() -> Self {
+        Self {
+            half_size: 1.0,
+            iterations: 5,
+        }
+    }
+}
+
+impl DistanceEstimator for MengerSponge {
+    fn distance(&self, p: Vec3) -> f32 {
+        let p = p / self.half_size;
+        let q = p.abs() - Vec3::ONE;
+        let mut d = q.max(Vec3::ZERO).length() + q.max_element().min(0.0);
+
+        // Carve out the cross-shaped tunnels of each level, a third the size of the last
+        let mut s = 1.0;
+        for _ in 0..self.iterations {
+            let a = (p * s).rem_euclid(Vec3::splat(2.0)) - 1.0;
+            s *= 3.0;
+            let r = (Vec3::ONE - 3.0 * a.abs()).abs();
+            let da = r.x.max(r.y);
+            let db = r.y.max(r.z);
+            let dc = r.z.max(r.x);
+            let c = (da.min(db).min(dc) - 1.0) / s;
+            d = d.max(c);
+        }
+        d * self.half_size
+    }
+}
+
+/// The Sierpinski tetrahedron with corners at `(1, 1, 1)`, `(-1, -1, 1)`, `(1, -1, -1)` and
+/// `(-1, 1, -1)` times `half_size`
+#[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+pub struct SierpinskiTetrahedron {
+    pub half_size: f32,
+    pub iterations: u32,
+}
+
+impl Default for SierpinskiTetrahedron {
+    fn default() -> Self {
+        Self {
+            half_size: 1.0,
+            iterations: 10,
+        }
+    }
+}
+
+impl DistanceEstimator for SierpinskiTetrahedron {
+    fn distance(&self, p: Vec3) -> f32 {
+        let mut z = p / self.half_size;
+        let mut scale = 1.0;
+        for _ in 0..self.iterations {
+            // Fold into the copy around the (1, 1, 1) corner, then blow it up to full size
+            if z.x + z.y < 0.0 {
+                (z.x, z.y) = (-z.y, -z.x);
+            }
+            if z.x + z.z < 0.0 {
+                (z.x, z.z) = (-z.z, -z.x);
+            }
+            if z.y + z.z < 0.0 {
+                (z.y, z.z) = (-z.z, -z.y);
+            }
+            z = z * 2.0 - Vec3::ONE;
+            scale *= 2.0;
+        }
+
+        let tetrahedron = (-z.x - z.y - z.z)
+            .max(z.x + z.y - z.z)
+            .max(-z.x + z.y + z.z)
+            .max(z.x - z.y + z.z);
+        (tetrahedron - 1.0) / 3.0f32.sqrt() / scale * self.half_size
+    }
+}
+
+/// Any one of the built-in estimators, for code that picks between them at runtime
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Estimator {
+    Mandelbulb(Mandelbulb),
+    Mandelbox(Mandelbox),
+    QuaternionJulia(QuaternionJulia),
+    MengerSponge(MengerSponge),
+    SierpinskiTetrahedron(SierpinskiTetrahedron),
+}
+
+impl Estimator {
+    /// Half the width of an origin-centred cube that holds the whole fractal
+    pub fn bounds(&self) -> f32 {
+        match self {
+            Estimator::Mandelbulb(bulb) if bulb.power < 4.0 => 1.6,
+            Estimator::Mandelbulb(_) => 1.25,
+            Estimator::Mandelbox(mandelbox) => {
+                // Beyond `2 (|s| + 1) / (|s| - 1)` every orbit escapes, for scales away from 1
+                let scale = mandelbox.scale.abs();
+                if scale > 1.5 {
+                    2.0 * (scale + 1.0) / (scale - 1.0)
+                } else {
+                    10.0
+                }
+            }
+            Estimator::QuaternionJulia(_) => 1.5,
+            Estimator::MengerSponge(sponge) => sponge.half_size,
+            Estimator::SierpinskiTetrahedron(tetrahedron) => tetrahedron.half_size,
+        }
+    }
+}
+
+impl DistanceEstimator for Estimator {
+    fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            Estimator::Mandelbulb(de) => de.distance(p),
+            Estimator::Mandelbox(de) => de.distance(p),
+            Estimator::QuaternionJulia(de) => de.distance(p),
+            Estimator::MengerSponge(de) => de.distance(p),
+            Estimator::SierpinskiTetrahedron(de) => de.distance(p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn assert_inside(de: &dyn DistanceEstimator, p: Vec3) {
+        let d = de.distance(p);
+        assert!(d <= 0.0, "expected {p} inside, got distance {d}");
+    }
+
+    fn assert_outside(de: &dyn DistanceEstimator, p: Vec3) {
+        let d = de.distance(p);
+        assert!(d > 0.0, "expected {p} outside, got distance {d}");
+    }
+
+    #[test]
+    fn mandelbulb_sign() {
+        for power in [2.0, 8.0] {
+            let bulb = Mandelbulb { power, ..default() };
+            assert_inside(&bulb, Vec3::ZERO);
+            assert_inside(&bulb, Vec3::new(0.1, -0.1, 0.05));
+            assert_outside(&bulb, Vec3::new(2.0, 2.0, 2.0));
+            assert_outside(&bulb, Vec3::new(0.0, 0.0, -2.5));
+        }
+        assert_outside(&Mandelbulb::default(), Vec3::new(0.0, 0.0, -1.5));
+    }
+
+    #[test]
+    fn mandelbulb_angles_stay_finite_on_its_axis() {
+        for (z, angle) in [(1e-30, 0.0), (-1e-30, PI), (1.0, 0.0), (-2.0, PI)] {
+            let p = Vec3::new(0.0, 0.0, z);
+            assert_eq!(polar_angle(p, p.length()), angle, "at {p}");
+        }
+        assert_inside(&Mandelbulb::default(), Vec3::new(0.0, 0.0, 1e-30));
+    }
+
+    #[test]
+    fn mandelbox_sign() {
+        let mandelbox = Mandelbox::default();
+        assert_inside(&mandelbox, Vec3::ZERO);
+        assert_outside(&mandelbox, Vec3::splat(10.0));
+        assert_outside(&mandelbox, Vec3::new(7.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn quaternion_julia_sign() {
+        let julia = QuaternionJulia {
+            c: Vec4::new(0.1, 0.1, 0.0, 0.0),
+            ..default()
+        };
+        assert_inside(&julia, Vec3::ZERO);
+        assert_outside(&julia, Vec3::new(3.0, 0.0, 0.0));
+        assert_outside(&julia, Vec3::new(0.0, -2.0, 2.0));
+    }
+
+    #[test]
+    fn menger_sign() {
+        let sponge = MengerSponge::default();
+        // The corner is solid at every level, the centre and face centres are tunnels
+        assert_inside(&sponge, Vec3::splat(1.0 - 1e-3));
+        assert_outside(&sponge, Vec3::ZERO);
+        assert_outside(&sponge, Vec3::new(0.9, 0.0, 0.0));
+        assert_outside(&sponge, Vec3::new(2.0, 0.0, 0.0));
+        assert!((sponge.distance(Vec3::new(2.0, 0.8, 0.8)) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sierpinski_sign() {
+        let tetrahedron = SierpinskiTetrahedron::default();
+        let corner = Vec3::ONE;
+        let leaf_center = corner * (1.0 - 0.5f32.powi(tetrahedron.iterations as i32));
+        assert_inside(&tetrahedron, leaf_center);
+        assert!(tetrahedron.distance(corner).abs() < 1e-4);
+        // The middle of the tetrahedron is the first octahedron cut away
+        assert_outside(&tetrahedron, Vec3::ZERO);
+        assert_outside(&tetrahedron, Vec3::splat(-2.0));
+    }
+
+    #[test]
+    fn normals_follow_the_gradient() {
+        let sponge = MengerSponge::default();
+        let normal = sponge.normal(Vec3::new(1.5, 0.8, 0.8));
+        assert!(normal.abs_diff_eq(Vec3::X, 1e-3), "{normal}");
+
+        let bulb = Mandelbulb::default();
+        let p = Vec3::new(0.0, 3.0, 0.0);
+        assert!(bulb.normal(p).dot(p.normalize()) > 0.9);
+    }
+}
@@ -0,0 +1,580 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+use crate::distance_estimators::DistanceEstimator;
+
+/// Regula falsi steps used to pin down where the surface crosses a cell edge
+const CROSSING_STEPS: u32 = 4;
+
+/// Octree levels whose children are built on threads of their own
+const PARALLEL_DEPTH: u32 = 2;
+
+/// Pull towards the mass point along directions the surface normals leave unconstrained
+const QEF_REGULARIZATION: f32 = 1e-3;
+
+/// Offset of child or corner `i` from the lowest corner of a cell, in units of the child size
+fn corner_offset(corner: usize) -> Vec3 {
+    Vec3::new(
+        (corner & 1) as f32,
+        (corner >> 1 & 1) as f32,
+        (corner >> 2 & 1) as f32,
+    )
+}
+
+/// The two axes perpendicular to `axis`, ordered so the first crossed with the second is `axis`
+fn perpendicular(axis: usize) -> (usize, usize) {
+    ((axis + 1) % 3, (axis + 2) % 3)
+}
+
+/// Quadratic error function: the summed squared distance to the tangent planes at every point
+/// where the surface crossed a cell edge
+#[derive(Clone, Copy)]
+struct Qef {
+    ata: Mat3,
+    atb: Vec3,
+    btb: f32,
+    mass: Vec3,
+    count: u32,
+}
+
+impl Default for Qef {
+    fn default() -> Self {
+        // `Mat3::default()` is the identity, which would count as three phantom planes
+        Self {
+            ata: Mat3::ZERO,
+            atb: Vec3::ZERO,
+            btb: 0.0,
+            mass: Vec3::ZERO,
+            count: 0,
+        }
+    }
+}
+
+impl Qef {
+    fn add(&mut self, point: Vec3, normal: Vec3) {
+        let b = normal.dot(point);
+        self.ata += Mat3::from_cols(normal * normal.x, normal * normal.y, normal * normal.z);
+        self.atb += normal * b;
+        self.btb += b * b;
+        self.mass += point;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Qef) {
+        self.ata += other.ata;
+        self.atb += other.atb;
+        self.btb += other.btb;
+        self.mass += other.mass;
+        self.count += other.count;
+    }
+
+    fn mass_point(&self) -> Vec3 {
+        self.mass / self.count.max(1) as f32
+    }
+
+    /// The point closest to every tangent plane, with its root mean square distance to them
+    fn solve(&self) -> (Vec3, f32) {
+        let mass_point = self.mass_point();
+        let regularized = self.ata + Mat3::IDENTITY * QEF_REGULARIZATION;
+        let point = mass_point + regularized.inverse() * (self.atb - self.ata * mass_point);
+        let error = point.dot(self.ata * point) - 2.0 * point.dot(self.atb) + self.btb;
+        (point, (error.max(0.0) / self.count.max(1) as f32).sqrt())
+    }
+}
+
+struct Leaf {
+    /// Bit `i` is set when corner `i` is inside the surface
+    corners: u8,
+    depth: u32,
+    qef: Qef,
+    vertex: Vec3,
+    index: u32,
+}
+
+impl Leaf {
+    fn inside(&self, corner: usize) -> bool {
+        self.corners & (1 << corner) != 0
+    }
+}
+
+enum Node {
+    /// Entirely inside or outside the surface
+    Empty {
+        inside: bool,
+    },
+    Internal(Box<[Node; 8]>),
+    Leaf(Leaf),
+}
+
+impl Node {
+    /// Child `i`, or the node itself if it is not subdivided
+    fn child(&self, i: usize) -> &Node {
+        match self {
+            Node::Internal(children) => &children[i],
+            node => node,
+        }
+    }
+
+    fn is_internal(&self) -> bool {
+        matches!(self, Node::Internal(_))
+    }
+
+    /// Whether corner `i` is inside the surface, if the node is not subdivided
+    fn corner_inside(&self, corner: usize) -> Option<bool> {
+        match self {
+            Node::Empty { inside } => Some(*inside),
+            Node::Leaf(leaf) => Some(leaf.inside(corner)),
+            Node::Internal(_) => None,
+        }
+    }
+}
+
+/// Meshes the zero level set of a distance estimator with dual contouring over an octree that
+/// only subdivides near the surface.
+///
+/// Each leaf gets a single vertex placed to minimise its distance to the tangent planes where
+/// the surface crosses the leaf's edges, which keeps the sharp edges and corners of shapes like
+/// the Menger sponge. Leaves whose combined vertex would stay within `tolerance` of those planes
+/// are merged, so flat regions end up covered by a few large quads.
+#[derive(Clone, Copy, Debug)]
+pub struct DualContouring {
+    pub min: Vec3,
+    /// Edge length of the cube being meshed
+    pub size: f32,
+    /// Subdivisions down to the finest cells, `2^max_depth` of them along each axis
+    pub max_depth: u32,
+    /// How far, on average, a merged vertex may sit from the surface it replaces
+    pub tolerance: f32,
+}
+
+impl DualContouring {
+    /// Meshes the cube around `min..max`, which is widened to a cube if it is not one.
+    ///
+    /// The default tolerance only merges regions that are flat to within rounding error.
+    pub fn new(min: Vec3, max: Vec3, max_depth: u32) -> Self {
+        let size = (max - min).max_element();
+        Self {
+            min,
+            size,
+            max_depth,
+            tolerance: size * 1e-4,
+        }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Builds an indexed mesh of the surface where `field` crosses zero, with normals taken
+    /// from the field's gradient
+    pub fn polygonize(&self, field: &dyn DistanceEstimator) -> Mesh {
+        let mut root = self.build(field, self.min, self.size, 0);
+
+        let mut positions = Vec::new();
+        assign_vertices(&mut root, &mut positions);
+        let mut indices = Vec::new();
+        cell_proc(&root, &mut indices);
+
+        let normals: Vec<[f32; 3]> = positions
+            .iter()
+            .map(|&p| field.normal(Vec3::from(p)).to_array())
+            .collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+
+    fn build(&self, field: &dyn DistanceEstimator, min: Vec3, size: f32, depth: u32) -> Node {
+        // The estimate never overshoots, so a cell further from the surface than its own
+        // radius cannot contain any of it
+        let center_distance = field.distance(min + Vec3::splat(size / 2.0));
+        if center_distance.abs() > size * 3.0f32.sqrt() / 2.0 {
+            return Node::Empty {
+                inside: center_distance < 0.0,
+            };
+        }
+
+        if depth == self.max_depth {
+            return self.build_leaf(field, min, size, depth);
+        }
+
+        let half = size / 2.0;
+        let build_child =
+            |i: usize| self.build(field, min + corner_offset(i) * half, half, depth + 1);
+        let children: [Node; 8] = if depth < PARALLEL_DEPTH {
+            std::thread::scope(|scope| {
+                let handles = [0, 1, 2, 3, 4, 5, 6, 7].map(|i| scope.spawn(move || build_child(i)));
+                handles.map(|handle| handle.join().unwrap())
+            })
+        } else {
+            std::array::from_fn(build_child)
+        };
+        if let Some(inside) = homogeneous(&children) {
+            return Node::Empty { inside };
+        }
+        self.collapse(&children, min, size, depth)
+            .unwrap_or_else(|| Node::Internal(Box::new(children)))
+    }
+
+    fn build_leaf(&self, field: &dyn DistanceEstimator, min: Vec3, size: f32, depth: u32) -> Node {
+        let corner = |i: usize| min + corner_offset(i) * size;
+        let values: [f32; 8] = std::array::from_fn(|i| field.distance(corner(i)));
+        let corners = (0..8).fold(0u8, |bits, i| bits | ((values[i] < 0.0) as u8) << i);
+        if corners == 0 || corners == u8::MAX {
+            return Node::Empty {
+                inside: corners != 0,
+            };
+        }
+
+        let mut qef = Qef::default();
+        for axis in 0..3 {
+            for a in (0..8).filter(|a| a & (1 << axis) == 0) {
+                let b = a | 1 << axis;
+                if (values[a] < 0.0) != (values[b] < 0.0) {
+                    let point = crossing(field, (corner(a), values[a]), (corner(b), values[b]));
+                    qef.add(point, field.normal(point));
+                }
+            }
+        }
+
+        let (mut vertex, _) = qef.solve();
+        if !contains(min, size, vertex) {
+            vertex = qef.mass_point();
+        }
+        Node::Leaf(Leaf {
+            corners,
+            depth,
+            qef,
+            vertex,
+            index: 0,
+        })
+    }
+
+    /// Replaces eight unsubdivided children with a single leaf, if one vertex can stand in
+    /// for all of theirs without losing detail or changing the surface's topology
+    fn collapse(&self, children: &[Node; 8], min: Vec3, size: f32, depth: u32) -> Option<Node> {
+        // Inside-ness of the 3×3×3 lattice of child corners
+        let lattice = |point: UVec3| {
+            let child = point.min(UVec3::ONE);
+            let local = point - child;
+            children[(child.x | child.y << 1 | child.z << 2) as usize]
+                .corner_inside((local.x | local.y << 1 | local.z << 2) as usize)
+        };
+
+        let mut corners = 0u8;
+        for i in 0..8 {
+            let point = (corner_offset(i) * 2.0).as_uvec3();
+            corners |= (lattice(point)? as u8) << i;
+        }
+        if corners == 0 || corners == u8::MAX {
+            return None;
+        }
+
+        // Every edge and face of the merged cell must cross the surface no more often than its
+        // corners say, or the single vertex would cut off part of the surface
+        for axis in 0..3 {
+            let (u, v) = perpendicular(axis);
+            for a in 0..2u32 {
+                for b in 0..2u32 {
+                    let mut start = UVec3::ZERO;
+                    start[u] = a * 2;
+                    start[v] = b * 2;
+                    let mut middle = start;
+                    middle[axis] = 1;
+                    let mut end = start;
+                    end[axis] = 2;
+                    let (start, middle, end) = (lattice(start)?, lattice(middle)?, lattice(end)?);
+                    if start == end && middle != start {
+                        return None;
+                    }
+                }
+            }
+            for side in 0..2u32 {
+                let face_point = |a: u32, b: u32| {
+                    let mut point = UVec3::ZERO;
+                    point[axis] = side * 2;
+                    point[u] = a;
+                    point[v] = b;
+                    lattice(point)
+                };
+                let rim = [(0, 0), (2, 0), (2, 2), (0, 2)].map(|(a, b)| face_point(a, b));
+                if rim.iter().all(|&corner| corner == rim[0]) && face_point(1, 1) != rim[0] {
+                    return None;
+                }
+            }
+        }
+
+        let mut qef = Qef::default();
+        for child in children {
+            if let Node::Leaf(leaf) = child {
+                qef.merge(&leaf.qef);
+            }
+        }
+        let (vertex, error) = qef.solve();
+        if error > self.tolerance || !contains(min, size, vertex) {
+            return None;
+        }
+        Some(Node::Leaf(Leaf {
+            corners,
+            depth,
+            qef,
+            vertex,
+            index: 0,
+        }))
+    }
+}
+
+/// If every node is empty on the same side of the surface, which side that is
+fn homogeneous(nodes: &[Node; 8]) -> Option<bool> {
+    let mut side = None;
+    for node in nodes {
+        let Node::Empty { inside } = node else {
+            return None;
+        };
+        if side.is_some_and(|side| side != *inside) {
+            return None;
+        }
+        side = Some(*inside);
+    }
+    side
+}
+
+fn contains(min: Vec3, size: f32, point: Vec3) -> bool {
+    let slack = size * 1e-3;
+    point.cmpge(min - slack).all() && point.cmple(min + size + slack).all()
+}
+
+/// Narrows the edge between two points with their distances down to where the surface
+/// crosses it, interpolating the distance at each step
+fn crossing(field: &dyn DistanceEstimator, a: (Vec3, f32), b: (Vec3, f32)) -> Vec3 {
+    let (mut lo, mut hi) = (a, b);
+    let mut point = a.0;
+    for _ in 0..=CROSSING_STEPS {
+        point = lo.0.lerp(hi.0, lo.1 / (lo.1 - hi.1));
+        let distance = field.distance(point);
+        if (distance < 0.0) == (lo.1 < 0.0) {
+            lo = (point, distance);
+        } else {
+            hi = (point, distance);
+        }
+    }
+    point
+}
+
+fn assign_vertices(node: &mut Node, positions: &mut Vec<[f32; 3]>) {
+    match node {
+        Node::Internal(children) => {
+            for child in children.iter_mut() {
+                assign_vertices(child, positions);
+            }
+        }
+        Node::Leaf(leaf) => {
+            leaf.index = positions.len() as u32;
+            positions.push(leaf.vertex.to_array());
+        }
+        Node::Empty { .. } => {}
+    }
+}
+
+/// The four nodes around an edge along `axis`, one level down from `parents`.
+///
+/// `parents` are ordered by their position around the edge, `k1 + 2 * k2` along the two
+/// perpendicular axes. `through_middle` says, for each of those axes, whether the edge runs
+/// through the middle of the parents rather than along their boundary.
+fn edge_children(
+    parents: [&Node; 4],
+    axis: usize,
+    half: usize,
+    through_middle: [bool; 2],
+) -> [&Node; 4] {
+    let (e1, e2) = perpendicular(axis);
+    std::array::from_fn(|k| {
+        let (k1, k2) = (k & 1, k >> 1);
+        let b1 = if through_middle[0] { k1 } else { 1 - k1 };
+        let b2 = if through_middle[1] { k2 } else { 1 - k2 };
+        parents[k].child(half << axis | b1 << e1 | b2 << e2)
+    })
+}
+
+fn cell_proc(node: &Node, indices: &mut Vec<u32>) {
+    let Node::Internal(children) = node else {
+        return;
+    };
+    for child in children.iter() {
+        cell_proc(child, indices);
+    }
+    for axis in 0..3 {
+        for i in (0..8).filter(|i| i & (1 << axis) == 0) {
+            face_proc([&children[i], &children[i | 1 << axis]], axis, indices);
+        }
+        for half in 0..2 {
+            edge_proc(
+                edge_children([node; 4], axis, half, [true, true]),
+                axis,
+                indices,
+            );
+        }
+    }
+}
+
+/// Contours the face between `nodes[0]` and `nodes[1]`, which sits above it along `axis`
+fn face_proc(nodes: [&Node; 2], axis: usize, indices: &mut Vec<u32>) {
+    if nodes.iter().any(|node| matches!(node, Node::Empty { .. }))
+        || !nodes.iter().any(|node| node.is_internal())
+    {
+        return;
+    }
+
+    let (u, v) = perpendicular(axis);
+    for a in 0..2 {
+        for b in 0..2 {
+            let lower = nodes[0].child(1 << axis | a << u | b << v);
+            let upper = nodes[1].child(a << u | b << v);
+            face_proc([lower, upper], axis, indices);
+        }
+    }
+
+    // The edges running across the face along `u` have the face's axis second around them,
+    // those along `v` have it first
+    for half in 0..2 {
+        let parents = [nodes[0], nodes[0], nodes[1], nodes[1]];
+        edge_proc(edge_children(parents, u, half, [true, false]), u, indices);
+        let parents = [nodes[0], nodes[1], nodes[0], nodes[1]];
+        edge_proc(edge_children(parents, v, half, [false, true]), v, indices);
+    }
+}
+
+/// Contours the edge along `axis` shared by `nodes`, emitting a quad once all four are leaves
+fn edge_proc(nodes: [&Node; 4], axis: usize, indices: &mut Vec<u32>) {
+    if nodes.iter().any(|node| matches!(node, Node::Empty { .. })) {
+        return;
+    }
+    if nodes.iter().any(|node| node.is_internal()) {
+        for half in 0..2 {
+            edge_proc(
+                edge_children(nodes, axis, half, [false, false]),
+                axis,
+                indices,
+            );
+        }
+        return;
+    }
+
+    let leaves = nodes.map(|node| match node {
+        Node::Leaf(leaf) => leaf,
+        _ => unreachable!(),
+    });
+    // The smallest leaf is the one the edge belongs to in full
+    let (k, smallest) = leaves
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, leaf)| leaf.depth)
+        .unwrap();
+    let (e1, e2) = perpendicular(axis);
+    let start = (1 - (k & 1)) << e1 | (1 - (k >> 1)) << e2;
+    let start_inside = smallest.inside(start);
+    if start_inside == smallest.inside(start | 1 << axis) {
+        return;
+    }
+
+    // Counter-clockwise seen from the upper end of the edge, which is the outside when the
+    // lower end is inside
+    let mut ring = [0, 1, 3, 2].map(|k| leaves[k].index).to_vec();
+    if !start_inside {
+        ring.reverse();
+    }
+    // A large leaf can sit on two sides of the same edge
+    ring.dedup();
+    if ring.first() == ring.last() {
+        ring.pop();
+    }
+    for k in 1..ring.len().saturating_sub(1) {
+        indices.extend_from_slice(&[ring[0], ring[k], ring[k + 1]]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_cubes::MarchingCubes;
+    use bevy::render::mesh::VertexAttributeValues;
+    use std::collections::HashMap;
+
+    fn triangles(mesh: &Mesh) -> (Vec<Vec3>, Vec<u32>) {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("missing positions");
+        };
+        let Some(Indices::U32(indices)) = mesh.indices() else {
+            panic!("missing indices");
+        };
+        (
+            positions.iter().map(|&p| Vec3::from(p)).collect(),
+            indices.clone(),
+        )
+    }
+
+    fn volume(positions: &[Vec3], indices: &[u32]) -> f32 {
+        indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [0, 1, 2].map(|k| positions[t[k] as usize]);
+                a.dot(b.cross(c)) / 6.0
+            })
+            .sum()
+    }
+
+    fn unit_box(p: Vec3) -> f32 {
+        let q = p.abs() - Vec3::ONE;
+        q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+    }
+
+    #[test]
+    fn sphere_is_closed_and_outward_facing() {
+        let sphere = |p: Vec3| p.length() - 1.0;
+        let mesh = DualContouring::new(Vec3::splat(-1.3), Vec3::splat(1.3), 5).polygonize(&sphere);
+        let (positions, indices) = triangles(&mesh);
+
+        let mut directed = HashMap::new();
+        for triangle in indices.chunks_exact(3) {
+            for k in 0..3 {
+                *directed
+                    .entry((triangle[k], triangle[(k + 1) % 3]))
+                    .or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &directed {
+            assert_eq!(directed.get(&(b, a)), Some(&count), "open edge {a}-{b}");
+        }
+
+        let expected = 4.0 / 3.0 * core::f32::consts::PI;
+        let volume = volume(&positions, &indices);
+        assert!(
+            (volume - expected).abs() / expected < 0.02,
+            "volume {volume}"
+        );
+    }
+
+    #[test]
+    fn box_keeps_its_corners_with_few_triangles() {
+        let (min, max) = (Vec3::splat(-1.3), Vec3::splat(1.3));
+        let mesh = DualContouring::new(min, max, 5).polygonize(&unit_box);
+        let (positions, indices) = triangles(&mesh);
+
+        for corner in (0..8).map(|i| corner_offset(i) * 2.0 - 1.0) {
+            assert!(
+                positions.iter().any(|p| p.distance(corner) < 1e-3),
+                "lost corner {corner}"
+            );
+        }
+        assert!((volume(&positions, &indices) - 8.0).abs() < 1e-2);
+
+        let uniform = MarchingCubes::new(min, max, 32).polygonize(&unit_box);
+        let uniform_triangles = uniform.indices().unwrap().len() / 3;
+        assert!(indices.len() / 3 * 10 < uniform_triangles);
+    }
+}
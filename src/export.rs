@@ -0,0 +1,527 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+
+use crate::fractal::FractalParams;
+use crate::generator::{instance_path, FractalGeometry, FractalNode};
+use crate::mesh_builder::{mesh_triangles, oriented};
+use crate::print_check::{PrintMesh, PrintReport};
+use crate::vox::VoxelGrid;
+use crate::{gltf, obj, ply, stl, vox};
+
+/// File formats fractal geometry can be written to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Binary STL
+    Stl,
+    AsciiStl,
+    /// Wavefront OBJ, with its materials in an MTL file of the same name
+    Obj,
+    /// glTF 2.0 JSON, with its buffer in a `.bin` file of the same name
+    Gltf,
+    /// Binary glTF 2.0, everything in one file
+    Glb,
+    /// Binary little-endian PLY, keeping point clouds' colours
+    Ply,
+    /// MagicaVoxel model of the cells inside the geometry
+    Vox,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 7] = [
+        ExportFormat::Stl,
+        ExportFormat::AsciiStl,
+        ExportFormat::Obj,
+        ExportFormat::Gltf,
+        ExportFormat::Glb,
+        ExportFormat::Ply,
+        ExportFormat::Vox,
+    ];
+
+    /// Name on the command line, which is also the usual file extension
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Stl => "stl",
+            ExportFormat::AsciiStl => "stl_ascii",
+            ExportFormat::Obj => "obj",
+            ExportFormat::Gltf => "gltf",
+            ExportFormat::Glb => "glb",
+            ExportFormat::Ply => "ply",
+            ExportFormat::Vox => "vox",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The format a file name's extension implies
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str().and_then(Self::from_name)
+    }
+}
+
+/// How to write a scene, for the formats these apply to
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportOptions {
+    /// Weld touching parts into one surface, dropping the faces they share exactly, for STL
+    pub weld: bool,
+    /// Millimetres per scene unit; STL coordinates are scaled by it, since slicers read them
+    /// as millimetres
+    pub scale: f32,
+    /// Smallest detail the printer can make, in millimetres, for the STL print check
+    pub min_feature: f32,
+    /// Write glTF as a node per sub-structure of the recursion, sharing meshes between
+    /// instances, rather than as one merged mesh
+    pub hierarchy: bool,
+    /// Voxels along each side of a `.vox` model; by default geometry built on a grid gets a
+    /// voxel per grid cell
+    pub voxel_resolution: Option<u32>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            weld: false,
+            scale: 1.0,
+            min_feature: 0.4,
+            hierarchy: false,
+            voxel_resolution: None,
+        }
+    }
+}
+
+/// The parts of a `StandardMaterial` file formats can carry
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportMaterial {
+    /// Unique within a scene and free of whitespace
+    pub name: String,
+    pub base_color: Color,
+    pub emissive: Color,
+    pub perceptual_roughness: f32,
+    pub metallic: f32,
+}
+
+impl ExportMaterial {
+    pub fn new(name: impl Into<String>, material: &StandardMaterial) -> Self {
+        Self {
+            name: name.into(),
+            base_color: material.base_color,
+            emissive: material.emissive,
+            perceptual_roughness: material.perceptual_roughness,
+            metallic: material.metallic,
+        }
+    }
+}
+
+/// One placed copy of a mesh
+#[derive(Clone, Debug)]
+pub struct ExportPart {
+    /// Unique within a scene and free of whitespace
+    pub name: String,
+    /// Index into `ExportScene::meshes`
+    pub mesh: usize,
+    /// Index into `ExportScene::materials`
+    pub material: usize,
+    pub transform: Affine3A,
+    /// Index into `ExportScene::fractals`
+    pub fractal: usize,
+    /// The child taken at each level of the recursion to reach this part, empty when the part
+    /// does not come from one
+    pub path: Vec<u32>,
+}
+
+/// Geometry gathered for writing out. Parts keep their transforms apart from the meshes they
+/// place, so formats that can share a mesh between parts do, and the others bake them in.
+#[derive(Clone, Debug, Default)]
+pub struct ExportScene {
+    /// Names of the fractals the parts belong to
+    pub fractals: Vec<String>,
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<ExportMaterial>,
+    pub parts: Vec<ExportPart>,
+}
+
+impl ExportScene {
+    /// A scene holding generator output, all drawn with `material`; instances share the seed
+    pub fn from_geometry(geometry: FractalGeometry, name: &str, material: ExportMaterial) -> Self {
+        let mut scene = Self {
+            fractals: vec![name.to_string()],
+            materials: vec![material],
+            ..default()
+        };
+        let mut add_part = |mesh: usize, transform: Affine3A, path: Vec<u32>| {
+            scene.parts.push(ExportPart {
+                name: part_name(name, &path, scene.parts.len()),
+                mesh,
+                material: 0,
+                transform,
+                fractal: 0,
+                path,
+            });
+        };
+
+        match geometry {
+            FractalGeometry::Merged(chunks) | FractalGeometry::MergedInstances { chunks, .. } => {
+                for index in 0..chunks.len() {
+                    add_part(index, Affine3A::IDENTITY, Vec::new());
+                }
+                scene.meshes = chunks;
+            }
+            FractalGeometry::Points(points) => {
+                add_part(0, Affine3A::IDENTITY, Vec::new());
+                scene.meshes = vec![points];
+            }
+            FractalGeometry::Instanced {
+                seed,
+                instances,
+                branching,
+            } => {
+                for (index, instance) in instances.iter().enumerate() {
+                    let path = instance_path(index, instances.len(), branching);
+                    add_part(0, instance.compute_affine(), path);
+                }
+                scene.meshes = vec![seed];
+            }
+        }
+        scene
+    }
+
+    /// Every generated fractal in `world` as it is drawn, named after its generator. Meshes
+    /// and materials shared between parts stay shared, and parts keep their `FractalNode` paths.
+    pub fn from_world(world: &mut World) -> Self {
+        let mut fractals = world.query::<(Entity, &FractalParams)>();
+        let mut children = world.query::<&Children>();
+        let mut parts = world.query::<(
+            &GlobalTransform,
+            &Handle<Mesh>,
+            &Handle<StandardMaterial>,
+            &FractalNode,
+        )>();
+        let mesh_assets = world.resource::<Assets<Mesh>>();
+        let material_assets = world.resource::<Assets<StandardMaterial>>();
+
+        let mut scene = Self::default();
+        let mut mesh_indices = HashMap::new();
+        let mut material_indices = HashMap::new();
+        for (root, params) in fractals.iter(world) {
+            let fractal = scene.fractals.len();
+            let fractal_name = format!("{}_{fractal}", params.kind);
+
+            // Depth first through the recursion, in the order the parts were generated
+            let mut stack = vec![root];
+            while let Some(entity) = stack.pop() {
+                if let Ok(entity_children) = children.get(world, entity) {
+                    stack.extend(entity_children.iter().rev());
+                }
+                let Ok((transform, mesh_handle, material_handle, node)) = parts.get(world, entity)
+                else {
+                    continue;
+                };
+                let (Some(mesh), Some(material)) = (
+                    mesh_assets.get(mesh_handle),
+                    material_assets.get(material_handle),
+                ) else {
+                    continue;
+                };
+
+                let mesh = *mesh_indices.entry(mesh_handle.clone()).or_insert_with(|| {
+                    scene.meshes.push(mesh.clone());
+                    scene.meshes.len() - 1
+                });
+                let material = *material_indices
+                    .entry(material_handle.clone())
+                    .or_insert_with(|| {
+                        let name = format!("material_{}", scene.materials.len());
+                        scene.materials.push(ExportMaterial::new(name, material));
+                        scene.materials.len() - 1
+                    });
+                scene.parts.push(ExportPart {
+                    name: part_name(&fractal_name, &node.path, scene.parts.len()),
+                    mesh,
+                    material,
+                    transform: transform.affine(),
+                    fractal,
+                    path: node.path.clone(),
+                });
+            }
+            scene.fractals.push(fractal_name);
+        }
+        scene
+    }
+
+    /// The triangles of each part, with its transform baked in
+    pub fn part_triangles(&self) -> Vec<Vec<[Vec3; 3]>> {
+        self.parts
+            .iter()
+            .map(|part| {
+                mesh_triangles(&self.meshes[part.mesh])
+                    .into_iter()
+                    .map(|corners| {
+                        let corners = corners.map(|corner| part.transform.transform_point3(corner));
+                        oriented(corners, &part.transform)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Every triangle with its part's transform baked in
+    pub fn triangles(&self) -> Vec<[Vec3; 3]> {
+        self.part_triangles().concat()
+    }
+}
+
+/// A part's name: its fractal's followed by its recursion path, or by `index` without one
+fn part_name(fractal: &str, path: &[u32], index: usize) -> String {
+    if path.is_empty() {
+        format!("{fractal}_{index}")
+    } else {
+        let steps: Vec<_> = path.iter().map(u32::to_string).collect();
+        format!("{fractal}_{}", steps.join("_"))
+    }
+}
+
+/// Writes `scene` to a new file at `path`, plus whatever files the format keeps beside it.
+/// STL exports are also checked for printing, and their report returned.
+pub fn export(
+    scene: &ExportScene,
+    format: ExportFormat,
+    path: &Path,
+    options: &ExportOptions,
+) -> io::Result<Option<PrintReport>> {
+    let create = |path: &Path| File::create(path).map(BufWriter::new);
+    match format {
+        ExportFormat::Stl | ExportFormat::AsciiStl => {
+            let mesh = if options.weld {
+                PrintMesh::welded(&scene.part_triangles())
+            } else {
+                PrintMesh::new(&scene.triangles())
+            };
+            if mesh.triangles.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "there are no triangles to write, STL cannot hold point clouds",
+                ));
+            }
+            let triangles: Vec<[Vec3; 3]> = mesh
+                .corners()
+                .into_iter()
+                .map(|corners| corners.map(|corner| corner * options.scale))
+                .collect();
+
+            let mut writer = create(path)?;
+            if format == ExportFormat::AsciiStl {
+                let name = path.file_stem().and_then(|name| name.to_str());
+                stl::write_ascii(&triangles, name.unwrap_or("fractal"), &mut writer)?;
+            } else {
+                stl::write_binary(&triangles, &mut writer)?;
+            }
+            writer.flush()?;
+            Ok(Some(mesh.report(options.scale, options.min_feature)))
+        }
+        ExportFormat::Obj => {
+            let mtl_path = path.with_extension("mtl");
+            let mtl_name = mtl_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bad file name"))?;
+            let mut writer = create(path)?;
+            obj::write_obj(scene, mtl_name, &mut writer)?;
+            writer.flush()?;
+            let mut writer = create(&mtl_path)?;
+            obj::write_mtl(&scene.materials, &mut writer)?;
+            writer.flush()?;
+            Ok(None)
+        }
+        ExportFormat::Gltf => {
+            let bin_path = path.with_extension("bin");
+            let bin_name = bin_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bad file name"))?;
+            let document = gltf::Document::new(scene, options.hierarchy);
+            let mut writer = create(path)?;
+            writer.write_all(document.json(Some(bin_name)).as_bytes())?;
+            writer.flush()?;
+            std::fs::write(&bin_path, document.buffer())?;
+            Ok(None)
+        }
+        ExportFormat::Glb => {
+            let document = gltf::Document::new(scene, options.hierarchy);
+            let mut writer = create(path)?;
+            document.write_glb(&mut writer)?;
+            writer.flush()?;
+            Ok(None)
+        }
+        ExportFormat::Ply => {
+            let mut writer = create(path)?;
+            ply::write_ply(scene, &mut writer)?;
+            writer.flush()?;
+            Ok(None)
+        }
+        ExportFormat::Vox => {
+            let grid = VoxelGrid::from_scene(scene, options.voxel_resolution)?;
+            if grid.cells.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no voxels are inside the geometry",
+                ));
+            }
+            let mut writer = create(path)?;
+            vox::write_vox(&grid, &mut writer)?;
+            writer.flush()?;
+            Ok(None)
+        }
+    }
+}
+
+/// Asks for every fractal in the scene to be written to `path`
+#[derive(Event, Clone, Debug)]
+pub struct ExportRequest {
+    pub format: ExportFormat,
+    pub path: PathBuf,
+    pub options: ExportOptions,
+}
+
+/// Snapshots the fractals for every request and writes them off the main thread
+fn export_requested_scenes(world: &mut World) {
+    let requests: Vec<ExportRequest> = world
+        .resource_mut::<Events<ExportRequest>>()
+        .drain()
+        .collect();
+    for request in requests {
+        let scene = ExportScene::from_world(world);
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                match export(&scene, request.format, &request.path, &request.options) {
+                    Ok(report) => {
+                        info!("Exported fractals to {}", request.path.display());
+                        if let Some(report) = report {
+                            info!("{report}");
+                        }
+                    }
+                    Err(error) => error!("Could not write {}: {error}", request.path.display()),
+                }
+            })
+            .detach();
+    }
+}
+
+/// Exports the scene's fractals to `fractals.obj` when F10 is pressed
+fn request_on_keypress(keys: Res<Input<KeyCode>>, mut requests: EventWriter<ExportRequest>) {
+    if keys.just_pressed(KeyCode::F10) {
+        requests.send(ExportRequest {
+            format: ExportFormat::Obj,
+            path: PathBuf::from("fractals.obj"),
+            options: default(),
+        });
+    }
+}
+
+/// Writes the viewer's fractals to disk on `ExportRequest`s
+pub struct ExportPlugin;
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExportRequest>().add_systems(
+            Update,
+            (request_on_keypress, export_requested_scenes).chain(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_share_their_seed() {
+        let geometry = FractalGeometry::Instanced {
+            seed: Mesh::from(shape::Cube::new(1.0)),
+            instances: vec![Transform::IDENTITY, Transform::from_xyz(2.0, 0.0, 0.0)],
+            branching: 2,
+        };
+        let material = ExportMaterial::new("fractal", &StandardMaterial::default());
+        let scene = ExportScene::from_geometry(geometry, "menger", material);
+
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.parts.len(), 2);
+        assert_eq!(scene.parts[1].name, "menger_1");
+
+        let triangles = scene.triangles();
+        assert_eq!(triangles.len(), 24);
+        let max_x = triangles
+            .iter()
+            .flatten()
+            .map(|p| p.x)
+            .fold(f32::MIN, f32::max);
+        assert_eq!(max_x, 2.5);
+    }
+
+    #[test]
+    fn obj_indices_run_across_parts() {
+        let geometry = FractalGeometry::Instanced {
+            seed: Mesh::from(shape::Cube::new(1.0)),
+            instances: vec![
+                Transform::IDENTITY,
+                Transform::from_scale(Vec3::new(-1.0, 1.0, 1.0)),
+            ],
+            branching: 2,
+        };
+        let material = ExportMaterial::new("fractal", &StandardMaterial::default());
+        let scene = ExportScene::from_geometry(geometry, "menger", material);
+        let mut bytes = Vec::new();
+        obj::write_obj(&scene, "menger.mtl", &mut bytes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+
+        assert_eq!(
+            text.lines().filter(|line| line.starts_with("v ")).count(),
+            48
+        );
+        assert_eq!(
+            text.lines().filter(|line| line.starts_with("vt ")).count(),
+            48
+        );
+        assert_eq!(
+            text.lines().filter(|line| line.starts_with("vn ")).count(),
+            48
+        );
+        assert_eq!(
+            text.lines().filter(|line| line.starts_with("f ")).count(),
+            24
+        );
+        assert!(text.contains("usemtl fractal"));
+        // The second cube's faces point at its own vertices
+        let last_face = text.lines().rfind(|line| line.starts_with("f ")).unwrap();
+        assert!(last_face.split_whitespace().skip(1).all(|corner| corner
+            .split('/')
+            .all(|i| (25..=48).contains(&i.parse().unwrap()))));
+
+        // STL bakes the mirror in, so its facets must still point away from each cube's centre
+        let mut bytes = Vec::new();
+        stl::write_binary(&scene.triangles(), &mut bytes).unwrap();
+        let facets = bytes[84..].chunks(50).map(|facet| {
+            let floats: Vec<f32> = facet[..48]
+                .chunks(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+            let normal = Vec3::from_slice(&floats[..3]);
+            let centroid = (Vec3::from_slice(&floats[3..6])
+                + Vec3::from_slice(&floats[6..9])
+                + Vec3::from_slice(&floats[9..]))
+                / 3.0;
+            (normal, centroid)
+        });
+        assert_eq!(facets.len(), 24);
+        for (normal, centroid) in facets {
+            assert!(normal.dot(centroid) > 0.0, "{normal} faces into the cube");
+        }
+    }
+}
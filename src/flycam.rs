@@ -2,6 +2,7 @@ use bevy::ecs::event::{Events, ManualEventReader};
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
 use bevy::window::{CursorGrabMode, PrimaryWindow};
+use bevy_rapier3d::prelude::*;
 
 pub mod prelude {
     pub use crate::*;
@@ -18,6 +19,9 @@ struct InputState {
 pub struct MovementSettings {
     pub sensitivity: f32,
     pub speed: f32,
+    /// Radius of the sphere flycams sweep through Rapier colliders before moving, sliding
+    /// along what they hit; `None` flies through everything
+    pub collision_radius: Option<f32>,
 }
 
 impl Default for MovementSettings {
@@ -25,6 +29,38 @@ impl Default for MovementSettings {
         Self {
             sensitivity: 0.00012,
             speed: 12.,
+            collision_radius: None,
+        }
+    }
+}
+
+/// How the camera moves on foot in walk mode, in scene units and seconds
+#[derive(Resource)]
+pub struct WalkSettings {
+    pub speed: f32,
+    /// Upward speed a jump starts with
+    pub jump_speed: f32,
+    pub gravity: f32,
+    /// Height of the camera above the feet
+    pub eye_height: f32,
+    pub radius: f32,
+    /// Tallest ledge walked up without jumping
+    pub step_height: f32,
+    /// Steepest slope walked up, in radians
+    pub max_slope: f32,
+}
+
+impl Default for WalkSettings {
+    fn default() -> Self {
+        // Small enough to fit through the tunnels of the sponges in the scene
+        Self {
+            speed: 3.,
+            jump_speed: 3.5,
+            gravity: 9.81,
+            eye_height: 0.6,
+            radius: 0.15,
+            step_height: 0.15,
+            max_slope: 45f32.to_radians(),
         }
     }
 }
@@ -39,6 +75,8 @@ pub struct KeyBindings {
     pub move_ascend: KeyCode,
     pub move_descend: KeyCode,
     pub toggle_grab_cursor: KeyCode,
+    /// Switches between flying and walking; while walking, `move_ascend` jumps
+    pub toggle_walk: KeyCode,
 }
 
 impl Default for KeyBindings {
@@ -51,6 +89,7 @@ impl Default for KeyBindings {
             move_ascend: KeyCode::Space,
             move_descend: KeyCode::ShiftLeft,
             toggle_grab_cursor: KeyCode::Escape,
+            toggle_walk: KeyCode::G,
         }
     }
 }
@@ -60,6 +99,12 @@ impl Default for KeyBindings {
 #[derive(Component)]
 pub struct FlyCam;
 
+/// Marks a flycam that walks under gravity instead of flying
+#[derive(Component, Default)]
+pub struct Walking {
+    vertical_speed: f32,
+}
+
 /// Grabs/ungrabs mouse cursor
 fn toggle_grab_cursor(window: &mut Window) {
     match window.cursor.grab_mode {
@@ -101,7 +146,8 @@ fn player_move(
     primary_window: Query<&Window, With<PrimaryWindow>>,
     settings: Res<MovementSettings>,
     key_bindings: Res<KeyBindings>,
-    mut query: Query<(&FlyCam, &mut Transform)>, //    mut query: Query<&mut Transform, With<FlyCam>>,
+    rapier: Option<Res<RapierContext>>,
+    mut query: Query<(&FlyCam, &mut Transform), Without<Walking>>, //    mut query: Query<&mut Transform, With<FlyCam>>,
 ) {
     if let Ok(window) = primary_window.get_single() {
         for (_camera, mut transform) in query.iter_mut() {
@@ -130,17 +176,167 @@ fn player_move(
                         }
                     }
                 }
-
-                velocity = velocity.normalize_or_zero();
-
-                transform.translation += velocity * time.delta_seconds() * settings.speed
             }
+
+            let motion = velocity.normalize_or_zero() * time.delta_seconds() * settings.speed;
+            let motion = match (settings.collision_radius, &rapier) {
+                (Some(radius), Some(rapier)) => {
+                    slide(rapier, transform.translation, motion, radius)
+                }
+                _ => motion,
+            };
+            transform.translation += motion;
         }
     } else {
         warn!("Primary window not found for `player_move`!");
     }
 }
 
+/// How far a sphere of `radius` at `position` gets moving by `motion`, stopping short of the
+/// colliders in its way and sliding along them with what is left
+fn slide(rapier: &RapierContext, position: Vec3, motion: Vec3, radius: f32) -> Vec3 {
+    let sphere = Collider::ball(radius);
+    // Kept between the sphere and what it touches, so the next cast does not start inside it
+    let skin = radius * 0.05;
+    let mut moved = Vec3::ZERO;
+    let mut remaining = motion;
+    // A few bounces are enough to settle into a corner
+    for _ in 0..4 {
+        let distance = remaining.length();
+        if distance <= f32::EPSILON {
+            break;
+        }
+        let direction = remaining / distance;
+        let hit = rapier.cast_shape(
+            position + moved,
+            Quat::IDENTITY,
+            direction,
+            &sphere,
+            distance + skin,
+            QueryFilter::default().exclude_sensors(),
+        );
+        // Already inside something there is no telling which way is out, so nothing stops it
+        let Some((_, hit)) = hit.filter(|(_, hit)| hit.status != TOIStatus::Penetrating) else {
+            moved += remaining;
+            break;
+        };
+        let travelled = (hit.toi - skin).clamp(0.0, distance);
+        moved += direction * travelled;
+        remaining = direction * (distance - travelled);
+        remaining -= hit.normal1 * remaining.dot(hit.normal1).min(0.0);
+    }
+    moved
+}
+
+/// Switches flycams between flying and walking, giving walkers a character controller
+fn toggle_walk(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    settings: Res<WalkSettings>,
+    query: Query<(Entity, Option<&Walking>), With<FlyCam>>,
+) {
+    if !keys.just_pressed(key_bindings.toggle_walk) {
+        return;
+    }
+    for (entity, walking) in query.iter() {
+        if walking.is_some() {
+            commands.entity(entity).remove::<(
+                Walking,
+                KinematicCharacterController,
+                KinematicCharacterControllerOutput,
+            )>();
+            continue;
+        }
+        // A capsule from the feet up to the eyes; `player_walk` keeps it upright
+        let half_length = (settings.eye_height / 2.0 - settings.radius).max(0.0);
+        let capsule = Collider::capsule_y(half_length, settings.radius);
+        commands.entity(entity).insert((
+            Walking::default(),
+            KinematicCharacterController {
+                custom_shape: Some((capsule, Vec3::ZERO, Quat::IDENTITY)),
+                offset: CharacterLength::Absolute(0.01),
+                autostep: Some(CharacterAutostep {
+                    max_height: CharacterLength::Absolute(settings.step_height),
+                    min_width: CharacterLength::Absolute(settings.radius),
+                    include_dynamic_bodies: false,
+                }),
+                max_slope_climb_angle: settings.max_slope,
+                min_slope_slide_angle: settings.max_slope,
+                snap_to_ground: Some(CharacterLength::Absolute(settings.step_height)),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Walks flycams in walk mode along the ground, falling and jumping under gravity
+fn player_walk(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    settings: Res<WalkSettings>,
+    key_bindings: Res<KeyBindings>,
+    mut query: Query<(
+        &Transform,
+        &mut Walking,
+        &mut KinematicCharacterController,
+        Option<&KinematicCharacterControllerOutput>,
+    )>,
+) {
+    let Ok(window) = primary_window.get_single() else {
+        warn!("Primary window not found for `player_walk`!");
+        return;
+    };
+    let grabbed = window.cursor.grab_mode != CursorGrabMode::None;
+    let dt = time.delta_seconds();
+    for (transform, mut walking, mut controller, output) in query.iter_mut() {
+        let local_z = transform.local_z();
+        let forward = -Vec3::new(local_z.x, 0., local_z.z).normalize_or_zero();
+        let right = Vec3::new(local_z.z, 0., -local_z.x).normalize_or_zero();
+
+        let mut direction = Vec3::ZERO;
+        if grabbed {
+            for &key in keys.get_pressed() {
+                if key == key_bindings.move_forward {
+                    direction += forward;
+                } else if key == key_bindings.move_backward {
+                    direction -= forward;
+                } else if key == key_bindings.move_left {
+                    direction -= right;
+                } else if key == key_bindings.move_right {
+                    direction += right;
+                }
+            }
+        }
+
+        let grounded = output.is_some_and(|output| output.grounded);
+        if let Some(output) = output {
+            // Landing, or bumping a ceiling, stops the vertical motion
+            let blocked =
+                output.effective_translation.y.abs() < output.desired_translation.y.abs() * 0.5;
+            if (grounded && walking.vertical_speed < 0.0) || blocked {
+                walking.vertical_speed = 0.0;
+            }
+        }
+        if grounded && grabbed && keys.just_pressed(key_bindings.move_ascend) {
+            walking.vertical_speed = settings.jump_speed;
+        }
+        walking.vertical_speed -= settings.gravity * dt;
+
+        let velocity =
+            direction.normalize_or_zero() * settings.speed + Vec3::Y * walking.vertical_speed;
+        controller.translation = Some(velocity * dt);
+
+        // The capsule hangs below the camera and stays upright however the camera looks
+        let unrotate = transform.rotation.inverse();
+        if let Some((_, offset, rotation)) = &mut controller.custom_shape {
+            *offset = unrotate * Vec3::new(0.0, -settings.eye_height / 2.0, 0.0);
+            *rotation = unrotate;
+        }
+    }
+}
+
 /// Handles looking around if cursor is locked
 fn player_look(
     settings: Res<MovementSettings>,
@@ -212,9 +408,11 @@ impl Plugin for PlayerPlugin {
         app.init_resource::<InputState>()
             .init_resource::<MovementSettings>()
             .init_resource::<KeyBindings>()
+            .init_resource::<WalkSettings>()
             .add_systems(Startup, setup_player)
             .add_systems(Startup, initial_grab_cursor)
             .add_systems(Update, player_move)
+            .add_systems(Update, (toggle_walk, player_walk).chain())
             .add_systems(Update, player_look)
             .add_systems(Update, cursor_grab);
     }
@@ -227,10 +425,71 @@ impl Plugin for NoCameraPlayerPlugin {
         app.init_resource::<InputState>()
             .init_resource::<MovementSettings>()
             .init_resource::<KeyBindings>()
+            .init_resource::<WalkSettings>()
             .add_systems(Startup, initial_grab_cursor)
             .add_systems(Startup, initial_grab_on_flycam_spawn)
             .add_systems(Update, player_move)
+            .add_systems(Update, (toggle_walk, player_walk).chain())
             .add_systems(Update, player_look)
             .add_systems(Update, cursor_grab);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier3d::rapier::prelude::ColliderBuilder;
+
+    use super::*;
+
+    const RADIUS: f32 = 0.5;
+
+    /// A context holding one wall whose face is the plane x = 2
+    fn wall() -> RapierContext {
+        let mut rapier = RapierContext::default();
+        let wall = ColliderBuilder::cuboid(1.0, 5.0, 5.0)
+            .translation([3.0, 0.0, 0.0].into())
+            .user_data(Entity::PLACEHOLDER.to_bits() as u128)
+            .build();
+        rapier.colliders.insert(wall);
+        rapier
+            .query_pipeline
+            .update(&rapier.bodies, &rapier.colliders);
+        rapier
+    }
+
+    #[test]
+    fn moves_stop_a_skin_short_of_walls() {
+        let rapier = wall();
+        let skin = RADIUS * 0.05;
+        // Short of the wall nothing gets in the way
+        let moved = slide(&rapier, Vec3::ZERO, Vec3::X, RADIUS);
+        assert!(moved.abs_diff_eq(Vec3::X, 1e-5), "{moved}");
+
+        let moved = slide(&rapier, Vec3::ZERO, Vec3::X * 4.0, RADIUS);
+        let stop = 2.0 - RADIUS - skin;
+        assert!(moved.abs_diff_eq(Vec3::X * stop, 1e-3), "{moved}");
+    }
+
+    #[test]
+    fn diagonal_moves_slide_along_walls() {
+        let rapier = wall();
+        let moved = slide(&rapier, Vec3::ZERO, Vec3::new(4.0, 0.0, 3.0), RADIUS);
+        // Only the part heading into the wall is taken off
+        assert!((moved.z - 3.0).abs() < 1e-3, "{moved}");
+        assert!(
+            moved.x < 2.0 - RADIUS && moved.x > 2.0 - RADIUS * 1.1,
+            "{moved}"
+        );
+        assert!(moved.y.abs() < 1e-3, "{moved}");
+    }
+
+    #[test]
+    fn moves_starting_inside_are_not_stopped() {
+        let rapier = wall();
+        let motion = Vec3::new(1.0, 0.5, 0.0);
+        assert_eq!(
+            slide(&rapier, Vec3::new(3.0, 0.0, 0.0), motion, RADIUS),
+            motion
+        );
+    }
+}
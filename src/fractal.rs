@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy_rapier3d::prelude::*;
+use futures_lite::future;
+
+use crate::collider::{fractal_collider, ColliderSettings};
+use crate::generator::{FractalGeometry, FractalRegistry, ParamValue, ParamValues, SeedShape};
+
+/// Describes the fractal an entity displays; changing it rebuilds the fractal in place
+#[derive(Component, Clone)]
+pub struct FractalParams {
+    /// Name of the generator in the `FractalRegistry`
+    pub kind: String,
+    pub depth: u32,
+    /// Size of each child relative to its parent, or the generator's own ratio when `None`.
+    /// Only generators that declare a `scale_ratio` parameter use it
+    pub scale_ratio: Option<f32>,
+    /// Shape copied at every leaf, or the generator's own shape when `None`
+    pub seed: Option<SeedShape>,
+    pub material: Handle<StandardMaterial>,
+    /// Gives the generated geometry a collider when set
+    pub colliders: Option<ColliderSettings>,
+    /// Any further generator-specific parameters
+    pub extra: ParamValues,
+}
+
+impl FractalParams {
+    pub fn new(kind: impl Into<String>, depth: u32, material: Handle<StandardMaterial>) -> Self {
+        Self {
+            kind: kind.into(),
+            depth,
+            scale_ratio: None,
+            seed: None,
+            material,
+            colliders: None,
+            extra: ParamValues::default(),
+        }
+    }
+
+    /// The parameter values handed to the generator
+    pub fn values(&self) -> ParamValues {
+        let mut values = self.extra.clone();
+        values.set("depth", ParamValue::Int(self.depth as i64));
+        if let Some(ratio) = self.scale_ratio {
+            values.set("scale_ratio", ParamValue::Float(ratio));
+        }
+        if let Some(seed) = self.seed {
+            values.set("seed", ParamValue::Seed(seed));
+        }
+        values
+    }
+}
+
+/// Everything needed to spawn a fractal; the generated parts become its children
+#[derive(Bundle)]
+pub struct FractalBundle {
+    pub params: FractalParams,
+    pub spatial: SpatialBundle,
+}
+
+impl FractalBundle {
+    pub fn new(params: FractalParams, transform: Transform) -> Self {
+        Self {
+            params,
+            spatial: SpatialBundle::from_transform(transform),
+        }
+    }
+}
+
+/// Geometry for a fractal that is still being built on the async compute pool, with its
+/// collider when one was asked for
+#[derive(Component)]
+struct PendingGeometry(Task<(FractalGeometry, Option<Collider>)>);
+
+/// Starts rebuilding every fractal whose `FractalParams` were added or changed.
+///
+/// Generation runs off the main thread; the previous geometry stays on screen until
+/// `spawn_generated_fractals` swaps in the new one.
+fn regenerate_fractals(
+    mut commands: Commands,
+    registry: Res<FractalRegistry>,
+    query: Query<(Entity, &FractalParams), Changed<FractalParams>>,
+) {
+    for (entity, params) in query.iter() {
+        let Some(generator) = registry.shared(&params.kind) else {
+            let available: Vec<_> = registry.names().collect();
+            warn!(
+                "No fractal generator named `{}`, expected one of {available:?}",
+                params.kind
+            );
+            continue;
+        };
+
+        let schema = generator.params();
+        let values = params.values();
+        for name in values.undeclared(&schema) {
+            warn!(
+                "Fractal generator `{}` has no `{name}` parameter, ignoring it",
+                params.kind
+            );
+        }
+        let values = values.resolve(&schema);
+        let colliders = params.colliders;
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let geometry = generator.generate(&values);
+            // Hulls and trimesh hierarchies take a while to build, so they are made here too
+            let collider = colliders.and_then(|settings| fractal_collider(&geometry, &settings));
+            (geometry, collider)
+        });
+        // Replacing a task that has not finished yet drops it, abandoning the stale generation
+        commands.entity(entity).insert(PendingGeometry(task));
+    }
+}
+
+/// Replaces a fractal's children with its new geometry once generation has finished
+fn spawn_generated_fractals(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(Entity, &FractalParams, &mut PendingGeometry)>,
+) {
+    for (entity, params, mut pending) in query.iter_mut() {
+        let Some((geometry, collider)) = future::block_on(future::poll_once(&mut pending.0)) else {
+            continue;
+        };
+
+        // Clear out the previous generation so the new one does not stack on top of it
+        commands
+            .entity(entity)
+            .remove::<PendingGeometry>()
+            .despawn_descendants();
+        let mut parts = geometry.spawn(
+            &mut commands,
+            &mut meshes,
+            &params.material,
+            Transform::IDENTITY,
+        );
+        if let Some(collider) = collider {
+            parts.push(
+                commands
+                    .spawn((RigidBody::Fixed, collider, TransformBundle::default()))
+                    .id(),
+            );
+        }
+        commands.entity(entity).push_children(&parts);
+    }
+}
+
+/// Registers the built-in generators and keeps fractals in sync with their `FractalParams`
+pub struct FractalPlugin;
+impl Plugin for FractalPlugin {
+    fn build(&self, app: &mut App) {
+        // The new tasks have to be in place before finished ones are collected, or a task
+        // finishing the frame its params change would take the newer one down with it
+        app.insert_resource(FractalRegistry::builtin()).add_systems(
+            Update,
+            (
+                regenerate_fractals,
+                apply_deferred,
+                spawn_generated_fractals,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undeclared_fields_are_reported() {
+        let registry = FractalRegistry::builtin();
+        let mut params = FractalParams::new("menger", 2, Handle::default());
+        params.scale_ratio = Some(0.4);
+        params.seed = Some(SeedShape::Cube);
+        let values = params.values();
+
+        let menger = registry.shared("menger").unwrap().params();
+        let undeclared: Vec<_> = values.undeclared(&menger).collect();
+        assert_eq!(undeclared, ["scale_ratio"]);
+
+        let sierpinski = registry.shared("sierpinski_tetrahedron").unwrap().params();
+        assert_eq!(values.undeclared(&sierpinski).count(), 0);
+    }
+
+    #[test]
+    fn changes_outlive_tasks_finishing_alongside_them() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_asset::<Mesh>()
+            .add_plugins(FractalPlugin);
+
+        // A stale generation that is already done when the params change
+        let stale = AsyncComputeTaskPool::get()
+            .spawn(async { (FractalGeometry::Merged(Vec::new()), None) });
+        while !stale.is_finished() {
+            std::thread::yield_now();
+        }
+        let mut params = FractalParams::new("menger", 1, default());
+        params.extra.set("merged", ParamValue::Bool(false));
+        let fractal = app
+            .world
+            .spawn((
+                FractalBundle::new(params, Transform::IDENTITY),
+                PendingGeometry(stale),
+            ))
+            .id();
+
+        for _ in 0..1000 {
+            app.update();
+            if app.world.get::<PendingGeometry>(fractal).is_none() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let children = app.world.get::<Children>(fractal).map_or(0, |c| c.len());
+        assert_eq!(children, 20);
+    }
+}
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use core::f32::consts::PI;
+
+use crate::ifs::{Ifs, IfsFractal};
+use crate::implicit::{
+    JuliaFractal, MandelboxFractal, MandelbulbFractal, MengerSdf, SierpinskiSdf,
+};
+use crate::mesh_builder::ChunkedMeshBuilder;
+use crate::primitives::Polyhedron;
+use crate::rule_sponge::SpongePattern;
+
+/// Value of a single generator parameter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamValue {
+    Int(i64),
+    Float(f32),
+    Bool(bool),
+    Seed(SeedShape),
+    Pattern(SpongePattern),
+}
+
+/// Shape a fractal is built from, copied at every leaf of the recursion
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedShape {
+    Tetrahedron,
+    Cube,
+    Octahedron,
+    Dodecahedron,
+    Icosahedron,
+    SquarePyramid,
+}
+
+impl SeedShape {
+    pub const ALL: [SeedShape; 6] = [
+        SeedShape::Tetrahedron,
+        SeedShape::Cube,
+        SeedShape::Octahedron,
+        SeedShape::Dodecahedron,
+        SeedShape::Icosahedron,
+        SeedShape::SquarePyramid,
+    ];
+
+    /// Lowercase name, as written on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            SeedShape::Tetrahedron => "tetrahedron",
+            SeedShape::Cube => "cube",
+            SeedShape::Octahedron => "octahedron",
+            SeedShape::Dodecahedron => "dodecahedron",
+            SeedShape::Icosahedron => "icosahedron",
+            SeedShape::SquarePyramid => "square_pyramid",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|shape| shape.name() == name)
+    }
+
+    pub fn polyhedron(self) -> Polyhedron {
+        match self {
+            SeedShape::Tetrahedron => Polyhedron::tetrahedron(),
+            SeedShape::Cube => Polyhedron::cube(),
+            SeedShape::Octahedron => Polyhedron::octahedron(),
+            SeedShape::Dodecahedron => Polyhedron::dodecahedron(),
+            SeedShape::Icosahedron => Polyhedron::icosahedron(),
+            SeedShape::SquarePyramid => Polyhedron::square_pyramid(),
+        }
+    }
+
+    /// The shape scaled to unit edges
+    pub fn mesh(self) -> Mesh {
+        self.polyhedron().mesh()
+    }
+}
+
+/// Type and allowed range of a generator parameter
+#[derive(Clone, Debug)]
+pub enum ParamKind {
+    Int(RangeInclusive<i64>),
+    Float(RangeInclusive<f32>),
+    Bool,
+    Seed,
+    Pattern,
+}
+
+/// Describes one parameter a generator accepts
+#[derive(Clone, Debug)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    pub default: ParamValue,
+}
+
+impl ParamSpec {
+    pub fn int(name: &'static str, range: RangeInclusive<i64>, default: i64) -> Self {
+        Self {
+            name,
+            kind: ParamKind::Int(range),
+            default: ParamValue::Int(default),
+        }
+    }
+
+    pub fn float(name: &'static str, range: RangeInclusive<f32>, default: f32) -> Self {
+        Self {
+            name,
+            kind: ParamKind::Float(range),
+            default: ParamValue::Float(default),
+        }
+    }
+
+    pub fn bool(name: &'static str, default: bool) -> Self {
+        Self {
+            name,
+            kind: ParamKind::Bool,
+            default: ParamValue::Bool(default),
+        }
+    }
+
+    pub fn seed(name: &'static str, default: SeedShape) -> Self {
+        Self {
+            name,
+            kind: ParamKind::Seed,
+            default: ParamValue::Seed(default),
+        }
+    }
+
+    pub fn pattern(name: &'static str, default: SpongePattern) -> Self {
+        Self {
+            name,
+            kind: ParamKind::Pattern,
+            default: ParamValue::Pattern(default),
+        }
+    }
+
+    /// Converts `value` to this parameter's type and clamps it into range
+    fn sanitize(&self, value: ParamValue) -> ParamValue {
+        match (&self.kind, value) {
+            (ParamKind::Int(range), ParamValue::Int(v)) => {
+                ParamValue::Int(v.clamp(*range.start(), *range.end()))
+            }
+            (ParamKind::Int(range), ParamValue::Float(v)) => {
+                ParamValue::Int((v.round() as i64).clamp(*range.start(), *range.end()))
+            }
+            (ParamKind::Float(range), ParamValue::Float(v)) => {
+                ParamValue::Float(v.clamp(*range.start(), *range.end()))
+            }
+            (ParamKind::Float(range), ParamValue::Int(v)) => {
+                ParamValue::Float((v as f32).clamp(*range.start(), *range.end()))
+            }
+            (ParamKind::Bool, ParamValue::Bool(v)) => ParamValue::Bool(v),
+            (ParamKind::Seed, ParamValue::Seed(v)) => ParamValue::Seed(v),
+            (ParamKind::Pattern, ParamValue::Pattern(v)) => ParamValue::Pattern(v),
+            _ => self.default,
+        }
+    }
+}
+
+/// Named parameter values handed to a generator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParamValues(HashMap<String, ParamValue>);
+
+impl ParamValues {
+    pub fn set(&mut self, name: impl Into<String>, value: ParamValue) -> &mut Self {
+        self.0.insert(name.into(), value);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<ParamValue> {
+        self.0.get(name).copied()
+    }
+
+    /// Fills in defaults for anything missing and clamps the rest to `schema`, dropping
+    /// values the schema does not know about
+    pub fn resolve(&self, schema: &[ParamSpec]) -> ParamValues {
+        let mut resolved = ParamValues::default();
+        for spec in schema {
+            let value = self
+                .get(spec.name)
+                .map_or(spec.default, |v| spec.sanitize(v));
+            resolved.set(spec.name, value);
+        }
+        resolved
+    }
+
+    /// Names of the values `schema` does not declare, which `resolve` would drop
+    pub fn undeclared<'a>(&'a self, schema: &'a [ParamSpec]) -> impl Iterator<Item = &'a str> {
+        self.0
+            .keys()
+            .map(String::as_str)
+            .filter(|name| schema.iter().all(|spec| spec.name != *name))
+    }
+
+    pub fn int(&self, name: &str) -> i64 {
+        match self.get(name) {
+            Some(ParamValue::Int(v)) => v,
+            other => panic!("parameter `{name}` is not an integer: {other:?}"),
+        }
+    }
+
+    pub fn float(&self, name: &str) -> f32 {
+        match self.get(name) {
+            Some(ParamValue::Float(v)) => v,
+            other => panic!("parameter `{name}` is not a float: {other:?}"),
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        match self.get(name) {
+            Some(ParamValue::Bool(v)) => v,
+            other => panic!("parameter `{name}` is not a bool: {other:?}"),
+        }
+    }
+
+    pub fn seed(&self, name: &str) -> SeedShape {
+        match self.get(name) {
+            Some(ParamValue::Seed(v)) => v,
+            other => panic!("parameter `{name}` is not a seed shape: {other:?}"),
+        }
+    }
+
+    pub fn pattern(&self, name: &str) -> SpongePattern {
+        match self.get(name) {
+            Some(ParamValue::Pattern(v)) => v,
+            other => panic!("parameter `{name}` is not a sponge pattern: {other:?}"),
+        }
+    }
+}
+
+/// Where a spawned piece of a fractal sits in its recursion. Every piece is a child of the
+/// piece it was copied from, up to the entity holding the fractal's `FractalParams`, so a whole
+/// subtree can be moved, hidden or despawned through its top entity.
+///
+/// Merged meshes and point clouds stand for the whole fractal, at depth 0; merged instances
+/// also carry `MergedParts` to tell the pieces within them apart.
+#[derive(Component, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FractalNode {
+    /// Levels below the fractal's root
+    pub depth: u32,
+    /// Which of its parent's copies this piece is
+    pub child_index: u32,
+    /// The child taken at each level of the recursion to reach this piece, from the root down
+    pub path: Vec<u32>,
+}
+
+impl FractalNode {
+    pub fn new(path: Vec<u32>) -> Self {
+        Self {
+            depth: path.len() as u32,
+            child_index: path.last().copied().unwrap_or(0),
+            path,
+        }
+    }
+}
+
+/// On a mesh of merged instances, how to read the `ATTRIBUTE_PART` of its vertices back as
+/// paths through the recursion
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MergedParts {
+    /// Instances merged, as in `instance_path`
+    pub count: usize,
+    pub branching: usize,
+}
+
+impl MergedParts {
+    /// The path to instance `part`
+    pub fn path(&self, part: u32) -> Vec<u32> {
+        instance_path(part as usize, self.count, self.branching)
+    }
+
+    /// The instance at the end of `path`, undoing `path`
+    pub fn part(&self, path: &[u32]) -> u32 {
+        path.iter()
+            .fold(0, |part, &child| part * self.branching as u32 + child)
+    }
+}
+
+/// Output of a fractal generator
+pub enum FractalGeometry {
+    /// The whole fractal merged into a few meshes
+    Merged(Vec<Mesh>),
+    /// Instances merged into a few meshes, every vertex tagged with the instance it belongs to
+    /// in `ATTRIBUTE_PART`
+    MergedInstances {
+        chunks: Vec<Mesh>,
+        parts: MergedParts,
+    },
+    /// One copy of `seed` for each transform
+    Instanced {
+        seed: Mesh,
+        instances: Vec<Transform>,
+        /// Copies every part is replaced by at each level of the recursion; `instances` lists
+        /// its leaves depth first, as `instance_path` reads them
+        branching: usize,
+    },
+    /// A point cloud as a single `PrimitiveTopology::PointList` mesh
+    Points(Mesh),
+}
+
+impl FractalGeometry {
+    /// Collapses the geometry into merged meshes, baking instance transforms into the vertices
+    pub fn into_merged(self) -> Vec<Mesh> {
+        match self {
+            FractalGeometry::Merged(chunks) | FractalGeometry::MergedInstances { chunks, .. } => {
+                chunks
+            }
+            FractalGeometry::Points(points) => vec![points],
+            FractalGeometry::Instanced {
+                seed, instances, ..
+            } => {
+                let mut builder = ChunkedMeshBuilder::default();
+                for (index, instance) in instances.iter().enumerate() {
+                    let chunk = builder.current();
+                    chunk.push_mesh(&seed, &instance.compute_affine());
+                    chunk.finish_part(index as u32);
+                }
+                builder.build()
+            }
+        }
+    }
+
+    /// Merges instances like `into_merged`, remembering which instance every vertex came from
+    pub fn merged(self) -> FractalGeometry {
+        match self {
+            FractalGeometry::Instanced {
+                ref instances,
+                branching,
+                ..
+            } => {
+                let parts = MergedParts {
+                    count: instances.len(),
+                    branching,
+                };
+                FractalGeometry::MergedInstances {
+                    chunks: self.into_merged(),
+                    parts,
+                }
+            }
+            geometry => geometry,
+        }
+    }
+
+    /// Spawns the geometry as `PbrBundle`s positioned relative to `transform`, returning the
+    /// entities at the top of the recursion. Instances become the leaves of a tree of
+    /// `FractalNode`s, with an untransformed `SpatialBundle` for every part of every level above.
+    pub fn spawn(
+        self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        material: &Handle<StandardMaterial>,
+        transform: Transform,
+    ) -> Vec<Entity> {
+        let mut spawn_mesh = |mesh: Handle<Mesh>, transform: Transform, node: FractalNode| {
+            commands
+                .spawn((
+                    PbrBundle {
+                        mesh,
+                        material: material.clone(),
+                        transform,
+                        ..default()
+                    },
+                    node,
+                ))
+                .id()
+        };
+
+        let (seed, instances, branching) = match self {
+            FractalGeometry::Merged(chunks) => {
+                return chunks
+                    .into_iter()
+                    .map(|chunk| spawn_mesh(meshes.add(chunk), transform, default()))
+                    .collect();
+            }
+            FractalGeometry::MergedInstances { chunks, parts } => {
+                let chunks: Vec<_> = chunks
+                    .into_iter()
+                    .map(|chunk| spawn_mesh(meshes.add(chunk), transform, default()))
+                    .collect();
+                for &chunk in &chunks {
+                    commands.entity(chunk).insert(parts);
+                }
+                return chunks;
+            }
+            FractalGeometry::Points(points) => {
+                return vec![spawn_mesh(meshes.add(points), transform, default())];
+            }
+            FractalGeometry::Instanced {
+                seed,
+                instances,
+                branching,
+            } => (meshes.add(seed), instances, branching),
+        };
+
+        let count = instances.len();
+        let leaves: Vec<_> = instances
+            .into_iter()
+            .enumerate()
+            .map(|(index, instance)| {
+                let node = FractalNode::new(instance_path(index, count, branching));
+                let path = node.path.clone();
+                (spawn_mesh(seed.clone(), transform * instance, node), path)
+            })
+            .collect();
+
+        // Walk down each leaf's path, making the parts above it on the way
+        let mut top = Vec::new();
+        let mut parts: HashMap<Vec<u32>, Entity> = HashMap::new();
+        let mut children: HashMap<Entity, Vec<Entity>> = HashMap::new();
+        for (leaf, path) in leaves {
+            let mut parent = None;
+            for depth in 1..=path.len() {
+                let entity = if depth == path.len() {
+                    leaf
+                } else if let Some(&part) = parts.get(&path[..depth]) {
+                    parent = Some(part);
+                    continue;
+                } else {
+                    let prefix = path[..depth].to_vec();
+                    let node = FractalNode::new(prefix.clone());
+                    let part = commands.spawn((SpatialBundle::default(), node)).id();
+                    parts.insert(prefix, part);
+                    part
+                };
+                match parent {
+                    Some(parent) => children.entry(parent).or_default().push(entity),
+                    None => top.push(entity),
+                }
+                parent = Some(entity);
+            }
+            if path.is_empty() {
+                top.push(leaf);
+            }
+        }
+        for (parent, children) in children {
+            commands.entity(parent).push_children(&children);
+        }
+        top
+    }
+}
+
+/// Where instance `index` of `count` sits in a recursion replacing every part by `branching`
+/// copies: the child taken at each level, from the root down
+pub fn instance_path(index: usize, count: usize, branching: usize) -> Vec<u32> {
+    let mut leaves = 1;
+    while branching > 1 && leaves < count {
+        leaves *= branching;
+    }
+    let mut path = Vec::new();
+    let mut index = index;
+    while leaves > 1 {
+        leaves /= branching;
+        path.push((index / leaves) as u32);
+        index %= leaves;
+    }
+    path
+}
+
+/// Applies `children` to `root` and then to every copy it produced, `depth` times over
+pub fn expand_instances(root: Transform, children: &[Transform], depth: u32) -> Vec<Transform> {
+    let mut instances = vec![root];
+    for _ in 0..depth {
+        instances = instances
+            .into_iter()
+            .flat_map(|parent| children.iter().map(move |&child| parent * child))
+            .collect();
+    }
+    instances
+}
+
+/// A kind of fractal that can be built from a set of parameters
+pub trait FractalGenerator: Send + Sync + 'static {
+    /// Unique name used to select the generator
+    fn name(&self) -> &'static str;
+
+    /// Parameters the generator understands
+    fn params(&self) -> Vec<ParamSpec>;
+
+    /// Builds the fractal; `params` has already been resolved against `params()`
+    fn generate(&self, params: &ParamValues) -> FractalGeometry;
+}
+
+/// Every fractal generator available at runtime
+#[derive(Resource, Default)]
+pub struct FractalRegistry {
+    generators: Vec<Arc<dyn FractalGenerator>>,
+}
+
+impl FractalRegistry {
+    /// A registry holding the generators that ship with the crate
+    pub fn builtin() -> Self {
+        let mut registry = Self::default();
+        registry
+            .register(crate::menger::MengerSponge)
+            .register(crate::sierpinski::SierpinskiTetrahedron)
+            .register(crate::jerusalem::JerusalemCube)
+            .register(crate::nflake::NFlake)
+            .register(crate::rule_sponge::RuleSponge)
+            .register(IfsFractal::new(
+                "sierpinski_ifs",
+                Ifs::sierpinski_tetrahedron(),
+                SeedShape::Tetrahedron,
+            ))
+            .register(IfsFractal::new(
+                "menger_ifs",
+                Ifs::menger(),
+                SeedShape::Cube,
+            ))
+            .register(IfsFractal::new(
+                "twisted_sponge",
+                Ifs::twisted_menger(PI / 12.0),
+                SeedShape::Cube,
+            ))
+            .register(IfsFractal::new(
+                "spiral_tree",
+                Ifs::spiral_tree(),
+                SeedShape::Cube,
+            ))
+            .register(IfsFractal::new(
+                "sheared_rep_tile",
+                Ifs::sheared_rep_tile(0.5),
+                SeedShape::Cube,
+            ))
+            .register(MandelbulbFractal)
+            .register(MandelboxFractal)
+            .register(JuliaFractal)
+            .register(MengerSdf)
+            .register(SierpinskiSdf);
+        registry
+    }
+
+    /// Adds `generator`, replacing any earlier one with the same name
+    pub fn register(&mut self, generator: impl FractalGenerator) -> &mut Self {
+        self.generators
+            .retain(|existing| existing.name() != generator.name());
+        self.generators.push(Arc::new(generator));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn FractalGenerator> {
+        self.generators
+            .iter()
+            .find(|generator| generator.name() == name)
+            .map(|generator| generator.as_ref())
+    }
+
+    /// A handle to the generator called `name` that can be moved onto another thread
+    pub fn shared(&self, name: &str) -> Option<Arc<dyn FractalGenerator>> {
+        self.generators
+            .iter()
+            .find(|generator| generator.name() == name)
+            .cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.generators.iter().map(|generator| generator.name())
+    }
+
+    /// Runs the generator called `name` with `params` filled in and clamped to its schema
+    pub fn generate(&self, name: &str, params: &ParamValues) -> Option<FractalGeometry> {
+        let generator = self.get(name)?;
+        Some(generator.generate(&params.resolve(&generator.params())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+
+    use super::*;
+    use crate::menger::menger_cubes;
+
+    #[test]
+    fn instances_hang_off_their_parents() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>();
+        let world = &mut app.world;
+
+        let geometry = FractalGeometry::Instanced {
+            seed: Mesh::from(shape::Cube::new(1.0)),
+            instances: menger_cubes(2, 3.0),
+            branching: 20,
+        };
+        let mut queue = CommandQueue::default();
+        let top = world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
+            let mut commands = Commands::new(&mut queue, world);
+            geometry.spawn(&mut commands, &mut meshes, &default(), Transform::IDENTITY)
+        });
+        queue.apply(world);
+
+        assert_eq!(top.len(), 20);
+        let first = world.entity(top[3]);
+        assert_eq!(first.get::<FractalNode>(), Some(&FractalNode::new(vec![3])));
+        assert!(first.get::<Handle<Mesh>>().is_none());
+        let children = first.get::<Children>().unwrap();
+        assert_eq!(children.len(), 20);
+        let leaf = world.entity(children[17]);
+        assert_eq!(
+            leaf.get::<FractalNode>(),
+            Some(&FractalNode {
+                depth: 2,
+                child_index: 17,
+                path: vec![3, 17],
+            })
+        );
+        assert!(leaf.get::<Handle<Mesh>>().is_some());
+        assert_eq!(world.query::<&FractalNode>().iter(world).count(), 420);
+    }
+}
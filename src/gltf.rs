@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use bevy::render::mesh::{PrimitiveTopology, VertexAttributeValues};
+
+use crate::export::{ExportMaterial, ExportScene};
+use crate::mesh_builder::MeshBuilder;
+
+// Accessor component types and buffer view targets from the glTF 2.0 specification
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+// GLB magic and chunk types, read as little-endian words
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON: u32 = 0x4E4F_534A;
+const GLB_BIN: u32 = 0x004E_4942;
+
+/// A node before it is written out, children referring to other nodes by index
+struct Node {
+    name: String,
+    mesh: Option<usize>,
+    transform: Option<Affine3A>,
+    children: Vec<usize>,
+}
+
+/// A glTF 2.0 document built from an `ExportScene`: the JSON's top-level arrays, kept as
+/// already written objects, and the binary buffer their accessors point into
+pub struct Document {
+    buffer: Vec<u8>,
+    buffer_views: Vec<String>,
+    accessors: Vec<String>,
+    materials: Vec<String>,
+    meshes: Vec<String>,
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+}
+
+impl Document {
+    /// Lays `scene` out as one node per fractal holding all its triangles merged into a mesh,
+    /// or with `hierarchy` as a tree of nodes following each part's recursion path, the leaves
+    /// sharing meshes so viewers can instance them
+    pub fn new(scene: &ExportScene, hierarchy: bool) -> Self {
+        let mut document = Self {
+            buffer: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            materials: scene.materials.iter().map(material).collect(),
+            meshes: Vec::new(),
+            nodes: Vec::new(),
+            roots: Vec::new(),
+        };
+        let mut roots = HashMap::new();
+        let mut root = |document: &mut Self, fractal: usize| {
+            *roots.entry(fractal).or_insert_with(|| {
+                let name = scene.fractals.get(fractal).cloned().unwrap_or_default();
+                let node = document.push_node(name, None, None);
+                document.roots.push(node);
+                node
+            })
+        };
+
+        if hierarchy {
+            let mut meshes = HashMap::new();
+            let mut groups = HashMap::new();
+            for part in &scene.parts {
+                let Some(mesh) = *meshes.entry((part.mesh, part.material)).or_insert_with(|| {
+                    let name = format!("mesh_{}", part.mesh);
+                    document.push_mesh(name, &[(&scene.meshes[part.mesh], part.material)])
+                }) else {
+                    continue;
+                };
+
+                // Sub-structures of the recursion become plain grouping nodes, so every level
+                // can be picked out and moved as one in a viewer
+                let mut parent = root(&mut document, part.fractal);
+                for depth in 1..part.path.len() {
+                    let prefix = &part.path[..depth];
+                    parent = *groups
+                        .entry((part.fractal, prefix.to_vec()))
+                        .or_insert_with(|| {
+                            let steps: Vec<_> = prefix.iter().map(u32::to_string).collect();
+                            let name =
+                                format!("{}_{}", document.nodes[parent].name, steps[depth - 1]);
+                            let node = document.push_node(name, None, None);
+                            document.nodes[parent].children.push(node);
+                            node
+                        });
+                }
+                let leaf = document.push_node(part.name.clone(), Some(mesh), Some(part.transform));
+                document.nodes[parent].children.push(leaf);
+            }
+        } else {
+            // Triangles are merged per fractal and material; anything else keeps its own node
+            let mut merged: HashMap<usize, HashMap<usize, MeshBuilder>> = HashMap::new();
+            for part in &scene.parts {
+                let mesh = &scene.meshes[part.mesh];
+                if mesh.primitive_topology() == PrimitiveTopology::TriangleList {
+                    merged
+                        .entry(part.fractal)
+                        .or_default()
+                        .entry(part.material)
+                        .or_default()
+                        .push_mesh(mesh, &part.transform);
+                } else if let Some(index) =
+                    document.push_mesh(part.name.clone(), &[(mesh, part.material)])
+                {
+                    let parent = root(&mut document, part.fractal);
+                    let node =
+                        document.push_node(part.name.clone(), Some(index), Some(part.transform));
+                    document.nodes[parent].children.push(node);
+                }
+            }
+
+            let mut fractals: Vec<_> = merged.into_iter().collect();
+            fractals.sort_by_key(|(fractal, _)| *fractal);
+            for (fractal, builders) in fractals {
+                let mut builders: Vec<_> = builders.into_iter().collect();
+                builders.sort_by_key(|(material, _)| *material);
+                let meshes: Vec<_> = builders
+                    .into_iter()
+                    .map(|(material, builder)| (builder.build(), material))
+                    .collect();
+                let primitives: Vec<_> = meshes
+                    .iter()
+                    .map(|(mesh, material)| (mesh, *material))
+                    .collect();
+                let parent = root(&mut document, fractal);
+                let name = document.nodes[parent].name.clone();
+                document.nodes[parent].mesh = document.push_mesh(name, &primitives);
+            }
+        }
+        document
+    }
+
+    /// The binary buffer, to be written where `json`'s buffer URI points
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// The document's JSON, referring to its buffer by `buffer_uri`, or to the binary chunk of
+    /// a GLB when there is none
+    pub fn json(&self, buffer_uri: Option<&str>) -> String {
+        let nodes: Vec<_> = self.nodes.iter().map(node).collect();
+        let uri = buffer_uri.map_or(String::new(), |uri| format!(",\"uri\":{}", string(uri)));
+        let mut json = format!(
+            "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"bevy_3d_fractals\"}},\
+             \"scene\":0,\"scenes\":[{{\"nodes\":{}}}],\"nodes\":[{}]",
+            list(&self.roots),
+            nodes.join(","),
+        );
+        for (key, objects) in [
+            ("meshes", &self.meshes),
+            ("materials", &self.materials),
+            ("accessors", &self.accessors),
+            ("bufferViews", &self.buffer_views),
+        ] {
+            if !objects.is_empty() {
+                json += &format!(",\"{key}\":[{}]", objects.join(","));
+            }
+        }
+        if !self.buffer.is_empty() {
+            json += &format!(
+                ",\"buffers\":[{{\"byteLength\":{}{uri}}}]",
+                self.buffer.len()
+            );
+        }
+        json + "}"
+    }
+
+    /// Writes the document as a single binary glTF file
+    pub fn write_glb(&self, writer: &mut impl Write) -> io::Result<()> {
+        // Chunks start on 4-byte boundaries; JSON is padded with spaces, binary with zeros
+        let mut json = self.json(None).into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = self.buffer.clone();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let mut length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            length += 8 + bin.len();
+        }
+        for word in [GLB_MAGIC, 2, length as u32, json.len() as u32, GLB_JSON] {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        writer.write_all(&json)?;
+        if !bin.is_empty() {
+            writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+            writer.write_all(&GLB_BIN.to_le_bytes())?;
+            writer.write_all(&bin)?;
+        }
+        Ok(())
+    }
+
+    fn push_node(
+        &mut self,
+        name: String,
+        mesh: Option<usize>,
+        transform: Option<Affine3A>,
+    ) -> usize {
+        self.nodes.push(Node {
+            name,
+            mesh,
+            transform,
+            children: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Adds a mesh with a primitive per `(mesh, material)`, skipping empty ones and those
+    /// glTF cannot draw; `None` if nothing was left
+    fn push_mesh(&mut self, name: String, primitives: &[(&Mesh, usize)]) -> Option<usize> {
+        let primitives: Vec<_> = primitives
+            .iter()
+            .filter_map(|&(mesh, material)| self.primitive(mesh, material))
+            .collect();
+        if primitives.is_empty() {
+            return None;
+        }
+        self.meshes.push(format!(
+            "{{\"name\":{},\"primitives\":[{}]}}",
+            string(&name),
+            primitives.join(",")
+        ));
+        Some(self.meshes.len() - 1)
+    }
+
+    fn primitive(&mut self, mesh: &Mesh, material: usize) -> Option<String> {
+        let mode = match mesh.primitive_topology() {
+            PrimitiveTopology::PointList => 0,
+            PrimitiveTopology::LineList => 1,
+            PrimitiveTopology::LineStrip => 3,
+            PrimitiveTopology::TriangleList => 4,
+            PrimitiveTopology::TriangleStrip => 5,
+        };
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+        if positions.is_empty() {
+            return None;
+        }
+
+        let mut attributes = vec![format!("\"POSITION\":{}", self.positions(positions))];
+        if let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        {
+            let accessor =
+                self.accessor(&le_bytes(normals), normals.len(), "VEC3", ARRAY_BUFFER, "");
+            attributes.push(format!("\"NORMAL\":{accessor}"));
+        }
+        if let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            let accessor = self.accessor(&le_bytes(uvs), uvs.len(), "VEC2", ARRAY_BUFFER, "");
+            attributes.push(format!("\"TEXCOORD_0\":{accessor}"));
+        }
+        if let Some(VertexAttributeValues::Float32x4(colors)) =
+            mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+        {
+            let accessor = self.accessor(&le_bytes(colors), colors.len(), "VEC4", ARRAY_BUFFER, "");
+            attributes.push(format!("\"COLOR_0\":{accessor}"));
+        }
+
+        let mut primitive = format!(
+            "{{\"attributes\":{{{}}},\"material\":{material},\"mode\":{mode}",
+            attributes.join(",")
+        );
+        if let Some(indices) = mesh.indices() {
+            let data: Vec<u8> = indices
+                .iter()
+                .flat_map(|i| (i as u32).to_le_bytes())
+                .collect();
+            let view = self.buffer_view(&data, ELEMENT_ARRAY_BUFFER);
+            self.accessors.push(format!(
+                "{{\"bufferView\":{view},\"componentType\":{UNSIGNED_INT},\"count\":{},\"type\":\"SCALAR\"}}",
+                indices.len()
+            ));
+            primitive += &format!(",\"indices\":{}", self.accessors.len() - 1);
+        }
+        Some(primitive + "}")
+    }
+
+    /// Positions need their bounds written alongside them
+    fn positions(&mut self, positions: &[[f32; 3]]) -> usize {
+        let (min, max) = positions.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), &position| (min.min(position.into()), max.max(position.into())),
+        );
+        let bounds = format!(
+            ",\"min\":{},\"max\":{}",
+            floats(&min.to_array()),
+            floats(&max.to_array())
+        );
+        self.accessor(
+            &le_bytes(positions),
+            positions.len(),
+            "VEC3",
+            ARRAY_BUFFER,
+            &bounds,
+        )
+    }
+
+    /// Adds a float accessor over `data`, with `extra` appended to its JSON object
+    fn accessor(
+        &mut self,
+        data: &[u8],
+        count: usize,
+        kind: &str,
+        target: u32,
+        extra: &str,
+    ) -> usize {
+        let view = self.buffer_view(data, target);
+        self.accessors.push(format!(
+            "{{\"bufferView\":{view},\"componentType\":{FLOAT},\"count\":{count},\"type\":\"{kind}\"{extra}}}"
+        ));
+        self.accessors.len() - 1
+    }
+
+    fn buffer_view(&mut self, data: &[u8], target: u32) -> usize {
+        // Every component is four bytes wide, so views stay aligned by starting on a multiple
+        // of four
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+        self.buffer_views.push(format!(
+            "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{target}}}",
+            self.buffer.len(),
+            data.len()
+        ));
+        self.buffer.extend_from_slice(data);
+        self.buffer_views.len() - 1
+    }
+}
+
+/// glTF's metallic-roughness material; glTF colour factors are linear, as Bevy shades in
+fn material(material: &ExportMaterial) -> String {
+    let base_color = material.base_color.as_linear_rgba_f32();
+    let [r, g, b, _] = material.emissive.as_linear_rgba_f32();
+    let alpha_mode = if base_color[3] < 1.0 {
+        "BLEND"
+    } else {
+        "OPAQUE"
+    };
+    format!(
+        "{{\"name\":{},\"pbrMetallicRoughness\":{{\"baseColorFactor\":{},\
+         \"metallicFactor\":{},\"roughnessFactor\":{}}},\"emissiveFactor\":{},\
+         \"alphaMode\":\"{alpha_mode}\"}}",
+        string(&material.name),
+        floats(&base_color),
+        number(material.metallic.clamp(0.0, 1.0)),
+        number(material.perceptual_roughness.clamp(0.0, 1.0)),
+        floats(&[r, g, b]),
+    )
+}
+
+fn node(node: &Node) -> String {
+    let mut json = format!("{{\"name\":{}", string(&node.name));
+    if let Some(mesh) = node.mesh {
+        json += &format!(",\"mesh\":{mesh}");
+    }
+    if let Some(transform) = node
+        .transform
+        .filter(|&transform| transform != Affine3A::IDENTITY)
+    {
+        json += &format!(
+            ",\"matrix\":{}",
+            floats(&Mat4::from(transform).to_cols_array())
+        );
+    }
+    if !node.children.is_empty() {
+        json += &format!(",\"children\":{}", list(&node.children));
+    }
+    json + "}"
+}
+
+/// Vertex data as the little-endian floats glTF buffers hold, whatever the host's byte order
+fn le_bytes<const N: usize>(values: &[[f32; N]]) -> Vec<u8> {
+    values
+        .iter()
+        .flatten()
+        .flat_map(|component| component.to_le_bytes())
+        .collect()
+}
+
+/// JSON has no infinities or NaNs, so those are written as zero
+fn number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "0".to_string()
+    }
+}
+
+fn floats(values: &[f32]) -> String {
+    let values: Vec<_> = values.iter().map(|&value| number(value)).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn list(indices: &[usize]) -> String {
+    let indices: Vec<_> = indices.iter().map(usize::to_string).collect();
+    format!("[{}]", indices.join(","))
+}
+
+fn string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            c if c.is_control() => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::ExportMaterial;
+    use crate::generator::FractalGeometry;
+
+    fn menger_like_scene() -> ExportScene {
+        // Two levels of a recursion with two children each
+        let geometry = FractalGeometry::Instanced {
+            seed: Mesh::from(shape::Cube::new(1.0)),
+            instances: (0..4)
+                .map(|i| Transform::from_xyz(i as f32, 0.0, 0.0))
+                .collect(),
+            branching: 2,
+        };
+        let material = ExportMaterial::new("fractal", &Color::rgba(1.0, 0.5, 0.0, 0.5).into());
+        ExportScene::from_geometry(geometry, "menger", material)
+    }
+
+    #[test]
+    fn hierarchy_shares_the_seed_mesh() {
+        let document = Document::new(&menger_like_scene(), true);
+        assert_eq!(document.meshes.len(), 1);
+        // The root, a group per first-level child and a leaf per instance
+        assert_eq!(document.nodes.len(), 1 + 2 + 4);
+        assert_eq!(document.roots, [0]);
+        assert_eq!(document.nodes[0].children.len(), 2);
+        let group = &document.nodes[document.nodes[0].children[1]];
+        assert_eq!(group.name, "menger_1");
+        assert_eq!(document.nodes[group.children[0]].name, "menger_1_0");
+        assert!(
+            document
+                .nodes
+                .iter()
+                .filter(|node| node.mesh == Some(0))
+                .count()
+                == 4
+        );
+
+        let json = document.json(Some("menger.bin"));
+        assert!(json.contains("\"uri\":\"menger.bin\""));
+        assert!(json.contains("\"alphaMode\":\"BLEND\""));
+    }
+
+    #[test]
+    fn merged_bakes_one_mesh() {
+        let document = Document::new(&menger_like_scene(), false);
+        assert_eq!(document.nodes.len(), 1);
+        assert_eq!(document.nodes[0].mesh, Some(0));
+        assert!(document.accessors[0].contains("\"count\":96"));
+        assert!(document.accessors[0].contains("\"max\":[3.5,0.5,0.5]"));
+
+        // The positions lead the buffer as little-endian floats
+        let positions: Vec<f32> = document.buffer()[..96 * 12]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        let max_x = positions.iter().step_by(3).fold(f32::MIN, |a, &b| a.max(b));
+        assert_eq!(max_x, 3.5);
+        assert!(positions.iter().all(|p| p.abs() <= 3.5));
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned() {
+        let document = Document::new(&menger_like_scene(), true);
+        let mut glb = Vec::new();
+        document.write_glb(&mut glb).unwrap();
+
+        let word = |offset: usize| u32::from_le_bytes(glb[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8) as usize, glb.len());
+        let json_length = word(12) as usize;
+        assert_eq!(json_length % 4, 0);
+        assert_eq!(&glb[16..20], b"JSON");
+        let bin = 20 + json_length;
+        assert_eq!(
+            word(bin) as usize,
+            document.buffer().len().next_multiple_of(4)
+        );
+        assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+        assert!(!String::from_utf8_lossy(&glb[20..bin]).contains("\"uri\""));
+    }
+}
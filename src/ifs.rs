@@ -0,0 +1,322 @@
+use core::f32::consts::PI;
+
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+
+use crate::chaos::chaos_game_mesh;
+use crate::generator::{
+    FractalGenerator, FractalGeometry, MergedParts, ParamSpec, ParamValues, SeedShape,
+};
+use crate::mesh_builder::ChunkedMeshBuilder;
+use crate::sierpinski::tetrahedron_vertices;
+
+/// Upper bound on seed copies before the depth gets cut back
+const MAX_INSTANCES: usize = 1 << 21;
+
+/// An iterated function system: a fractal made of affine copies of itself
+#[derive(Clone, Debug)]
+pub struct Ifs {
+    pub maps: Vec<Affine3A>,
+    /// Relative odds of each map being picked by the chaos game, if not left to the default
+    weights: Option<Vec<f32>>,
+}
+
+impl Ifs {
+    pub fn new(maps: Vec<Affine3A>) -> Self {
+        Self {
+            maps,
+            weights: None,
+        }
+    }
+
+    /// Picks the maps in the chaos game with the given relative odds, one per map.
+    ///
+    /// Panics unless there is a weight for every map, none negative and not all zero.
+    pub fn with_weights(mut self, weights: Vec<f32>) -> Self {
+        assert_eq!(weights.len(), self.maps.len(), "need one weight per map");
+        assert!(
+            weights.iter().all(|w| w.is_finite() && *w >= 0.0) && weights.iter().sum::<f32>() > 0.0,
+            "weights must be non-negative and not all zero: {weights:?}"
+        );
+        self.weights = Some(weights);
+        self
+    }
+
+    pub fn weights(&self) -> Option<&[f32]> {
+        self.weights.as_deref()
+    }
+
+    /// Every composition of `depth` maps applied after `root`.
+    ///
+    /// Copies come out ordered by their address: the copy reached through maps `i, j, k` sits
+    /// at index `(i * n + j) * n + k`, so the expansion is the same on every run.
+    pub fn expand(&self, root: Affine3A, depth: u32) -> Vec<Affine3A> {
+        let mut instances = vec![root];
+        for _ in 0..depth {
+            instances = instances
+                .into_iter()
+                .flat_map(|parent| self.maps.iter().map(move |&map| parent * map))
+                .collect();
+        }
+        instances
+    }
+
+    /// Deepest expansion that stays within `max_instances` copies, capped at `depth`
+    pub fn clamp_depth(&self, depth: u32, max_instances: usize) -> u32 {
+        let mut depth = depth;
+        while depth > 0
+            && self
+                .maps
+                .len()
+                .checked_pow(depth)
+                .is_none_or(|count| count > max_instances)
+        {
+            depth -= 1;
+        }
+        depth
+    }
+
+    /// Whether every map only rotates, translates and scales uniformly, so copies can be
+    /// expressed as a `Transform`
+    pub fn is_similarity(&self) -> bool {
+        self.maps.iter().all(|map| {
+            let m = Mat3::from(map.matrix3);
+            let gram = m.transpose() * m;
+            let scale = gram.x_axis.x;
+            gram.abs_diff_eq(Mat3::from_diagonal(Vec3::splat(scale)), 1e-4) && m.determinant() > 0.0
+        })
+    }
+
+    /// Bakes one copy of `seed` per composition of `depth` maps into merged meshes, each copy
+    /// tagged with its place in `expand`'s order
+    pub fn mesh(&self, seed: &Mesh, root: Affine3A, depth: u32) -> Vec<Mesh> {
+        let mut builder = ChunkedMeshBuilder::default();
+        for (index, instance) in self.expand(root, depth).into_iter().enumerate() {
+            let chunk = builder.current();
+            chunk.push_mesh(seed, &instance);
+            chunk.finish_part(index as u32);
+        }
+        builder.build()
+    }
+
+    /// The four half-size corner copies of the Sierpinski tetrahedron
+    pub fn sierpinski_tetrahedron() -> Self {
+        let maps = tetrahedron_vertices()
+            .iter()
+            .map(|&corner| {
+                Affine3A::from_scale_rotation_translation(
+                    Vec3::splat(0.5),
+                    Quat::IDENTITY,
+                    corner * 0.5,
+                )
+            })
+            .collect();
+        Self::new(maps)
+    }
+
+    /// The 20 third-size copies of the Menger sponge
+    pub fn menger() -> Self {
+        Self::twisted_menger(0.0)
+    }
+
+    /// The Menger sponge with every child also turned about its vertical axis
+    pub fn twisted_menger(angle: f32) -> Self {
+        let maps = (0..27u32)
+            .map(|i| UVec3::new(i % 3, i / 3 % 3, i / 9))
+            .filter(|&cell| crate::menger::is_solid(cell, 1))
+            .map(|cell| {
+                Affine3A::from_scale_rotation_translation(
+                    Vec3::splat(1.0 / 3.0),
+                    Quat::from_rotation_y(angle),
+                    (cell.as_vec3() - 1.0) / 3.0,
+                )
+            })
+            .collect();
+        Self::new(maps)
+    }
+
+    /// A stretched trunk topped by a shrinking, turning copy of the whole tree and a side branch
+    pub fn spiral_tree() -> Self {
+        // Consecutive branches step round by the golden angle so they never line up
+        let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+        Self::new(vec![
+            Affine3A::from_scale_rotation_translation(
+                Vec3::new(0.12, 0.5, 0.12),
+                Quat::IDENTITY,
+                Vec3::new(0.0, -0.25, 0.0),
+            ),
+            Affine3A::from_scale_rotation_translation(
+                Vec3::splat(0.75),
+                Quat::from_rotation_y(golden_angle) * Quat::from_rotation_z(0.15),
+                Vec3::new(0.0, 0.35, 0.0),
+            ),
+            Affine3A::from_scale_rotation_translation(
+                Vec3::splat(0.45),
+                Quat::from_rotation_y(golden_angle) * Quat::from_rotation_z(1.0),
+                Vec3::new(0.0, 0.15, 0.0),
+            ),
+        ])
+        // Weighting by volume would leave the thin trunk almost empty in the chaos game
+        .with_weights(vec![0.15, 0.55, 0.3])
+    }
+
+    /// A parallelepiped tiled by eight half-size copies of itself, showing that shear passes
+    /// through the recursion untouched
+    pub fn sheared_rep_tile(shear: f32) -> Self {
+        let shear = Mat3::from_cols(Vec3::X, Vec3::new(shear, 1.0, 0.0), Vec3::Z);
+        let maps = (0..8)
+            .map(|i| {
+                let corner = Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32);
+                Affine3A::from_mat3_translation(shear * 0.5, shear * ((corner - 0.5) / 2.0))
+            })
+            .collect();
+        Self::new(maps)
+    }
+}
+
+/// Exposes an `Ifs` through the generator registry under its own name
+pub struct IfsFractal {
+    pub name: &'static str,
+    pub ifs: Ifs,
+    pub seed: SeedShape,
+}
+
+impl IfsFractal {
+    pub fn new(name: &'static str, ifs: Ifs, seed: SeedShape) -> Self {
+        Self { name, ifs, seed }
+    }
+}
+
+impl FractalGenerator for IfsFractal {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::int("depth", 0..=10, 4),
+            ParamSpec::float("size", 0.1..=100.0, 3.0),
+            ParamSpec::bool("merged", true),
+            ParamSpec::seed("seed", self.seed),
+            // Any number of points switches from meshing copies to playing the chaos game
+            ParamSpec::int("chaos_points", 0..=10_000_000, 0),
+            ParamSpec::int("rng_seed", 0..=i64::MAX, 1),
+        ]
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let root = Affine3A::from_scale(Vec3::splat(params.float("size")));
+        let chaos_points = params.int("chaos_points") as usize;
+        if chaos_points > 0 {
+            let seed = params.int("rng_seed") as u64;
+            return FractalGeometry::Points(chaos_game_mesh(&self.ifs, root, chaos_points, seed));
+        }
+
+        let depth = self
+            .ifs
+            .clamp_depth(params.int("depth") as u32, MAX_INSTANCES);
+        let seed = params.seed("seed").mesh();
+
+        // Shear and non-uniform scale have no `Transform` equivalent, so those always merge
+        if params.bool("merged") || !self.ifs.is_similarity() {
+            FractalGeometry::MergedInstances {
+                chunks: self.ifs.mesh(&seed, root, depth),
+                parts: MergedParts {
+                    count: self.ifs.maps.len().pow(depth),
+                    branching: self.ifs.maps.len(),
+                },
+            }
+        } else {
+            let instances = self
+                .ifs
+                .expand(root, depth)
+                .into_iter()
+                .map(|instance| Transform::from_matrix(Mat4::from(instance)))
+                .collect();
+            FractalGeometry::Instanced {
+                seed,
+                instances,
+                branching: self.ifs.maps.len(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `n` maps shrinking by `1 / n` and shifting along x by their index, so a copy's offset
+    /// written in base `n` spells out the maps that reached it
+    fn digits(n: usize) -> Ifs {
+        let maps = (0..n)
+            .map(|i| {
+                Affine3A::from_scale_rotation_translation(
+                    Vec3::splat(1.0 / n as f32),
+                    Quat::IDENTITY,
+                    Vec3::X * i as f32,
+                )
+            })
+            .collect();
+        Ifs::new(maps)
+    }
+
+    #[test]
+    fn expansion_is_ordered_by_address() {
+        let ifs = digits(3);
+        let instances = ifs.expand(Affine3A::IDENTITY, 3);
+        assert_eq!(instances.len(), 27);
+        for (index, instance) in instances.iter().enumerate() {
+            let address = instance.translation.x * 9.0;
+            assert!((address - index as f32).abs() < 1e-4, "{index}: {address}");
+        }
+        assert_eq!(instances, ifs.expand(Affine3A::IDENTITY, 3));
+
+        // The root applies after every map
+        let root = Affine3A::from_translation(Vec3::Y);
+        assert!(ifs
+            .expand(root, 2)
+            .iter()
+            .all(|instance| instance.translation.y == 1.0));
+        assert_eq!(ifs.expand(root, 0), [root]);
+    }
+
+    #[test]
+    fn depth_is_clamped_to_the_instance_budget() {
+        let menger = Ifs::menger();
+        assert_eq!(menger.clamp_depth(3, 8000), 3);
+        assert_eq!(menger.clamp_depth(4, 8000), 3);
+        assert_eq!(menger.clamp_depth(4, 7999), 2);
+        assert_eq!(menger.clamp_depth(2, 10), 0);
+        // Counts too large for `usize` still come back down
+        assert_eq!(menger.clamp_depth(100, MAX_INSTANCES), 4);
+        assert_eq!(Ifs::new(vec![Affine3A::IDENTITY]).clamp_depth(100, 1), 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "one weight per map")]
+    fn weights_must_match_the_maps() {
+        digits(2).with_weights(vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not all zero")]
+    fn weights_must_not_all_be_zero() {
+        digits(2).with_weights(vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn similarities_exclude_shear_stretch_and_mirrors() {
+        assert!(Ifs::menger().is_similarity());
+        assert!(Ifs::twisted_menger(0.3).is_similarity());
+        assert!(Ifs::sierpinski_tetrahedron().is_similarity());
+        assert!(!Ifs::sheared_rep_tile(0.5).is_similarity());
+        assert!(!Ifs::spiral_tree().is_similarity());
+
+        // A mirror image keeps lengths but has a negative determinant
+        let half = Affine3A::from_scale(Vec3::splat(0.5));
+        let mirror = Affine3A::from_scale(Vec3::new(-0.5, 0.5, 0.5));
+        assert!(Ifs::new(vec![half]).is_similarity());
+        assert!(!Ifs::new(vec![half, mirror]).is_similarity());
+    }
+}
@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+
+use crate::distance_estimators::{
+    DistanceEstimator, Mandelbox, Mandelbulb, MengerSponge, QuaternionJulia, SierpinskiTetrahedron,
+};
+use crate::dual_contouring::DualContouring;
+use crate::generator::{FractalGenerator, FractalGeometry, ParamSpec, ParamValues};
+use crate::marching_cubes::MarchingCubes;
+use crate::mesh_builder::ChunkedMeshBuilder;
+
+/// Finest uniform grid marching cubes is given; every corner of it is sampled up front, so
+/// only the adaptive mesher may go finer
+const MAX_UNIFORM_RESOLUTION: i64 = 384;
+
+/// Grid and scale parameters every implicit fractal shares
+fn implicit_params(extent: f32, adaptive: bool) -> Vec<ParamSpec> {
+    vec![
+        // Cells along each side of the marching cubes grid
+        ParamSpec::int("resolution", 8..=MAX_UNIFORM_RESOLUTION, 128),
+        // Levels of the `adaptive` octree, which is up to two to this many cells across
+        ParamSpec::int("octree_depth", 3..=9, 7),
+        ParamSpec::float("size", 0.1..=100.0, 3.0),
+        // Half the width of the cube sampled around the origin, in the fractal's own units
+        ParamSpec::float("extent", 0.1..=20.0, extent),
+        // Dual contouring on an octree rather than marching cubes on a uniform grid
+        ParamSpec::bool("adaptive", adaptive),
+        // How far merged octree cells may stray from the surface, relative to `size`
+        ParamSpec::float("tolerance", 0.0..=0.1, 1e-4),
+    ]
+}
+
+/// Polygonizes `estimator` over its sampled cube, scaled so that cube is `size` across
+fn implicit_mesh(estimator: &impl DistanceEstimator, params: &ParamValues) -> FractalGeometry {
+    let size = params.float("size");
+    let half_size = size / 2.0;
+    let scale = params.float("extent") / half_size;
+    let scaled = |p: Vec3| estimator.distance(p * scale) / scale;
+    let (min, max) = (Vec3::splat(-half_size), Vec3::splat(half_size));
+
+    let mesh = if params.bool("adaptive") {
+        DualContouring::new(min, max, params.int("octree_depth") as u32)
+            .with_tolerance(params.float("tolerance") * size)
+            .polygonize(&scaled)
+    } else {
+        MarchingCubes::new(min, max, params.int("resolution") as u32).polygonize(&scaled)
+    };
+    let mut builder = ChunkedMeshBuilder::default();
+    builder.push_split(&mesh);
+    FractalGeometry::Merged(builder.build())
+}
+
+/// The Mandelbulb, meshed with marching cubes
+pub struct MandelbulbFractal;
+
+impl FractalGenerator for MandelbulbFractal {
+    fn name(&self) -> &'static str {
+        "mandelbulb"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        let mut params = vec![
+            ParamSpec::float("power", 2.0..=16.0, 8.0),
+            ParamSpec::int("iterations", 1..=30, 12),
+        ];
+        // Lower powers bulge out further, so this covers the power-2 bulb too
+        params.extend(implicit_params(1.6, false));
+        params
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let estimator = Mandelbulb {
+            power: params.float("power"),
+            iterations: params.int("iterations") as u32,
+            ..default()
+        };
+        implicit_mesh(&estimator, params)
+    }
+}
+
+/// The Mandelbox, meshed with marching cubes
+pub struct MandelboxFractal;
+
+impl FractalGenerator for MandelboxFractal {
+    fn name(&self) -> &'static str {
+        "mandelbox"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        let mut params = vec![
+            ParamSpec::float("scale", -3.0..=3.0, 2.0),
+            ParamSpec::float("min_radius", 0.05..=1.0, 0.5),
+            ParamSpec::int("iterations", 1..=30, 15),
+        ];
+        // A scale 2 box stays within 6 of the origin
+        params.extend(implicit_params(6.0, false));
+        params
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let estimator = Mandelbox {
+            scale: params.float("scale"),
+            min_radius: params.float("min_radius"),
+            iterations: params.int("iterations") as u32,
+            ..default()
+        };
+        implicit_mesh(&estimator, params)
+    }
+}
+
+/// A slice through a quaternion Julia set, meshed with marching cubes
+pub struct JuliaFractal;
+
+impl FractalGenerator for JuliaFractal {
+    fn name(&self) -> &'static str {
+        "quaternion_julia"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        let c = QuaternionJulia::default().c;
+        let mut params = vec![
+            ParamSpec::float("c_x", -1.0..=1.0, c.x),
+            ParamSpec::float("c_y", -1.0..=1.0, c.y),
+            ParamSpec::float("c_z", -1.0..=1.0, c.z),
+            ParamSpec::float("c_w", -1.0..=1.0, c.w),
+            ParamSpec::int("iterations", 1..=30, 12),
+        ];
+        params.extend(implicit_params(1.5, false));
+        params
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let estimator = QuaternionJulia {
+            c: Vec4::new(
+                params.float("c_x"),
+                params.float("c_y"),
+                params.float("c_z"),
+                params.float("c_w"),
+            ),
+            iterations: params.int("iterations") as u32,
+            ..default()
+        };
+        implicit_mesh(&estimator, params)
+    }
+}
+
+/// The Menger sponge from its distance estimator, meshed adaptively to keep its square edges
+pub struct MengerSdf;
+
+impl FractalGenerator for MengerSdf {
+    fn name(&self) -> &'static str {
+        "menger_sdf"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        let mut params = vec![ParamSpec::int("iterations", 0..=6, 3)];
+        params.extend(implicit_params(1.1, true));
+        params
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let estimator = MengerSponge {
+            iterations: params.int("iterations") as u32,
+            ..default()
+        };
+        implicit_mesh(&estimator, params)
+    }
+}
+
+/// The Sierpinski tetrahedron from its folding distance estimator, meshed adaptively
+pub struct SierpinskiSdf;
+
+impl FractalGenerator for SierpinskiSdf {
+    fn name(&self) -> &'static str {
+        "sierpinski_sdf"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        let mut params = vec![ParamSpec::int("iterations", 0..=12, 5)];
+        params.extend(implicit_params(1.1, true));
+        params
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let estimator = SierpinskiTetrahedron {
+            iterations: params.int("iterations") as u32,
+            ..default()
+        };
+        implicit_mesh(&estimator, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::ParamValue;
+
+    #[test]
+    fn uniform_grids_are_declared_within_their_cap() {
+        let mut values = ParamValues::default();
+        values
+            .set("resolution", ParamValue::Int(500))
+            .set("octree_depth", ParamValue::Int(9));
+        let resolved = values.resolve(&MandelbulbFractal.params());
+        assert_eq!(resolved.int("resolution"), MAX_UNIFORM_RESOLUTION);
+        // Finer meshes go through the octree, which has its own parameter
+        assert_eq!(resolved.int("octree_depth"), 9);
+    }
+}
@@ -0,0 +1,130 @@
+use bevy::prelude::*;
+
+use crate::generator::{
+    expand_instances, FractalGenerator, FractalGeometry, ParamSpec, ParamValues, SeedShape,
+};
+
+/// Edge of the corner cubes relative to their parent; the edge cubes use its square, which is
+/// exactly the gap left between two corner cubes
+pub fn corner_ratio() -> f32 {
+    2.0f32.sqrt() - 1.0
+}
+
+/// The 8 corner and 12 edge children of a unit cube centred on the origin
+pub fn jerusalem_children() -> Vec<Transform> {
+    let big = corner_ratio();
+    let small = big * big;
+    let mut children = Vec::with_capacity(20);
+
+    for i in 0..8 {
+        let sign = Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32) * 2.0 - 1.0;
+        children.push(
+            Transform::from_translation(sign * (1.0 - big) / 2.0).with_scale(Vec3::splat(big)),
+        );
+    }
+
+    // Each edge cube sits midway along an edge of the parent, flush with both faces it touches
+    for axis in 0..3 {
+        for i in 0..4 {
+            let mut offset = Vec3::ZERO;
+            offset[(axis + 1) % 3] = if i & 1 == 0 { -1.0 } else { 1.0 };
+            offset[(axis + 2) % 3] = if i & 2 == 0 { -1.0 } else { 1.0 };
+            children.push(
+                Transform::from_translation(offset * (1.0 - small) / 2.0)
+                    .with_scale(Vec3::splat(small)),
+            );
+        }
+    }
+    children
+}
+
+/// Transforms of the unit cubes making up a Jerusalem cube of edge length `size`
+pub fn jerusalem_cubes(depth: u32, size: f32) -> Vec<Transform> {
+    expand_instances(
+        Transform::from_scale(Vec3::splat(size)),
+        &jerusalem_children(),
+        depth,
+    )
+}
+
+/// A cube replaced by large corner cubes joined by smaller edge cubes, recursively
+pub struct JerusalemCube;
+
+impl FractalGenerator for JerusalemCube {
+    fn name(&self) -> &'static str {
+        "jerusalem_cube"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::int("depth", 0..=4, 2),
+            ParamSpec::float("size", 0.1..=100.0, 3.0),
+            ParamSpec::bool("merged", true),
+            ParamSpec::seed("seed", SeedShape::Cube),
+        ]
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let geometry = FractalGeometry::Instanced {
+            seed: params.seed("seed").mesh(),
+            instances: jerusalem_cubes(params.int("depth") as u32, params.float("size")),
+            branching: jerusalem_children().len(),
+        };
+        if params.bool("merged") {
+            geometry.merged()
+        } else {
+            geometry
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    /// Corners of the box a child of the unit cube covers
+    fn bounds(child: &Transform) -> (Vec3, Vec3) {
+        let half = child.scale / 2.0;
+        (child.translation - half, child.translation + half)
+    }
+
+    #[test]
+    fn children_are_eight_corners_and_twelve_edges() {
+        let children = jerusalem_children();
+        assert_eq!(children.len(), 20);
+        let big = corner_ratio();
+        assert!(children[..8]
+            .iter()
+            .all(|child| child.scale == Vec3::splat(big)));
+        assert!(children[8..]
+            .iter()
+            .all(|child| child.scale == Vec3::splat(big * big)));
+        // Two corner cubes and the edge cube between them span the parent's edge exactly
+        assert!((2.0 * big + big * big - 1.0).abs() <= EPSILON);
+
+        for depth in 0..=3 {
+            assert_eq!(jerusalem_cubes(depth, 1.0).len(), 20usize.pow(depth));
+        }
+    }
+
+    #[test]
+    fn children_fill_the_parent_without_overlapping() {
+        let children = jerusalem_children();
+        for (i, a) in children.iter().enumerate() {
+            let (min, max) = bounds(a);
+            assert!(min.cmpge(Vec3::splat(-0.5 - EPSILON)).all());
+            assert!(max.cmple(Vec3::splat(0.5 + EPSILON)).all());
+
+            for b in &children[i + 1..] {
+                let (other_min, other_max) = bounds(b);
+                let overlap = max.min(other_max) - min.max(other_min);
+                assert!(
+                    overlap.min_element() <= EPSILON,
+                    "{a:?} and {b:?} overlap by {overlap}"
+                );
+            }
+        }
+    }
+}
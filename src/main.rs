@@ -1,90 +1,165 @@
 use bevy::prelude::*;
-use bevy::render::mesh::{Indices, PrimitiveTopology};
-use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 use bevy_rapier3d::prelude::*;
-use core::f32::consts::PI;
 
+mod chaos;
+mod cli;
+mod collider;
+mod distance_estimators;
+mod dual_contouring;
+mod export;
 mod flycam;
-use crate::flycam::{FlyCam, NoCameraPlayerPlugin};
+mod fractal;
+mod generator;
+mod gltf;
+mod ifs;
+mod implicit;
+mod jerusalem;
+mod marching_cubes;
+mod menger;
+mod mesh_builder;
+mod nflake;
+mod obj;
+mod path_tracer;
+mod picking;
+mod ply;
+mod primitives;
+mod print_check;
+mod raymarch;
+mod rule_sponge;
+mod sierpinski;
+mod stl;
+mod vox;
+mod voxel;
+use crate::distance_estimators::Estimator;
+use crate::export::ExportPlugin;
+use crate::flycam::{FlyCam, MovementSettings, NoCameraPlayerPlugin};
+use crate::fractal::{FractalBundle, FractalParams, FractalPlugin};
+use crate::generator::{ParamValue, SeedShape};
+use crate::path_tracer::PathTracerPlugin;
+use crate::picking::PickingPlugin;
+use crate::raymarch::{RaymarchPlugin, RaymarchedFractal, RaymarchedFractalBundle};
+use crate::rule_sponge::SpongePattern;
 
 fn main() {
+    // Commands such as `export` run headless; with none the interactive viewer opens
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::parse(&args) {
+        Ok(Some(command)) => return cli::run(command),
+        Ok(None) => {}
+        Err(message) => {
+            eprintln!("error: {message}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    }
+
     App::new()
-        .insert_resource(NeedsUpdate(true))
         .add_systems(Startup, setup)
-        .add_systems(Update, update)
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugins(DefaultPlugins)
+        // Keep the camera from flying through walls of the fractals that have colliders
+        .insert_resource(MovementSettings {
+            collision_radius: Some(0.02),
+            ..default()
+        })
         .add_plugins(NoCameraPlayerPlugin)
+        .add_plugins(FractalPlugin)
+        .add_plugins(RaymarchPlugin)
+        .add_plugins(PathTracerPlugin)
+        .add_plugins(ExportPlugin)
+        .add_plugins(PickingPlugin)
         .run();
 }
 
-/// A marker component for our shapes so we can query them separately from other entities
-#[derive(Component)]
-struct Shape;
-
-#[derive(Resource)]
-struct NeedsUpdate(bool);
-
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut images: ResMut<Assets<Image>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    // Create a debug material, or use your own
-    //let debug_material = materials.add(StandardMaterial {
-    //    base_color_texture: Some(images.add(uv_debug_texture())),
-    //    ..default()
-    //});
-
-    //// Initialize fractal shape (e.g., a cube for demonstration)
-    //let fractal_shape = meshes.add(shape::Cube { size: 1.0 }.into());
-
-    //// Spawn fractal entity
-    //commands.spawn((
-    //    PbrBundle {
-    //        mesh: fractal_shape,
-    //        material: materials.add(Color::GREEN.into()),
-    //        transform: Transform::from_xyz(0.0, 0.0, 0.0)
-    //            .with_rotation(Quat::from_rotation_x(-PI / 4.)),
-    //        visibility: Visibility::Hidden,
-    //        ..default()
-    //    },
-    //    Shape, // Custom marker component
-    //));
-    // Create a base tetrahedron mesh
-    // Create a new mesh
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-
-    // Base vertices of a tetrahedron
-    let vertices = [
-        [0.0, 0.0, 0.0],                                     // vertex 0
-        [1.0, 0.0, 0.0],                                     // vertex 1
-        [0.5, 0.0, 3.0f32.sqrt() / 2.0],                     // vertex 2
-        [0.5, (6.0f32).sqrt() / 3.0, (3.0f32).sqrt() / 6.0], // vertex 3
-    ];
-
-    let indices = [
-        0, 1, 2, // triangle 0
-        0, 2, 3, // triangle 1
-        0, 3, 1, // triangle 2
-        1, 3, 2, // triangle 3
-    ];
-
-    // Create a mesh from the vertices and triangle indices.
-    for vertex in &vertices {
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![*vertex]);
+    // Show the seed shapes fractals can be built from in a row behind them
+    let seed_material = materials.add(Color::GREEN.into());
+    for (i, seed) in SeedShape::ALL.into_iter().enumerate() {
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(seed.mesh()),
+            material: seed_material.clone(),
+            transform: Transform::from_xyz(i as f32 * 2.0 - 5.0, 0.5, -6.0),
+            ..Default::default()
+        });
     }
 
-    mesh.set_indices(Some(Indices::U32(indices.to_vec())));
-
-    // Add the custom mesh to the resource and spawn it.
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(mesh),
-        material: materials.add(Color::GREEN.into()),
-        transform: Transform::from_xyz(0.0, 0.0, 0.0),
-        ..Default::default()
+    // Spawn the fractals; editing their `FractalParams` later regenerates them
+    let fractal_material = materials.add(Color::rgb(0.8, 0.7, 0.6).into());
+    let mut menger = FractalParams::new("menger", 4, fractal_material.clone());
+    menger.colliders = Some(default());
+    commands.spawn(FractalBundle::new(
+        menger,
+        Transform::from_xyz(0.0, 1.5, 0.0),
+    ));
+    commands.spawn(FractalBundle::new(
+        FractalParams::new("jerusalem_cube", 3, fractal_material.clone()),
+        Transform::from_xyz(5.0, 1.5, 0.0),
+    ));
+    let mut snowflake = FractalParams::new("rule_sponge", 3, fractal_material.clone());
+    if let Some(pattern) = SpongePattern::preset("mosely_snowflake") {
+        snowflake.extra.set("pattern", ParamValue::Pattern(pattern));
+    }
+    snowflake.colliders = Some(default());
+    commands.spawn(FractalBundle::new(
+        snowflake,
+        Transform::from_xyz(0.0, 1.5, 6.0),
+    ));
+    // Points carry their own colours and no normals, so they are drawn unlit
+    let point_material = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        unlit: true,
+        ..default()
     });
+    let mut chaos = FractalParams::new("sierpinski_ifs", 0, point_material);
+    chaos.extra.set("chaos_points", ParamValue::Int(200_000));
+    commands.spawn(FractalBundle::new(
+        chaos,
+        Transform::from_xyz(-6.0, 0.0, 6.0),
+    ));
+    // Kept as separate tetrahedra so each one collides as its own hull
+    let mut sierpinski = FractalParams::new("sierpinski_tetrahedron", 4, fractal_material.clone());
+    sierpinski.extra.set("merged", ParamValue::Bool(false));
+    sierpinski.colliders = Some(default());
+    commands.spawn(FractalBundle::new(
+        sierpinski,
+        Transform::from_xyz(-6.0, 0.0, 0.0),
+    ));
+    // Implicit fractals have no recursion depth; they are meshed from their distance estimator
+    commands.spawn(FractalBundle::new(
+        FractalParams::new("mandelbulb", 0, fractal_material.clone()),
+        Transform::from_xyz(6.0, 1.5, 6.0),
+    ));
+    commands.spawn(FractalBundle::new(
+        FractalParams::new("menger_sdf", 0, fractal_material),
+        Transform::from_xyz(10.0, 1.5, 0.0),
+    ));
+    // The same Mandelbulb raymarched on the GPU, scaled to match the meshed one beside it
+    commands.spawn(RaymarchedFractalBundle::new(
+        RaymarchedFractal::new(Estimator::Mandelbulb(default())),
+        Transform::from_xyz(10.0, 1.5, 6.0).with_scale(Vec3::splat(1.5 / 1.6)),
+    ));
+    commands.spawn(RaymarchedFractalBundle::new(
+        RaymarchedFractal::new(Estimator::Mandelbox(default())),
+        Transform::from_xyz(-12.0, 1.5, 0.0).with_scale(Vec3::splat(0.3)),
+    ));
+    // The rest of the estimators in a row past the seed shapes
+    for (i, estimator) in [
+        Estimator::QuaternionJulia(default()),
+        Estimator::MengerSponge(default()),
+        Estimator::SierpinskiTetrahedron(default()),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let scale = 1.5 / estimator.bounds();
+        commands.spawn(RaymarchedFractalBundle::new(
+            RaymarchedFractal::new(estimator),
+            Transform::from_xyz(i as f32 * 4.0 - 4.0, 1.5, -12.0).with_scale(Vec3::splat(scale)),
+        ));
+    }
 
     // Initialize a light source
     commands.spawn(PointLightBundle {
@@ -108,178 +183,3 @@ fn setup(
         FlyCam,
     ));
 }
-// Recursively create the Sierpinski tetrahedrons
-fn create_tetrahedron(
-    commands: &mut Commands,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    position: Vec3,
-    scale: f32,
-    iteration: u32,
-    tetrahedron_mesh: &Handle<Mesh>,
-) {
-    if iteration == 0 {
-        return;
-    }
-
-    // scale down the tetrahedron for this iteration
-    let new_scale = scale / 2.0;
-
-    // For a Sierpinski tetrahedron, we place smaller tetrahedrons at the corners of the current one
-    let offsets = [
-        Vec3::new(1.0, 0.0, -1.0 / (2.0_f32).sqrt()), // front right
-        Vec3::new(-1.0, 0.0, -1.0 / (2.0_f32).sqrt()), // front left
-        Vec3::new(0.0, 0.0, 1.0 / (2.0_f32).sqrt()),  // back middle
-        Vec3::new(0.0, (2.0_f32).sqrt(), 0.0),        // top
-    ];
-
-    for &offset in offsets.iter() {
-        let new_position = position + offset * new_scale * 2.0;
-        commands.spawn(PbrBundle {
-            mesh: tetrahedron_mesh.clone(),
-            material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-            transform: Transform::from_scale(Vec3::splat(new_scale)) // Scale down
-                .with_translation(new_position), // Move to the correct position
-            ..Default::default()
-        });
-
-        // Recursive call to create the smaller tetrahedrons
-        create_tetrahedron(
-            commands,
-            materials,
-            new_position,
-            new_scale,
-            iteration - 1,
-            tetrahedron_mesh,
-        );
-    }
-}
-const MAX_ITERATIONS: u32 = 4; // Adjust this for the desired depth.
-const SCALING_FACTOR: f32 = 1.0 / 3.0; // Menger Sponge is divided into thirds.
-
-fn generate_fractal(
-    position: Vec3, // This is the center of the imaginary larger cube
-    scale: f32,     // This is the scale of the individual cubes
-    iteration: u32,
-    commands: &mut Commands,
-    mesh_handle: &Handle<Mesh>,
-    material_handle: &Handle<StandardMaterial>,
-) {
-    if iteration == 0 {
-        // At iteration 0, do nothing. We start building from iteration 1.
-        return;
-    }
-
-    let offset = scale; // The distance from the center to place the smaller cubes
-
-    for i in 0..3 {
-        for j in 0..3 {
-            for k in 0..3 {
-                // Skip the center cube and the centers of each face
-                if (i == 1 && j == 1) || (i == 1 && k == 1) || (j == 1 && k == 1) {
-                    continue;
-                }
-
-                let new_position = Vec3::new(
-                    position.x + (i as f32 - 1.0) * offset,
-                    position.y + (j as f32 - 1.0) * offset,
-                    position.z + (k as f32 - 1.0) * offset,
-                );
-
-                // Create a single base cube with Menger Sponge texture at the new position.
-                commands.spawn(PbrBundle {
-                    mesh: mesh_handle.clone(),
-                    material: material_handle.clone(),
-                    transform: Transform {
-                        translation: new_position,
-                        scale: Vec3::new(scale, scale, scale),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                });
-
-                if iteration > 1 {
-                    // Recursive call to place even larger structures, if needed
-                    generate_fractal(
-                        new_position,
-                        scale,
-                        iteration - 1,
-                        commands,
-                        mesh_handle,
-                        material_handle,
-                    );
-                }
-            }
-        }
-    }
-}
-
-fn update(
-    mut commands: Commands,
-    mut query: Query<(&Handle<Mesh>, &Handle<StandardMaterial>, &Transform, &Shape)>,
-    mut needs_update: ResMut<NeedsUpdate>,
-) {
-    if needs_update.0 {
-        for (mesh_handle, material_handle, transform, _) in query.iter_mut() {
-            // Start generating the fractal from an initial position, scale, and iteration
-            let initial_position = Vec3::new(0.0, 0.0, 0.0);
-            let initial_scale = 1.0;
-            generate_fractal(
-                initial_position,
-                initial_scale,
-                4,
-                &mut commands,
-                mesh_handle,
-                material_handle,
-            );
-        }
-        needs_update.0 = false;
-    }
-}
-
-/// Creates a colorful test pattern
-fn uv_debug_texture() -> Image {
-    const TEXTURE_SIZE: usize = 8;
-
-    let mut palette: [u8; 32] = [
-        255, 102, 159, 255, 255, 159, 102, 255, 236, 255, 102, 255, 121, 255, 102, 255, 102, 255,
-        198, 255, 102, 198, 255, 255, 121, 102, 255, 255, 236, 102, 255, 255,
-    ];
-
-    let mut texture_data = [0; TEXTURE_SIZE * TEXTURE_SIZE * 4];
-    for y in 0..TEXTURE_SIZE {
-        let offset = TEXTURE_SIZE * y * 4;
-        texture_data[offset..(offset + TEXTURE_SIZE * 4)].copy_from_slice(&palette);
-        palette.rotate_right(4);
-    }
-
-    Image::new_fill(
-        Extent3d {
-            width: TEXTURE_SIZE as u32,
-            height: TEXTURE_SIZE as u32,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &texture_data,
-        TextureFormat::Rgba8UnormSrgb,
-    )
-}
-
-fn create_shape(
-    position: Vec3,
-    scale: f32,
-    commands: &mut Commands,
-    fractal_mesh: &Handle<Mesh>,
-    debug_material: &Handle<StandardMaterial>,
-) {
-    commands.spawn((
-        PbrBundle {
-            mesh: fractal_mesh.clone(),
-            material: debug_material.clone(),
-            transform: Transform::from_scale(Vec3::splat(scale))
-                .with_translation(position)
-                .with_rotation(Quat::from_rotation_x(-PI)),
-            ..default()
-        },
-        Shape, // Custom marker component
-    ));
-}
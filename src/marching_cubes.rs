@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+use crate::distance_estimators::DistanceEstimator;
+
+/// Offset of cube corner `i` from the cell's lowest corner
+fn corner_offset(corner: usize) -> UVec3 {
+    UVec3::new(
+        corner as u32 & 1,
+        corner as u32 >> 1 & 1,
+        corner as u32 >> 2 & 1,
+    )
+}
+
+/// The 12 cube edges as corner pairs, each running along the positive direction of its axis
+fn edges() -> [(usize, usize); 12] {
+    let mut edges = [(0, 0); 12];
+    let mut next = 0;
+    for axis in 0..3 {
+        for corner in (0..8).filter(|c| c & (1 << axis) == 0) {
+            edges[next] = (corner, corner | 1 << axis);
+            next += 1;
+        }
+    }
+    edges
+}
+
+fn edge_between(a: usize, b: usize) -> usize {
+    let (a, b) = (a.min(b), a.max(b));
+    edges()
+        .iter()
+        .position(|&edge| edge == (a, b))
+        .expect("corners share an edge")
+}
+
+/// The corners of each cube face, counter-clockwise when seen from outside the cube
+fn faces() -> [[usize; 4]; 6] {
+    let mut faces = [[0; 4]; 6];
+    for axis in 0..3 {
+        for side in 0..2 {
+            let corner =
+                |a: usize, b: usize| side << axis | a << ((axis + 1) % 3) | b << ((axis + 2) % 3);
+            let mut ring = [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)];
+            if side == 0 {
+                ring.reverse();
+            }
+            faces[axis * 2 + side] = ring;
+        }
+    }
+    faces
+}
+
+/// For every combination of inside corners, the closed loops of edges the surface crosses.
+///
+/// Rather than a hand-written table this walks the faces of the cube: on each face the surface
+/// enters through one edge and leaves through another, and where a face has inside corners on
+/// opposite diagonals they are always cut off separately. Both cells sharing a face make the
+/// same choice, so the surface never opens up between them.
+fn case_table() -> &'static [Vec<Vec<usize>>; 256] {
+    static TABLE: OnceLock<[Vec<Vec<usize>>; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|case| {
+            let inside = |corner: usize| case & (1 << corner) != 0;
+            let mut next_edge = HashMap::new();
+            for ring in faces() {
+                let crossings: Vec<(usize, bool)> = (0..4)
+                    .filter_map(|j| {
+                        let (a, b) = (ring[j], ring[(j + 1) % 4]);
+                        (inside(a) != inside(b)).then(|| (edge_between(a, b), inside(b)))
+                    })
+                    .collect();
+                // Pair every crossing into the inside with the next one back out
+                for (k, &(edge, entering)) in crossings.iter().enumerate() {
+                    if entering {
+                        let (exit, _) = crossings[(k + 1) % crossings.len()];
+                        next_edge.insert(edge, exit);
+                    }
+                }
+            }
+
+            let mut loops = Vec::new();
+            while let Some(&start) = next_edge.keys().min() {
+                let mut polygon = vec![start];
+                let mut edge = next_edge.remove(&start).unwrap();
+                while edge != start {
+                    polygon.push(edge);
+                    edge = next_edge.remove(&edge).unwrap();
+                }
+                loops.push(polygon);
+            }
+            loops
+        })
+    })
+}
+
+/// Polygonizes the zero level set of a distance estimator or scalar field over a box
+#[derive(Clone, Copy, Debug)]
+pub struct MarchingCubes {
+    pub min: Vec3,
+    pub max: Vec3,
+    /// Number of cells along each axis
+    pub resolution: UVec3,
+}
+
+impl MarchingCubes {
+    pub fn new(min: Vec3, max: Vec3, resolution: u32) -> Self {
+        Self {
+            min,
+            max,
+            resolution: UVec3::splat(resolution.max(1)),
+        }
+    }
+
+    fn sample_point(&self, sample: UVec3) -> Vec3 {
+        self.min + (self.max - self.min) * sample.as_vec3() / self.resolution.as_vec3()
+    }
+
+    /// Evaluates `field` at every grid corner, spreading the work over all cores
+    fn sample(&self, field: &dyn DistanceEstimator) -> Vec<f32> {
+        let samples = self.resolution + 1;
+        let slice = (samples.x * samples.y) as usize;
+        let mut values = vec![0.0; slice * samples.z as usize];
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let slices_per_thread = (samples.z as usize).div_ceil(threads);
+
+        std::thread::scope(|scope| {
+            for (chunk_index, chunk) in values.chunks_mut(slice * slices_per_thread).enumerate() {
+                scope.spawn(move || {
+                    for (i, value) in chunk.iter_mut().enumerate() {
+                        let i = chunk_index * slice * slices_per_thread + i;
+                        let sample = UVec3::new(
+                            (i % samples.x as usize) as u32,
+                            (i / samples.x as usize % samples.y as usize) as u32,
+                            (i / slice) as u32,
+                        );
+                        *value = field.distance(self.sample_point(sample));
+                    }
+                });
+            }
+        });
+        values
+    }
+
+    /// Builds an indexed mesh of the surface where `field` crosses zero, with vertices shared
+    /// between neighbouring cells and normals taken from the field's gradient
+    pub fn polygonize(&self, field: &dyn DistanceEstimator) -> Mesh {
+        let values = self.sample(field);
+        let samples = self.resolution + 1;
+        let value_at = |sample: UVec3| {
+            values[(sample.x + samples.x * (sample.y + samples.y * sample.z)) as usize]
+        };
+
+        let edges = edges();
+        let table = case_table();
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut vertex_of_edge: HashMap<(UVec3, usize), u32> = HashMap::new();
+        let mut indices = Vec::new();
+
+        for z in 0..self.resolution.z {
+            for y in 0..self.resolution.y {
+                for x in 0..self.resolution.x {
+                    let cell = UVec3::new(x, y, z);
+                    let case = (0..8).fold(0, |case, corner| {
+                        let inside = value_at(cell + corner_offset(corner)) < 0.0;
+                        case | (inside as usize) << corner
+                    });
+
+                    for polygon in &table[case] {
+                        let vertices: Vec<u32> = polygon
+                            .iter()
+                            .map(|&edge| {
+                                let (a, b) = edges[edge];
+                                let (a, b) = (cell + corner_offset(a), cell + corner_offset(b));
+                                // Edges are listed four per axis, so this names the edge
+                                // the same way from every cell that touches it
+                                let axis = edge / 4;
+                                *vertex_of_edge.entry((a, axis)).or_insert_with(|| {
+                                    let (va, vb) = (value_at(a), value_at(b));
+                                    let t = va / (va - vb);
+                                    let position =
+                                        self.sample_point(a).lerp(self.sample_point(b), t);
+                                    positions.push(position.to_array());
+                                    positions.len() as u32 - 1
+                                })
+                            })
+                            .collect();
+
+                        // Crossing loops run counter-clockwise seen from outside the surface
+                        for k in 1..vertices.len() - 1 {
+                            indices.extend_from_slice(&[vertices[0], vertices[k], vertices[k + 1]]);
+                        }
+                    }
+                }
+            }
+        }
+
+        let normals: Vec<[f32; 3]> = positions
+            .iter()
+            .map(|&p| field.normal(Vec3::from(p)).to_array())
+            .collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+    use core::f32::consts::PI;
+
+    fn sphere_mesh(resolution: u32) -> (Vec<Vec3>, Vec<u32>) {
+        let sphere = |p: Vec3| p.length() - 1.0;
+        let mesh =
+            MarchingCubes::new(Vec3::splat(-1.5), Vec3::splat(1.5), resolution).polygonize(&sphere);
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("missing positions");
+        };
+        let Some(Indices::U32(indices)) = mesh.indices() else {
+            panic!("missing indices");
+        };
+        (
+            positions.iter().map(|&p| Vec3::from(p)).collect(),
+            indices.clone(),
+        )
+    }
+
+    #[test]
+    fn sphere_is_watertight_and_consistently_wound() {
+        let (_, indices) = sphere_mesh(24);
+        let mut directed = HashMap::new();
+        for triangle in indices.chunks_exact(3) {
+            for k in 0..3 {
+                *directed
+                    .entry((triangle[k], triangle[(k + 1) % 3]))
+                    .or_insert(0) += 1;
+            }
+        }
+        // Every edge is used once in each direction, by exactly two triangles
+        for (&(a, b), &count) in &directed {
+            assert_eq!(count, 1);
+            assert_eq!(directed.get(&(b, a)), Some(&1), "open edge {a}-{b}");
+        }
+    }
+
+    #[test]
+    fn sphere_has_expected_area_and_volume() {
+        let (positions, indices) = sphere_mesh(48);
+        let (mut area, mut volume) = (0.0, 0.0);
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| positions[triangle[k] as usize]);
+            area += (b - a).cross(c - a).length() / 2.0;
+            volume += a.dot(b.cross(c)) / 6.0;
+        }
+        assert!((area - 4.0 * PI).abs() / (4.0 * PI) < 0.02, "area {area}");
+        // A positive volume means the triangles face outwards
+        assert!(
+            (volume - 4.0 / 3.0 * PI).abs() / (4.0 / 3.0 * PI) < 0.02,
+            "volume {volume}"
+        );
+    }
+
+    #[test]
+    fn vertices_lie_on_the_surface() {
+        let (positions, _) = sphere_mesh(32);
+        assert!(positions.iter().all(|p| (p.length() - 1.0).abs() < 0.01));
+    }
+}
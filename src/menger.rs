@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+
+use crate::generator::{FractalGenerator, FractalGeometry, ParamSpec, ParamValues, SeedShape};
+use crate::voxel::{mesh_voxels, subdivision_parts};
+
+/// Whether the cell at `(x, y, z)` of a `3^depth` grid survives the Menger removal rule
+pub fn is_solid(cell: UVec3, depth: u32) -> bool {
+    let mut cell = cell;
+    for _ in 0..depth {
+        let centred = cell.to_array().iter().filter(|&&c| c % 3 == 1).count();
+        // Removing the body centre and the face centres leaves every cube with at most one
+        // coordinate in the middle third
+        if centred >= 2 {
+            return false;
+        }
+        cell /= 3;
+    }
+    true
+}
+
+/// Builds a Menger sponge of edge length `size` as a handful of merged meshes, each face
+/// tagged with the sub-cube `menger_cubes` would make it from.
+///
+/// Faces shared by adjacent sub-cubes are dropped, which keeps depth 4–5 sponges within reach
+/// where one entity per sub-cube would not be.
+pub fn build_menger_sponge(depth: u32, size: f32) -> FractalGeometry {
+    let (parts, part) = subdivision_parts(3, depth, |cell| is_solid(cell, 1));
+    FractalGeometry::MergedInstances {
+        chunks: mesh_voxels(3u32.pow(depth), size, |cell| is_solid(cell, depth), part),
+        parts,
+    }
+}
+
+/// Transforms of the unit cubes making up a Menger sponge of edge length `size`
+pub fn menger_cubes(depth: u32, size: f32) -> Vec<Transform> {
+    let mut cubes = vec![Transform::from_scale(Vec3::splat(size))];
+    for _ in 0..depth {
+        cubes = cubes
+            .into_iter()
+            .flat_map(|parent| {
+                let scale = parent.scale / 3.0;
+                (0..27u32)
+                    .map(|i| UVec3::new(i % 3, i / 3 % 3, i / 9))
+                    .filter(|&cell| is_solid(cell, 1))
+                    .map(move |cell| {
+                        let offset = (cell.as_vec3() - 1.0) * scale;
+                        Transform::from_translation(parent.translation + offset).with_scale(scale)
+                    })
+            })
+            .collect();
+    }
+    cubes
+}
+
+/// The classic sponge: every cube keeps the 20 of its 27 thirds that touch an edge
+pub struct MengerSponge;
+
+impl FractalGenerator for MengerSponge {
+    fn name(&self) -> &'static str {
+        "menger"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::int("depth", 0..=5, 3),
+            ParamSpec::float("size", 0.1..=100.0, 3.0),
+            ParamSpec::bool("merged", true),
+            ParamSpec::seed("seed", SeedShape::Cube),
+        ]
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let depth = params.int("depth") as u32;
+        let size = params.float("size");
+        let seed = params.seed("seed");
+        if params.bool("merged") && seed == SeedShape::Cube {
+            return build_menger_sponge(depth, size);
+        }
+
+        let geometry = FractalGeometry::Instanced {
+            seed: seed.mesh(),
+            instances: menger_cubes(depth, size),
+            branching: 20,
+        };
+        if params.bool("merged") {
+            geometry.merged()
+        } else {
+            geometry
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::instance_path;
+    use crate::mesh_builder::{mesh_triangles, MAX_CHUNK_VERTICES};
+
+    fn chunks(depth: u32, size: f32) -> Vec<Mesh> {
+        match build_menger_sponge(depth, size) {
+            FractalGeometry::MergedInstances { chunks, .. } => chunks,
+            _ => panic!("expected a merged sponge"),
+        }
+    }
+
+    #[test]
+    fn only_exposed_faces_are_meshed() {
+        // Twenty cubes of six faces, less both sides of the 24 corner-to-edge contacts
+        let triangles: Vec<_> = chunks(1, 3.0).iter().flat_map(mesh_triangles).collect();
+        assert_eq!(triangles.len(), 2 * (20 * 6 - 2 * 24));
+
+        for [a, b, c] in triangles {
+            let normal = (b - a).cross(c - a).normalize();
+            let centre = (a + b + c) / 3.0;
+            let cell = |p: Vec3| (p + 1.5).floor().as_ivec3();
+            let solid = |cell: IVec3| {
+                cell.cmpge(IVec3::ZERO).all()
+                    && cell.cmplt(IVec3::splat(3)).all()
+                    && is_solid(cell.as_uvec3(), 1)
+            };
+            // Solid behind every face and empty in front of it
+            assert!(solid(cell(centre - normal * 0.1)), "{centre} {normal}");
+            assert!(!solid(cell(centre + normal * 0.1)), "{centre} {normal}");
+        }
+    }
+
+    #[test]
+    fn deep_sponges_are_chunked() {
+        let chunks = chunks(4, 3.0);
+        assert!(chunks.len() > 1);
+        // A cube started just under the limit is finished in the same chunk
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.count_vertices() < MAX_CHUNK_VERTICES + 24));
+    }
+
+    #[test]
+    fn cells_are_numbered_like_instances() {
+        let depth = 2;
+        let kept: Vec<UVec3> = (0..27u32)
+            .map(|i| UVec3::new(i % 3, i / 3 % 3, i / 9))
+            .filter(|&cell| is_solid(cell, 1))
+            .collect();
+        let (parts, part) = subdivision_parts(3, depth, |cell| is_solid(cell, 1));
+        assert_eq!(parts.count, 400);
+        assert_eq!(parts.branching, 20);
+
+        for (index, cube) in menger_cubes(depth, 9.0).iter().enumerate() {
+            let cell = (cube.translation + 4.5).floor().as_uvec3();
+            assert_eq!(part(cell) as usize, index);
+            // The path names the kept block taken at each level, from the outside in
+            let path: Vec<u32> = [cell / 3, cell % 3]
+                .iter()
+                .map(|block| kept.iter().position(|c| c == block).unwrap() as u32)
+                .collect();
+            assert_eq!(instance_path(index, parts.count, parts.branching), path);
+            assert_eq!(parts.path(part(cell)), path);
+        }
+    }
+}
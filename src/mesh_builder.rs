@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, MeshVertexAttribute, PrimitiveTopology, VertexAttributeValues};
+use bevy::render::render_resource::VertexFormat;
+
+/// Vertex budget for a single chunk before `ChunkedMeshBuilder` starts a new mesh
+pub const MAX_CHUNK_VERTICES: usize = 1 << 19;
+
+/// Which instance of the recursion a vertex of a merged mesh was made from, numbered as
+/// `instance_path` reads them
+pub const ATTRIBUTE_PART: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_FractalPart", 0x6a1f_3c52, VertexFormat::Uint32);
+
+/// Accumulates vertices and triangles before handing them over to a `Mesh`
+#[derive(Default, Clone)]
+pub struct MeshBuilder {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    /// `ATTRIBUTE_PART` of each vertex, kept only once every vertex has one
+    pub parts: Vec<u32>,
+}
+
+impl MeshBuilder {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Tags the vertices added since the last call as coming from instance `part`
+    pub fn finish_part(&mut self, part: u32) {
+        self.parts.resize(self.positions.len(), part);
+    }
+
+    /// Adds a flat triangle, corners given counter-clockwise when seen from the front
+    pub fn push_triangle(&mut self, corners: [Vec3; 3], normal: Vec3, uvs: [Vec2; 3]) {
+        let base = self.positions.len() as u32;
+        for (corner, uv) in corners.iter().zip(uvs.iter()) {
+            self.positions.push(corner.to_array());
+            self.normals.push(normal.to_array());
+            self.uvs.push(uv.to_array());
+        }
+        self.indices.extend_from_slice(&[base, base + 1, base + 2]);
+    }
+
+    /// Adds a flat quad, corners given counter-clockwise when seen from the front
+    pub fn push_quad(&mut self, corners: [Vec3; 4], normal: Vec3) {
+        let base = self.positions.len() as u32;
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        for (corner, uv) in corners.iter().zip(uvs) {
+            self.positions.push(corner.to_array());
+            self.normals.push(normal.to_array());
+            self.uvs.push(uv);
+        }
+        self.indices
+            .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    /// Appends a triangle-list `mesh` with `matrix` baked into its positions and normals
+    pub fn push_mesh(&mut self, mesh: &Mesh, matrix: &Affine3A) {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+        let normal_matrix = Mat3::from(matrix.matrix3).inverse().transpose();
+        let base = self.positions.len() as u32;
+
+        self.positions.extend(
+            positions
+                .iter()
+                .map(|&p| matrix.transform_point3(Vec3::from(p)).to_array()),
+        );
+        match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => {
+                self.normals.extend(normals.iter().map(|&n| {
+                    (normal_matrix * Vec3::from(n))
+                        .normalize_or_zero()
+                        .to_array()
+                }))
+            }
+            _ => self
+                .normals
+                .extend(std::iter::repeat_n([0.0, 0.0, 0.0], positions.len())),
+        }
+        match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => self.uvs.extend_from_slice(uvs),
+            _ => self
+                .uvs
+                .extend(std::iter::repeat_n([0.0, 0.0], positions.len())),
+        }
+        let indices: Vec<u32> = match mesh.indices() {
+            Some(indices) => indices.iter().map(|i| base + i as u32).collect(),
+            None => (base..base + positions.len() as u32).collect(),
+        };
+        for triangle in indices.chunks_exact(3) {
+            self.indices
+                .extend_from_slice(&oriented([triangle[0], triangle[1], triangle[2]], matrix));
+        }
+    }
+
+    pub fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        if self.parts.len() == mesh.count_vertices() {
+            mesh.insert_attribute(ATTRIBUTE_PART, self.parts);
+        }
+        mesh
+    }
+}
+
+/// The corners of every triangle in a triangle-list `mesh`, or nothing for other topologies
+pub fn mesh_triangles(mesh: &Mesh) -> Vec<[Vec3; 3]> {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return Vec::new();
+    };
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return Vec::new();
+    }
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(indices) => indices.iter().collect(),
+        None => (0..positions.len()).collect(),
+    };
+    indices
+        .chunks_exact(3)
+        .map(|corners| [corners[0], corners[1], corners[2]].map(|i| Vec3::from(positions[i])))
+        .collect()
+}
+
+/// The corners of a triangle in the order that keeps it facing the same way once `matrix` is
+/// applied. Mirroring turns triangles inside out unless their winding is flipped back.
+pub fn oriented<T>([a, b, c]: [T; 3], matrix: &Affine3A) -> [T; 3] {
+    if matrix.matrix3.determinant() < 0.0 {
+        [a, c, b]
+    } else {
+        [a, b, c]
+    }
+}
+
+/// Spreads geometry over several meshes so no single buffer grows without bound
+#[derive(Default)]
+pub struct ChunkedMeshBuilder {
+    chunks: Vec<MeshBuilder>,
+}
+
+impl ChunkedMeshBuilder {
+    /// The chunk new geometry should go into, starting a fresh one once the last is full
+    pub fn current(&mut self) -> &mut MeshBuilder {
+        let full = self
+            .chunks
+            .last()
+            .is_none_or(|chunk| chunk.vertex_count() >= MAX_CHUNK_VERTICES);
+        if full {
+            self.chunks.push(MeshBuilder::default());
+        }
+        self.chunks.last_mut().unwrap()
+    }
+
+    /// Spreads a triangle-list `mesh` over chunks, each holding only the vertices its own
+    /// triangles use. Triangles stay whole, so a chunk may run a couple of vertices over.
+    pub fn push_split(&mut self, mesh: &Mesh) {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+            _ => None,
+        };
+        let parts = match mesh.attribute(ATTRIBUTE_PART) {
+            Some(VertexAttributeValues::Uint32(parts)) => Some(parts),
+            _ => None,
+        };
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+
+        // Where each of the mesh's vertices went in the chunk being filled
+        let mut moved: HashMap<usize, u32> = HashMap::new();
+        let mut chunks = self.chunks.len();
+        for triangle in indices.chunks_exact(3) {
+            self.current();
+            if self.chunks.len() != chunks {
+                moved.clear();
+                chunks = self.chunks.len();
+            }
+            let chunk = self.chunks.last_mut().unwrap();
+            for &i in triangle {
+                let index = *moved.entry(i).or_insert_with(|| {
+                    chunk.positions.push(positions[i]);
+                    chunk
+                        .normals
+                        .push(normals.map_or([0.0, 0.0, 0.0], |normals| normals[i]));
+                    chunk.uvs.push([0.0, 0.0]);
+                    if let Some(parts) = parts {
+                        chunk.parts.push(parts[i]);
+                    }
+                    chunk.positions.len() as u32 - 1
+                });
+                chunk.indices.push(index);
+            }
+        }
+    }
+
+    pub fn build(self) -> Vec<Mesh> {
+        self.chunks
+            .into_iter()
+            .filter(|chunk| !chunk.is_empty())
+            .map(MeshBuilder::build)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_meshes_are_split_into_chunks() {
+        // A strip of triangles, each sharing an edge with the next
+        let vertices = MAX_CHUNK_VERTICES + 1000;
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let positions: Vec<[f32; 3]> = (0..vertices)
+            .map(|i| [i as f32, (i % 2) as f32, 0.0])
+            .collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        let indices: Vec<u32> = (0..vertices as u32 - 2)
+            .flat_map(|i| [i, i + 1, i + 2])
+            .collect();
+        mesh.set_indices(Some(Indices::U32(indices)));
+
+        let mut builder = ChunkedMeshBuilder::default();
+        builder.push_split(&mesh);
+        let chunks = builder.build();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.count_vertices() <= MAX_CHUNK_VERTICES + 2));
+        let triangles: Vec<[Vec3; 3]> = chunks.iter().flat_map(mesh_triangles).collect();
+        assert_eq!(triangles, mesh_triangles(&mesh));
+    }
+}
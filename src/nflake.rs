@@ -0,0 +1,178 @@
+use bevy::prelude::*;
+
+use crate::generator::{
+    expand_instances, FractalGenerator, FractalGeometry, ParamSpec, ParamValues, SeedShape,
+};
+use crate::primitives::Polyhedron;
+
+/// Contraction ratio at which the copies placed at `polyhedron`'s vertices just stay apart.
+///
+/// Each copy is the polyhedron shrunk towards one of its vertices. Two copies are kept apart
+/// by the plane perpendicular to the line between their vertices as long as their extents
+/// along that line do not overlap, so the tightest pair decides the ratio. Other planes could
+/// leave room for larger copies of some shapes, but for the Platonic solids this is where
+/// neighbouring copies touch.
+pub fn flake_ratio(polyhedron: &Polyhedron) -> f32 {
+    let vertices = &polyhedron.vertices;
+    let mut ratio = f32::INFINITY;
+    for (i, &a) in vertices.iter().enumerate() {
+        for &b in &vertices[i + 1..] {
+            let direction = (a - b).normalize();
+            let (min, max) = vertices
+                .iter()
+                .map(|v| v.dot(direction))
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), d| {
+                    (min.min(d), max.max(d))
+                });
+            let gap = (a - b).dot(direction);
+            ratio = ratio.min(gap / (max - min + gap));
+        }
+    }
+    ratio
+}
+
+/// One copy of the polyhedron per vertex, shrunk by `ratio` towards that vertex
+pub fn flake_children(polyhedron: &Polyhedron, ratio: f32) -> Vec<Transform> {
+    polyhedron
+        .vertices
+        .iter()
+        .map(|&vertex| {
+            Transform::from_translation(vertex * (1.0 - ratio)).with_scale(Vec3::splat(ratio))
+        })
+        .collect()
+}
+
+/// Transforms of the seed copies making up an n-flake of `polyhedron` scaled by `size`
+pub fn n_flake(polyhedron: &Polyhedron, depth: u32, size: f32, ratio: f32) -> Vec<Transform> {
+    expand_instances(
+        Transform::from_scale(Vec3::splat(size)),
+        &flake_children(polyhedron, ratio),
+        depth,
+    )
+}
+
+/// Copies of a polyhedron at each of its own vertices, recursively: Sierpinski octahedra,
+/// icosahedral and dodecahedral flakes and so on, depending on the seed
+pub struct NFlake;
+
+impl FractalGenerator for NFlake {
+    fn name(&self) -> &'static str {
+        "n_flake"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::int("depth", 0..=6, 3),
+            ParamSpec::float("size", 0.1..=100.0, 3.0),
+            // Zero picks `flake_ratio`, which keeps the copies from overlapping
+            ParamSpec::float("scale_ratio", 0.0..=0.9, 0.0),
+            ParamSpec::bool("merged", true),
+            ParamSpec::seed("seed", SeedShape::Octahedron),
+        ]
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let seed = params.seed("seed");
+        let polyhedron = seed.polyhedron();
+        let ratio = match params.float("scale_ratio") {
+            ratio if ratio > 0.0 => ratio,
+            _ => flake_ratio(&polyhedron),
+        };
+
+        let geometry = FractalGeometry::Instanced {
+            seed: polyhedron.mesh(),
+            instances: n_flake(
+                &polyhedron,
+                params.int("depth") as u32,
+                params.float("size"),
+                ratio,
+            ),
+            branching: polyhedron.vertices.len(),
+        };
+        if params.bool("merged") {
+            geometry.merged()
+        } else {
+            geometry
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the insides of two copies of `polyhedron` intersect, by looking for a
+    /// separating axis among the face normals and the cross products of edges
+    fn overlap(polyhedron: &Polyhedron, a: &Transform, b: &Transform) -> bool {
+        let vertices = &polyhedron.vertices;
+        let edges: Vec<Vec3> = polyhedron
+            .faces
+            .iter()
+            .flat_map(|face| {
+                (0..face.len()).map(|i| vertices[face[(i + 1) % face.len()]] - vertices[face[i]])
+            })
+            .collect();
+        let normals = polyhedron
+            .faces
+            .iter()
+            .map(|face| polyhedron.face_normal(face));
+        let crosses = edges.iter().enumerate().flat_map(|(i, &e)| {
+            edges[i + 1..]
+                .iter()
+                .filter_map(move |&f| e.cross(f).try_normalize())
+        });
+        let extent = |copy: &Transform, axis: Vec3| {
+            vertices
+                .iter()
+                .map(|&v| copy.transform_point(v).dot(axis))
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), d| {
+                    (min.min(d), max.max(d))
+                })
+        };
+        normals.chain(crosses).all(|axis| {
+            let (a_min, a_max) = extent(a, axis);
+            let (b_min, b_max) = extent(b, axis);
+            a_max.min(b_max) - a_min.max(b_min) > 1e-4
+        })
+    }
+
+    fn any_overlap(polyhedron: &Polyhedron, ratio: f32) -> bool {
+        let children = flake_children(polyhedron, ratio);
+        children
+            .iter()
+            .enumerate()
+            .any(|(i, a)| children[i + 1..].iter().any(|b| overlap(polyhedron, a, b)))
+    }
+
+    #[test]
+    fn ratios_match_the_classic_flakes() {
+        let phi = (1.0 + 5.0f32.sqrt()) / 2.0;
+        for (seed, expected) in [
+            (SeedShape::Tetrahedron, 0.5),
+            (SeedShape::Cube, 0.5),
+            (SeedShape::Octahedron, 0.5),
+            (SeedShape::Icosahedron, 1.0 / (1.0 + phi)),
+            (SeedShape::Dodecahedron, 1.0 / (2.0 + phi)),
+        ] {
+            let ratio = flake_ratio(&seed.polyhedron());
+            assert!((ratio - expected).abs() < 1e-5, "{seed:?}: {ratio}");
+        }
+    }
+
+    #[test]
+    fn copies_touch_without_overlapping() {
+        for seed in [
+            SeedShape::Tetrahedron,
+            SeedShape::Cube,
+            SeedShape::Octahedron,
+            SeedShape::Icosahedron,
+            SeedShape::Dodecahedron,
+        ] {
+            let polyhedron = seed.polyhedron();
+            let ratio = flake_ratio(&polyhedron);
+            assert!(!any_overlap(&polyhedron, ratio), "{seed:?} overlaps");
+            // Any larger and some neighbours run into each other
+            assert!(any_overlap(&polyhedron, ratio * 1.02), "{seed:?} has room");
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::{self, Write};
+
+use bevy::prelude::*;
+use bevy::render::mesh::{PrimitiveTopology, VertexAttributeValues};
+
+use crate::export::{ExportMaterial, ExportScene};
+use crate::mesh_builder::oriented;
+
+/// Writes every triangle-list part of `scene` as a Wavefront OBJ object, transforms baked in,
+/// referring to materials in the MTL file `mtl_name` next to it
+pub fn write_obj(scene: &ExportScene, mtl_name: &str, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "# bevy_3d_fractals")?;
+    writeln!(writer, "mtllib {mtl_name}")?;
+
+    // OBJ indices count from 1 across the whole file
+    let (mut positions_written, mut uvs_written, mut normals_written) = (1, 1, 1);
+    for part in &scene.parts {
+        let mesh = &scene.meshes[part.mesh];
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            continue;
+        }
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+            _ => None,
+        };
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+            _ => None,
+        };
+
+        writeln!(writer, "o {}", part.name)?;
+        for &position in positions {
+            let [x, y, z] = part.transform.transform_point3(position.into()).to_array();
+            writeln!(writer, "v {x} {y} {z}")?;
+        }
+        if let Some(uvs) = uvs {
+            // OBJ puts the texture origin at the bottom left, Bevy at the top left
+            for &[u, v] in uvs {
+                writeln!(writer, "vt {u} {}", 1.0 - v)?;
+            }
+        }
+        if let Some(normals) = normals {
+            let normal_matrix = Mat3::from(part.transform.matrix3).inverse().transpose();
+            for &normal in normals {
+                let [x, y, z] = (normal_matrix * Vec3::from(normal))
+                    .normalize_or_zero()
+                    .to_array();
+                writeln!(writer, "vn {x} {y} {z}")?;
+            }
+        }
+        writeln!(writer, "usemtl {}", scene.materials[part.material].name)?;
+
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+        let corner = |i: usize| {
+            let position = positions_written + i;
+            match (uvs.is_some(), normals.is_some()) {
+                (true, true) => format!("{position}/{}/{}", uvs_written + i, normals_written + i),
+                (true, false) => format!("{position}/{}", uvs_written + i),
+                (false, true) => format!("{position}//{}", normals_written + i),
+                (false, false) => position.to_string(),
+            }
+        };
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = oriented([triangle[0], triangle[1], triangle[2]], &part.transform);
+            writeln!(writer, "f {} {} {}", corner(a), corner(b), corner(c))?;
+        }
+
+        positions_written += positions.len();
+        uvs_written += uvs.map_or(0, Vec::len);
+        normals_written += normals.map_or(0, Vec::len);
+    }
+    Ok(())
+}
+
+/// Writes `materials` as an MTL file, with the PBR extension's roughness and metalness for
+/// importers that read them
+pub fn write_mtl(materials: &[ExportMaterial], writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "# bevy_3d_fractals")?;
+    for material in materials {
+        let [r, g, b, a] = material.base_color.as_linear_rgba_f32();
+        let [er, eg, eb, _] = material.emissive.as_linear_rgba_f32();
+        // The classic Phong exponent, falling from 1000 for a mirror to 0 for fully rough
+        let shininess = 1000.0 * (1.0 - material.perceptual_roughness).powi(2);
+        writeln!(writer)?;
+        writeln!(writer, "newmtl {}", material.name)?;
+        writeln!(writer, "Kd {r} {g} {b}")?;
+        writeln!(writer, "Ke {er} {eg} {eb}")?;
+        writeln!(writer, "Ns {shininess}")?;
+        writeln!(writer, "d {a}")?;
+        writeln!(writer, "Pr {}", material.perceptual_roughness)?;
+        writeln!(writer, "Pm {}", material.metallic)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,787 @@
+use core::f32::consts::{FRAC_1_PI, PI, TAU};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use bevy::render::mesh::{PrimitiveTopology, VertexAttributeValues};
+use bevy::tasks::AsyncComputeTaskPool;
+use image::RgbImage;
+
+use crate::chaos::SplitMix64;
+use crate::distance_estimators::{DistanceEstimator, Estimator};
+use crate::flycam::FlyCam;
+use crate::generator::FractalGeometry;
+use crate::raymarch::RaymarchedFractal;
+
+/// Most triangles a BVH leaf holds before it is split
+const LEAF_SIZE: usize = 4;
+/// Deep enough for any BVH built by median splits over fewer than 2^60 triangles
+const TRAVERSAL_STACK: usize = 64;
+/// How far rays leaving a triangle start off it, relative to the size of its coordinates
+const SURFACE_OFFSET: f32 = 1e-4;
+
+/// How the path tracer samples the image
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathTraceSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Paths averaged for each pixel
+    pub samples: u32,
+    /// Diffuse bounces after the first hit; zero gives direct light only
+    pub max_bounces: u32,
+    /// Seeds every pixel's random numbers, so the same scene and settings give the same image
+    pub seed: u64,
+}
+
+impl Default for PathTraceSettings {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 360,
+            samples: 16,
+            max_bounces: 2,
+            seed: 0,
+        }
+    }
+}
+
+/// A pinhole camera looking down its local -Z, like Bevy's
+#[derive(Clone, Copy, Debug)]
+pub struct TraceCamera {
+    pub transform: Affine3A,
+    /// Vertical field of view in radians, as in `PerspectiveProjection`
+    pub fov: f32,
+}
+
+impl TraceCamera {
+    /// The ray through `pixel`, measured from the top left corner of an image `size` pixels big
+    fn ray(&self, pixel: Vec2, size: Vec2) -> Ray {
+        let ndc = Vec2::new(pixel.x / size.x * 2.0 - 1.0, 1.0 - pixel.y / size.y * 2.0);
+        let half_height = (self.fov / 2.0).tan();
+        let half_width = half_height * size.x / size.y;
+        let local = Vec3::new(ndc.x * half_width, ndc.y * half_height, -1.0);
+        Ray {
+            origin: self.transform.translation.into(),
+            direction: self.transform.transform_vector3(local).normalize(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Ray {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+impl Ray {
+    fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Hit {
+    t: f32,
+    normal: Vec3,
+    albedo: Vec3,
+    /// How far along the normal rays leaving the hit start, so they miss the surface they left
+    offset: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Triangle {
+    vertices: [Vec3; 3],
+    normals: [Vec3; 3],
+    albedo: Vec3,
+}
+
+impl Triangle {
+    fn centroid(&self) -> Vec3 {
+        (self.vertices[0] + self.vertices[1] + self.vertices[2]) / 3.0
+    }
+
+    /// Möller–Trumbore; the distance to the hit and its barycentric coordinates
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<(f32, Vec2)> {
+        let [v0, v1, v2] = self.vertices;
+        let (e1, e2) = (v1 - v0, v2 - v0);
+        let p = ray.direction.cross(e2);
+        let determinant = e1.dot(p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let s = ray.origin - v0;
+        let u = s.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let v = ray.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(q) * inverse;
+        (t > 0.0 && t < t_max).then_some((t, Vec2::new(u, v)))
+    }
+
+    fn hit(&self, ray: &Ray, t: f32, barycentric: Vec2) -> Hit {
+        let [n0, n1, n2] = self.normals;
+        let smooth =
+            n0 * (1.0 - barycentric.x - barycentric.y) + n1 * barycentric.x + n2 * barycentric.y;
+        let [v0, v1, v2] = self.vertices;
+        let normal = smooth
+            .try_normalize()
+            .unwrap_or_else(|| (v1 - v0).cross(v2 - v0).normalize_or_zero());
+        let point = ray.at(t);
+        Hit {
+            t,
+            normal,
+            albedo: self.albedo,
+            offset: SURFACE_OFFSET * (1.0 + point.abs().max_element()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    min: Vec3,
+    max: Vec3,
+    /// For leaves the first of `count` entries in `Bvh::order`; for internal nodes the index of
+    /// the second child, the first being the node right after this one
+    start: u32,
+    count: u32,
+}
+
+impl BvhNode {
+    /// Whether the ray enters the box before `t_max`
+    fn hit_by(&self, origin: Vec3, inverse_direction: Vec3, t_max: f32) -> bool {
+        let t0 = (self.min - origin) * inverse_direction;
+        let t1 = (self.max - origin) * inverse_direction;
+        let near = t0.min(t1).max_element().max(0.0);
+        let far = t0.max(t1).min_element().min(t_max);
+        near <= far
+    }
+}
+
+/// Bounding volume hierarchy over a scene's triangles, split at the median centroid
+struct Bvh<'a> {
+    triangles: &'a [Triangle],
+    nodes: Vec<BvhNode>,
+    /// Triangle indices, ordered so each leaf's triangles sit together
+    order: Vec<u32>,
+}
+
+impl<'a> Bvh<'a> {
+    fn new(triangles: &'a [Triangle]) -> Self {
+        let mut bvh = Self {
+            triangles,
+            nodes: Vec::with_capacity(2 * triangles.len() / LEAF_SIZE + 1),
+            order: (0..triangles.len() as u32).collect(),
+        };
+        if !triangles.is_empty() {
+            bvh.build(0, triangles.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, start: usize, end: usize) {
+        let triangles = self.triangles;
+        let order = &mut self.order[start..end];
+        let (mut min, mut max) = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+        let (mut centroid_min, mut centroid_max) = (min, max);
+        for &index in order.iter() {
+            let triangle = &triangles[index as usize];
+            for vertex in triangle.vertices {
+                min = min.min(vertex);
+                max = max.max(vertex);
+            }
+            centroid_min = centroid_min.min(triangle.centroid());
+            centroid_max = centroid_max.max(triangle.centroid());
+        }
+
+        let node = self.nodes.len();
+        self.nodes.push(BvhNode {
+            min,
+            max,
+            start: start as u32,
+            count: (end - start) as u32,
+        });
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let middle = (end - start) / 2;
+        order.select_nth_unstable_by(middle, |&a, &b| {
+            let a = triangles[a as usize].centroid()[axis];
+            a.total_cmp(&triangles[b as usize].centroid()[axis])
+        });
+
+        self.build(start, start + middle);
+        let second = self.nodes.len();
+        self.build(start + middle, end);
+        self.nodes[node].start = second as u32;
+        self.nodes[node].count = 0;
+    }
+
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = ray.direction.recip();
+        let mut closest: Option<(f32, u32, Vec2)> = None;
+        let mut stack = [0u32; TRAVERSAL_STACK];
+        let mut len = 1;
+
+        while len > 0 {
+            len -= 1;
+            let index = stack[len];
+            let node = &self.nodes[index as usize];
+            let limit = closest.map_or(t_max, |(t, ..)| t);
+            if !node.hit_by(ray.origin, inverse_direction, limit) {
+                continue;
+            }
+            if node.count == 0 {
+                stack[len] = node.start;
+                stack[len + 1] = index + 1;
+                len += 2;
+                continue;
+            }
+            for &triangle in &self.order[node.start as usize..(node.start + node.count) as usize] {
+                let limit = closest.map_or(t_max, |(t, ..)| t);
+                if let Some((t, barycentric)) =
+                    self.triangles[triangle as usize].intersect(ray, limit)
+                {
+                    closest = Some((t, triangle, barycentric));
+                }
+            }
+        }
+        closest.map(|(t, triangle, barycentric)| {
+            self.triangles[triangle as usize].hit(ray, t, barycentric)
+        })
+    }
+}
+
+/// A distance-estimated fractal, sphere traced inside the same bounding cube the raymarching
+/// shader uses
+#[derive(Clone, Copy, Debug)]
+struct DistanceObject {
+    estimator: Estimator,
+    to_local: Affine3A,
+    albedo: Vec3,
+    max_steps: u32,
+    hit_epsilon: f32,
+}
+
+impl DistanceObject {
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let origin = self.to_local.transform_point3(ray.origin);
+        let direction = self.to_local.transform_vector3(ray.direction);
+        // Local units per world unit along the ray
+        let stretch = direction.length();
+        let direction = direction / stretch;
+
+        let bounds = self.estimator.bounds();
+        let t0 = (Vec3::splat(-bounds) - origin) / direction;
+        let t1 = (Vec3::splat(bounds) - origin) / direction;
+        let exit = t0.max(t1).min_element().min(t_max * stretch);
+        let mut t = t0.min(t1).max_element().max(0.0);
+
+        for _ in 0..self.max_steps {
+            if t > exit {
+                break;
+            }
+            let p = origin + direction * t;
+            let d = self.estimator.distance(p);
+            if d < self.hit_epsilon {
+                let normal = self.to_local.matrix3.transpose() * self.estimator.normal(p);
+                return Some(Hit {
+                    t: t / stretch,
+                    normal: normal.normalize_or_zero(),
+                    albedo: self.albedo,
+                    offset: 2.0 * self.hit_epsilon / stretch,
+                });
+            }
+            t += d;
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TraceLight {
+    position: Vec3,
+    /// Linear colour times luminous intensity, the same `color * intensity / 4π` Bevy uses
+    intensity: Vec3,
+    range: f32,
+}
+
+impl TraceLight {
+    /// Irradiance at `distance`, windowed to reach zero at the light's range like Bevy's
+    fn irradiance(&self, distance: f32) -> Vec3 {
+        let factor = (distance / self.range).powi(4);
+        let window = (1.0 - factor).clamp(0.0, 1.0).powi(2);
+        self.intensity * window / (distance * distance).max(1e-4)
+    }
+}
+
+/// Everything the path tracer renders: triangle meshes, distance-estimated fractals, point
+/// lights and the camera they are seen through
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub camera: TraceCamera,
+    /// What camera rays that miss everything see, like `ClearColor`
+    pub background: Vec3,
+    /// Light arriving from everywhere, what bounced rays that leave the scene pick up; this
+    /// plays the part of `AmbientLight`
+    pub ambient: Vec3,
+    triangles: Vec<Triangle>,
+    distance_objects: Vec<DistanceObject>,
+    lights: Vec<TraceLight>,
+}
+
+/// The linear RGB of a colour, which is what light is added up in
+fn linear_rgb(color: Color) -> Vec3 {
+    let [r, g, b, _] = color.as_linear_rgba_f32();
+    Vec3::new(r, g, b)
+}
+
+impl Scene {
+    pub fn new(camera: TraceCamera) -> Self {
+        let ambient = AmbientLight::default();
+        Self {
+            camera,
+            background: linear_rgb(ClearColor::default().0),
+            ambient: linear_rgb(ambient.color) * ambient.brightness,
+            triangles: Vec::new(),
+            distance_objects: Vec::new(),
+            lights: Vec::new(),
+        }
+    }
+
+    /// Adds the triangles of `mesh`; other topologies, such as point clouds, have no surface
+    /// to hit and are left out
+    pub fn add_mesh(&mut self, mesh: &Mesh, transform: &Affine3A, color: Color) {
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            return;
+        }
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+            _ => None,
+        };
+        let normal_matrix = transform.matrix3.inverse().transpose();
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+
+        let albedo = linear_rgb(color);
+        for corners in indices.chunks_exact(3) {
+            let vertex = |i: usize| transform.transform_point3(Vec3::from(positions[corners[i]]));
+            let normal = |i: usize| {
+                normals.map_or(Vec3::ZERO, |normals| {
+                    (normal_matrix * Vec3::from(normals[corners[i]])).normalize_or_zero()
+                })
+            };
+            self.triangles.push(Triangle {
+                vertices: [vertex(0), vertex(1), vertex(2)],
+                normals: [normal(0), normal(1), normal(2)],
+                albedo,
+            });
+        }
+    }
+
+    /// Adds a generator's output, each instance of an instanced fractal as a copy of its seed
+    pub fn add_geometry(&mut self, geometry: &FractalGeometry, transform: &Affine3A, color: Color) {
+        match geometry {
+            FractalGeometry::Merged(chunks) | FractalGeometry::MergedInstances { chunks, .. } => {
+                for chunk in chunks {
+                    self.add_mesh(chunk, transform, color);
+                }
+            }
+            FractalGeometry::Instanced {
+                seed, instances, ..
+            } => {
+                for instance in instances {
+                    self.add_mesh(seed, &(*transform * instance.compute_affine()), color);
+                }
+            }
+            FractalGeometry::Points(points) => self.add_mesh(points, transform, color),
+        }
+    }
+
+    /// Adds a distance-estimated fractal, marched with the settings of `fractal`
+    pub fn add_fractal(&mut self, fractal: &RaymarchedFractal, transform: &Affine3A) {
+        self.distance_objects.push(DistanceObject {
+            estimator: fractal.estimator,
+            to_local: transform.inverse(),
+            albedo: linear_rgb(fractal.color),
+            max_steps: fractal.max_steps,
+            hit_epsilon: fractal.hit_epsilon,
+        });
+    }
+
+    pub fn add_point_light(&mut self, position: Vec3, light: &PointLight) {
+        self.lights.push(TraceLight {
+            position,
+            intensity: linear_rgb(light.color) * light.intensity / (4.0 * PI),
+            range: light.range,
+        });
+    }
+
+    /// Collects what the `FlyCam` sees: every visible mesh drawn with a `StandardMaterial`, every
+    /// raymarched fractal, every point light and the ambient light and clear colour, if set.
+    /// Returns `None` without a fly camera to look through.
+    pub fn from_world(world: &mut World) -> Option<Self> {
+        let mut cameras = world.query_filtered::<(&GlobalTransform, &Projection), With<FlyCam>>();
+        let mut lights = world.query::<(&GlobalTransform, &PointLight)>();
+        let mut fractals = world.query::<(&GlobalTransform, &RaymarchedFractal)>();
+        let mut meshes = world.query::<(
+            &GlobalTransform,
+            &Handle<Mesh>,
+            &Handle<StandardMaterial>,
+            &ComputedVisibility,
+        )>();
+
+        let (transform, projection) = cameras.iter(world).next()?;
+        let fov = match projection {
+            Projection::Perspective(perspective) => perspective.fov,
+            // Parallel rays are not supported; fall back on the default perspective
+            Projection::Orthographic(_) => PerspectiveProjection::default().fov,
+        };
+        let mut scene = Scene::new(TraceCamera {
+            transform: transform.affine(),
+            fov,
+        });
+        if let Some(clear_color) = world.get_resource::<ClearColor>() {
+            scene.background = linear_rgb(clear_color.0);
+        }
+        if let Some(ambient) = world.get_resource::<AmbientLight>() {
+            scene.ambient = linear_rgb(ambient.color) * ambient.brightness;
+        }
+
+        for (transform, light) in lights.iter(world) {
+            scene.add_point_light(transform.translation(), light);
+        }
+        for (transform, fractal) in fractals.iter(world) {
+            scene.add_fractal(fractal, &transform.affine());
+        }
+        let mesh_assets = world.resource::<Assets<Mesh>>();
+        let materials = world.resource::<Assets<StandardMaterial>>();
+        for (transform, mesh, material, visibility) in meshes.iter(world) {
+            let (Some(mesh), Some(material)) = (mesh_assets.get(mesh), materials.get(material))
+            else {
+                continue;
+            };
+            if visibility.is_visible() {
+                scene.add_mesh(mesh, &transform.affine(), material.base_color);
+            }
+        }
+        Some(scene)
+    }
+
+    /// Path traces the scene with diffuse surfaces, one thread per core taking rows in turn
+    pub fn render(&self, settings: &PathTraceSettings) -> RgbImage {
+        let tracer = Tracer {
+            scene: self,
+            bvh: Bvh::new(&self.triangles),
+            settings,
+        };
+        let row_len = settings.width as usize * 3;
+        let mut pixels = vec![0; row_len * settings.height as usize];
+        let rows = Mutex::new(pixels.chunks_mut(row_len.max(1)).enumerate());
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let Some((y, row)) = rows.lock().unwrap().next() else {
+                        break;
+                    };
+                    for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                        pixel.copy_from_slice(&tracer.pixel(x as u32, y as u32));
+                    }
+                });
+            }
+        });
+        RgbImage::from_raw(settings.width, settings.height, pixels)
+            .expect("the buffer is sized for the image")
+    }
+}
+
+struct Tracer<'a> {
+    scene: &'a Scene,
+    bvh: Bvh<'a>,
+    settings: &'a PathTraceSettings,
+}
+
+impl Tracer<'_> {
+    fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        let size = Vec2::new(self.settings.width as f32, self.settings.height as f32);
+        let index = y as u64 * self.settings.width as u64 + x as u64;
+        let mut rng = SplitMix64::new(
+            self.settings
+                .seed
+                .wrapping_add(index)
+                .wrapping_mul(0xD1B5_4A32_D192_ED03),
+        );
+
+        let samples = self.settings.samples.max(1);
+        let mut sum = Vec3::ZERO;
+        for _ in 0..samples {
+            let jitter = Vec2::new(rng.next_f32(), rng.next_f32());
+            let ray = self
+                .scene
+                .camera
+                .ray(Vec2::new(x as f32, y as f32) + jitter, size);
+            sum += self.radiance(ray, &mut rng);
+        }
+        encode(sum / samples as f32)
+    }
+
+    /// The closest hit on either kind of surface, with its normal facing back along the ray
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let mut closest = self.bvh.intersect(ray, t_max);
+        for object in &self.scene.distance_objects {
+            let limit = closest.map_or(t_max, |hit| hit.t);
+            if let Some(hit) = object.intersect(ray, limit) {
+                closest = Some(hit);
+            }
+        }
+        closest.map(|mut hit| {
+            if hit.normal.dot(ray.direction) > 0.0 {
+                hit.normal = -hit.normal;
+            }
+            hit
+        })
+    }
+
+    /// Light arriving along `ray`, following it through diffuse bounces and sampling the point
+    /// lights directly at every hit
+    fn radiance(&self, mut ray: Ray, rng: &mut SplitMix64) -> Vec3 {
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        for bounce in 0..=self.settings.max_bounces {
+            let Some(hit) = self.intersect(&ray, f32::INFINITY) else {
+                let escaped = if bounce == 0 {
+                    self.scene.background
+                } else {
+                    self.scene.ambient
+                };
+                return radiance + throughput * escaped;
+            };
+
+            let point = ray.at(hit.t) + hit.normal * hit.offset;
+            for light in &self.scene.lights {
+                let to_light = light.position - point;
+                let distance = to_light.length();
+                let direction = to_light / distance;
+                let cosine = hit.normal.dot(direction);
+                if cosine <= 0.0 {
+                    continue;
+                }
+                let shadow = Ray {
+                    origin: point,
+                    direction,
+                };
+                if self.intersect(&shadow, distance).is_none() {
+                    radiance +=
+                        throughput * hit.albedo * FRAC_1_PI * light.irradiance(distance) * cosine;
+                }
+            }
+
+            // Sampling bounces by cosine cancels both the cosine and the Lambertian 1/π
+            throughput *= hit.albedo;
+            ray = Ray {
+                origin: point,
+                direction: cosine_weighted(hit.normal, rng),
+            };
+        }
+        radiance
+    }
+}
+
+/// A direction in the hemisphere around `normal`, more likely the closer it is to the normal
+fn cosine_weighted(normal: Vec3, rng: &mut SplitMix64) -> Vec3 {
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let radius = rng.next_f32().sqrt();
+    let angle = TAU * rng.next_f32();
+    tangent * radius * angle.cos()
+        + bitangent * radius * angle.sin()
+        + normal * (1.0 - radius * radius).max(0.0).sqrt()
+}
+
+/// Tone maps linear radiance with Reinhard on luminance and encodes it as 8-bit sRGB
+fn encode(radiance: Vec3) -> [u8; 3] {
+    let luminance = radiance.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+    let mapped = radiance / (1.0 + luminance);
+    let [r, g, b, _] = Color::rgb_linear(mapped.x, mapped.y, mapped.z).as_rgba_f32();
+    [r, g, b].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Asks for the `FlyCam`'s view to be path traced and written to `path` as a PNG
+#[derive(Event, Clone, Debug)]
+pub struct PathTraceRequest {
+    pub path: PathBuf,
+    pub settings: PathTraceSettings,
+}
+
+/// Snapshots the scene for every request and traces it off the main thread
+fn trace_requested_frames(world: &mut World) {
+    let requests: Vec<PathTraceRequest> = world
+        .resource_mut::<Events<PathTraceRequest>>()
+        .drain()
+        .collect();
+    for request in requests {
+        let Some(scene) = Scene::from_world(world) else {
+            warn!("Nothing to path trace without a FlyCam");
+            continue;
+        };
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let image = scene.render(&request.settings);
+                match image.save(&request.path) {
+                    Ok(()) => info!("Wrote path traced frame to {}", request.path.display()),
+                    Err(error) => error!("Could not write {}: {error}", request.path.display()),
+                }
+            })
+            .detach();
+    }
+}
+
+/// Path traces the current view to `path_traced.png` when F12 is pressed
+fn request_on_keypress(keys: Res<Input<KeyCode>>, mut requests: EventWriter<PathTraceRequest>) {
+    if keys.just_pressed(KeyCode::F12) {
+        requests.send(PathTraceRequest {
+            path: PathBuf::from("path_traced.png"),
+            settings: default(),
+        });
+    }
+}
+
+/// Renders PNGs on the CPU for `PathTraceRequest`s, so images can be made without a GPU
+pub struct PathTracerPlugin;
+impl Plugin for PathTracerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PathTraceRequest>().add_systems(
+            Update,
+            (request_on_keypress, trace_requested_frames).chain(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance_estimators::MengerSponge;
+
+    fn test_scene() -> Scene {
+        let mut scene = Scene::new(TraceCamera {
+            transform: Affine3A::from_translation(Vec3::new(0.0, 0.0, 4.0)),
+            fov: PI / 4.0,
+        });
+        scene.background = Vec3::ZERO;
+        let cube = RaymarchedFractal::new(Estimator::MengerSponge(MengerSponge {
+            half_size: 0.5,
+            iterations: 0,
+        }));
+        scene.add_fractal(&cube, &Affine3A::IDENTITY);
+        scene.add_point_light(
+            Vec3::new(0.0, 0.0, 6.0),
+            &PointLight {
+                intensity: 2000.0,
+                ..default()
+            },
+        );
+        scene
+    }
+
+    #[test]
+    fn renders_deterministically() {
+        let settings = PathTraceSettings {
+            width: 24,
+            height: 16,
+            samples: 2,
+            ..default()
+        };
+        let scene = test_scene();
+        let image = scene.render(&settings);
+        assert_eq!(image.dimensions(), (24, 16));
+        assert_eq!(image, scene.render(&settings));
+
+        // The cube fills the middle, lit from the camera's side, and the corners see nothing
+        assert!(image.get_pixel(12, 8).0.iter().all(|&c| c > 50));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn meshes_cast_shadows_on_fractals() {
+        let settings = PathTraceSettings {
+            width: 8,
+            height: 8,
+            samples: 1,
+            max_bounces: 0,
+            ..default()
+        };
+        // A wall between the light and the cube, facing away from the camera so it is unseen
+        let mut scene = test_scene();
+        let wall = Mesh::from(shape::Quad::new(Vec2::splat(4.0)));
+        scene.add_mesh(
+            &wall,
+            &Affine3A::from_translation(Vec3::new(0.0, 0.0, 5.0)),
+            Color::WHITE,
+        );
+        scene.camera.transform = Affine3A::from_translation(Vec3::new(0.0, 0.0, 4.5));
+        let mut unshadowed = test_scene();
+        unshadowed.camera = scene.camera;
+
+        assert!(unshadowed.render(&settings).get_pixel(4, 4).0[0] > 0);
+        assert_eq!(scene.render(&settings).get_pixel(4, 4).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn bvh_finds_the_closest_triangle() {
+        let mut rng = SplitMix64::new(7);
+        let mut random = || Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 4.0 - 2.0;
+        let triangles: Vec<Triangle> = (0..300)
+            .map(|_| {
+                let center = random();
+                Triangle {
+                    vertices: [center, center + random() * 0.2, center + random() * 0.2],
+                    normals: [Vec3::ZERO; 3],
+                    albedo: Vec3::ONE,
+                }
+            })
+            .collect();
+        let bvh = Bvh::new(&triangles);
+
+        for _ in 0..500 {
+            let ray = Ray {
+                origin: random() * 2.0,
+                direction: random().normalize(),
+            };
+            let brute_force = triangles
+                .iter()
+                .filter_map(|triangle| triangle.intersect(&ray, f32::INFINITY))
+                .map(|(t, _)| t)
+                .min_by(f32::total_cmp);
+            assert_eq!(
+                bvh.intersect(&ray, f32::INFINITY).map(|hit| hit.t),
+                brute_force
+            );
+        }
+    }
+}
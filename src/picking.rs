@@ -0,0 +1,408 @@
+use bevy::math::Vec3A;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::primitives::Aabb;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+use crate::flycam::FlyCam;
+use crate::fractal::FractalParams;
+use crate::generator::{FractalNode, MergedParts};
+use crate::mesh_builder::{mesh_triangles, MeshBuilder, ATTRIBUTE_PART};
+
+/// Sent when a click lands on a piece of a fractal
+#[derive(Event, Clone, Debug)]
+pub struct FractalPicked {
+    /// The entity holding the fractal's `FractalParams`
+    pub fractal: Entity,
+    /// The entity hit, which for merged instances holds more parts than the one picked
+    pub part: Entity,
+    /// The child taken at each level of the recursion to reach the part, as in `FractalNode`
+    pub path: Vec<u32>,
+    /// Where the click hit the part, in world space
+    pub point: Vec3,
+}
+
+/// What is currently drawn highlighted
+enum Highlighted {
+    /// A part wearing the highlight material, with the material it had before
+    Part(Entity, Handle<StandardMaterial>),
+    /// A mesh of one part of merged instances, drawn over them
+    Overlay(Entity),
+}
+
+/// The material picked parts are drawn with, and what is wearing it
+#[derive(Resource)]
+struct PickHighlight {
+    material: Handle<StandardMaterial>,
+    picked: Option<Highlighted>,
+}
+
+impl FromWorld for PickHighlight {
+    fn from_world(world: &mut World) -> Self {
+        let material = world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial {
+                base_color: Color::rgb(1.0, 0.3, 0.1),
+                emissive: Color::rgb(0.6, 0.15, 0.0),
+                // Overlays lie right on top of the faces they highlight
+                depth_bias: 100.0,
+                ..default()
+            });
+        Self {
+            material,
+            picked: None,
+        }
+    }
+}
+
+/// Distance along `direction`, in multiples of its length, to where the ray from `origin`
+/// enters the box, if it does
+fn ray_aabb(origin: Vec3, direction: Vec3, aabb: &Aabb) -> Option<f32> {
+    let (min, max) = (aabb.min(), aabb.max());
+    let inverse = Vec3A::from(direction).recip();
+    let (t1, t2) = (
+        (min - Vec3A::from(origin)) * inverse,
+        (max - Vec3A::from(origin)) * inverse,
+    );
+    let near = t1.min(t2).max_element().max(0.0);
+    let far = t1.max(t2).min_element();
+    (near <= far).then_some(near)
+}
+
+/// Möller–Trumbore; distance along `direction`, in multiples of its length, to the triangle
+fn ray_triangle(origin: Vec3, direction: Vec3, [v0, v1, v2]: [Vec3; 3]) -> Option<f32> {
+    let (e1, e2) = (v1 - v0, v2 - v0);
+    let p = direction.cross(e2);
+    let determinant = e1.dot(p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = origin - v0;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(q) * inverse;
+    (t > 0.0).then_some(t)
+}
+
+/// The nearest hit of a ray on `mesh`'s triangles, in the mesh's own space, and the index of
+/// the triangle hit
+fn ray_mesh(origin: Vec3, direction: Vec3, mesh: &Mesh) -> Option<(f32, usize)> {
+    mesh_triangles(mesh)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, triangle)| Some((ray_triangle(origin, direction, triangle)?, index)))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// The `ATTRIBUTE_PART` of a triangle of merged instances, read off its first corner
+fn triangle_part(mesh: &Mesh, triangle: usize) -> Option<u32> {
+    let Some(VertexAttributeValues::Uint32(parts)) = mesh.attribute(ATTRIBUTE_PART) else {
+        return None;
+    };
+    let corner = match mesh.indices() {
+        Some(Indices::U16(indices)) => *indices.get(3 * triangle)? as usize,
+        Some(Indices::U32(indices)) => *indices.get(3 * triangle)? as usize,
+        None => 3 * triangle,
+    };
+    parts.get(corner).copied()
+}
+
+/// The triangles of merged instances belonging to instance `part`, as a mesh of their own
+fn part_mesh(mesh: &Mesh, part: u32) -> Mesh {
+    let mut builder = MeshBuilder::default();
+    for (index, triangle) in mesh_triangles(mesh).into_iter().enumerate() {
+        if triangle_part(mesh, index) == Some(part) {
+            let [a, b, c] = triangle;
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            builder.push_triangle(triangle, normal, [Vec2::ZERO; 3]);
+        }
+    }
+    builder.build()
+}
+
+/// Everything a fractal part is hit-tested with
+type PartQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static Handle<Mesh>,
+        &'static FractalNode,
+        Option<&'static MergedParts>,
+        Option<&'static Aabb>,
+        &'static ComputedVisibility,
+    ),
+>;
+
+/// Parents of entities, to climb from a part to its fractal
+type LineageQuery<'w, 's> =
+    Query<'w, 's, (Option<&'static Parent>, Option<&'static FractalParams>)>;
+
+/// Casts a ray from the flycam through the cursor on left click, or through the middle of the
+/// screen while the cursor is grabbed, and reports the nearest fractal part it hits
+fn pick_on_click(
+    buttons: Res<Input<MouseButton>>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    lineage: LineageQuery,
+    parts: PartQuery,
+    meshes: Res<Assets<Mesh>>,
+    mut picked: EventWriter<FractalPicked>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let (Ok(window), Ok((camera, camera_transform))) =
+        (primary_window.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    let cursor = match window.cursor.grab_mode {
+        CursorGrabMode::None => window.cursor_position(),
+        _ => Some(Vec2::new(window.width(), window.height()) / 2.0),
+    };
+    let Some(ray) = cursor.and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+    else {
+        return;
+    };
+    if let Some(event) = pick(ray, &parts, &lineage, &meshes) {
+        picked.send(event);
+    }
+}
+
+/// The nearest visible fractal part `ray` hits. On merged instances, the part is the instance
+/// the triangle hit was made from.
+fn pick(
+    ray: Ray,
+    parts: &PartQuery,
+    lineage: &LineageQuery,
+    meshes: &Assets<Mesh>,
+) -> Option<FractalPicked> {
+    let mut nearest: Option<(f32, Entity, usize)> = None;
+    for (part, transform, mesh, _, _, aabb, visibility) in parts.iter() {
+        let Some(mesh) = meshes.get(mesh).filter(|_| visibility.is_visible()) else {
+            continue;
+        };
+        // The ray in the part's own space; distances along it stay the same
+        let to_local = transform.affine().inverse();
+        let origin = to_local.transform_point3(ray.origin);
+        let direction = to_local.transform_vector3(ray.direction);
+        let closest = nearest.map_or(f32::INFINITY, |(t, ..)| t);
+        if aabb.is_some_and(|aabb| ray_aabb(origin, direction, aabb).is_none_or(|t| t > closest)) {
+            continue;
+        }
+        if let Some((t, triangle)) = ray_mesh(origin, direction, mesh).filter(|&(t, _)| t < closest)
+        {
+            nearest = Some((t, part, triangle));
+        }
+    }
+
+    let (t, part, triangle) = nearest?;
+    // The fractal is the nearest ancestor with `FractalParams`
+    let mut fractal = part;
+    loop {
+        match lineage.get(fractal) {
+            Ok((_, Some(_))) => break,
+            Ok((Some(parent), None)) => fractal = parent.get(),
+            _ => return None,
+        }
+    }
+    let (_, _, mesh, node, merged, _, _) = parts.get(part).expect("the part was just hit");
+    let merged_part = merged
+        .zip(meshes.get(mesh))
+        .and_then(|(merged, mesh)| triangle_part(mesh, triangle).map(|index| merged.path(index)));
+    Some(FractalPicked {
+        fractal,
+        part,
+        path: merged_part.unwrap_or_else(|| node.path.clone()),
+        point: ray.get_point(t),
+    })
+}
+
+/// Draws the last picked part in the highlight material, giving the one before its own back.
+/// A part of merged instances gets a copy of its triangles drawn over it instead.
+fn highlight_picked(
+    mut commands: Commands,
+    mut highlight: ResMut<PickHighlight>,
+    mut picked: EventReader<FractalPicked>,
+    params: Query<&FractalParams>,
+    mut materials: Query<&mut Handle<StandardMaterial>>,
+    merged: Query<(&Handle<Mesh>, &MergedParts)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Some(event) = picked.iter().last() else {
+        return;
+    };
+    let kind = params
+        .get(event.fractal)
+        .map_or("fractal", |params| &params.kind);
+    info!("Picked {kind} part {:?} at {}", event.path, event.point);
+
+    // Either is gone if the fractal was regenerated since
+    match highlight.picked.take() {
+        Some(Highlighted::Part(part, original)) => {
+            if let Ok(mut material) = materials.get_mut(part) {
+                *material = original;
+            }
+        }
+        Some(Highlighted::Overlay(overlay)) => {
+            if let Some(overlay) = commands.get_entity(overlay) {
+                overlay.despawn_recursive();
+            }
+        }
+        None => {}
+    }
+
+    if let Ok((mesh, parts)) = merged.get(event.part) {
+        let Some(mesh) = meshes.get(mesh) else {
+            return;
+        };
+        let overlay = part_mesh(mesh, parts.part(&event.path));
+        let overlay = commands
+            .spawn(PbrBundle {
+                mesh: meshes.add(overlay),
+                material: highlight.material.clone(),
+                ..default()
+            })
+            .id();
+        commands.entity(event.part).add_child(overlay);
+        highlight.picked = Some(Highlighted::Overlay(overlay));
+    } else if let Ok(mut material) = materials.get_mut(event.part) {
+        let original = std::mem::replace(&mut *material, highlight.material.clone());
+        highlight.picked = Some(Highlighted::Part(event.part, original));
+    }
+}
+
+/// Picks fractal parts with the mouse, sending `FractalPicked` and highlighting the part
+pub struct PickingPlugin;
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<FractalPicked>()
+            .init_resource::<PickHighlight>()
+            .add_systems(Update, (pick_on_click, highlight_picked).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::{CommandQueue, SystemState};
+    use bevy::render::view::VisibilityPlugin;
+
+    use super::*;
+    use crate::generator::{
+        instance_path, FractalGenerator, FractalGeometry, ParamValue, ParamValues,
+    };
+    use crate::menger::{build_menger_sponge, menger_cubes, MengerSponge};
+
+    /// Where a ray down the Z axis through `(x, y)` picks a depth 2 Menger sponge nine units
+    /// across, spawned merged or not
+    fn pick_sponge(merged: bool, x: f32, y: f32) -> Option<Vec<u32>> {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            TransformPlugin,
+            HierarchyPlugin,
+            VisibilityPlugin,
+        ))
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>();
+
+        let mut values = ParamValues::default();
+        values.set("depth", ParamValue::Int(2));
+        values.set("size", ParamValue::Float(9.0));
+        values.set("merged", ParamValue::Bool(merged));
+        let geometry = MengerSponge.generate(&values.resolve(&MengerSponge.params()));
+        let world = &mut app.world;
+        let fractal = world
+            .spawn((
+                FractalParams::new("menger", 2, default()),
+                SpatialBundle::default(),
+            ))
+            .id();
+        let mut queue = CommandQueue::default();
+        world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
+            let mut commands = Commands::new(&mut queue, world);
+            let parts = geometry.spawn(&mut commands, &mut meshes, &default(), Transform::IDENTITY);
+            commands.entity(fractal).push_children(&parts);
+        });
+        queue.apply(world);
+        app.update();
+
+        // Nothing looks at the sponge, so it is put in view by hand
+        let world = &mut app.world;
+        for mut visibility in world.query::<&mut ComputedVisibility>().iter_mut(world) {
+            visibility.set_visible_in_view();
+        }
+        let mut state: SystemState<(PartQuery, LineageQuery, Res<Assets<Mesh>>)> =
+            SystemState::new(world);
+        let (parts, lineage, meshes) = state.get(world);
+        let ray = Ray {
+            origin: Vec3::new(x, y, 10.0),
+            direction: Vec3::NEG_Z,
+        };
+        let picked = pick(ray, &parts, &lineage, &meshes)?;
+        assert_eq!(picked.fractal, fractal);
+        Some(picked.path)
+    }
+
+    #[test]
+    fn picks_report_the_cube_hit() {
+        // The unit cube nearest the ray's origin at the corner the ray passes through
+        let cubes = menger_cubes(2, 9.0);
+        let hit = cubes
+            .iter()
+            .position(|cube| cube.translation == Vec3::new(-4.0, -4.0, 4.0))
+            .unwrap();
+        let expected = instance_path(hit, cubes.len(), 20);
+        assert_eq!(expected.len(), 2);
+
+        assert_eq!(pick_sponge(false, -3.9, -4.2), Some(expected.clone()));
+        assert_eq!(pick_sponge(true, -3.9, -4.2), Some(expected));
+        // Straight down the tunnel through the middle
+        assert_eq!(pick_sponge(true, 0.0, 0.0), None);
+    }
+
+    #[test]
+    fn overlays_hold_one_part() {
+        let FractalGeometry::MergedInstances { chunks, parts } = build_menger_sponge(1, 3.0) else {
+            panic!("merged Menger sponges keep their parts");
+        };
+        assert_eq!(
+            parts,
+            MergedParts {
+                count: 20,
+                branching: 20
+            }
+        );
+        // A corner cube touches three of its neighbours and shows its other three faces; the
+        // edge cube after it touches two and shows four
+        let corner = part_mesh(&chunks[0], parts.part(&[0]));
+        assert_eq!(mesh_triangles(&corner).len(), 6);
+        assert_eq!(mesh_triangles(&part_mesh(&chunks[0], 1)).len(), 8);
+    }
+
+    #[test]
+    fn rays_hit_the_nearest_face() {
+        let cube = Mesh::from(shape::Cube::new(1.0));
+        let origin = Vec3::new(0.2, 0.1, 5.0);
+        let hit = |direction| ray_mesh(origin, direction, &cube).map(|(t, _)| t);
+        assert_eq!(hit(Vec3::NEG_Z), Some(4.5));
+        // Distances scale with the direction, so they carry over between spaces
+        assert_eq!(hit(Vec3::NEG_Z * 2.0), Some(2.25));
+        assert_eq!(hit(Vec3::Z), None);
+
+        let aabb = Aabb::from_min_max(Vec3::splat(-0.5), Vec3::splat(0.5));
+        assert_eq!(ray_aabb(origin, Vec3::NEG_Z, &aabb), Some(4.5));
+        assert_eq!(ray_aabb(Vec3::new(2.0, 0.0, 5.0), Vec3::NEG_Z, &aabb), None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+
+use bevy::prelude::*;
+use bevy::render::mesh::{PrimitiveTopology, VertexAttributeValues};
+
+use crate::export::ExportScene;
+use crate::mesh_builder::oriented;
+
+/// Writes every part of `scene` as binary little-endian PLY, transforms baked in: a vertex per
+/// mesh vertex coloured by the mesh's vertex colours or else its material, and a face per
+/// triangle of the triangle-list parts, so point clouds and meshes alike come through
+pub fn write_ply(scene: &ExportScene, writer: &mut impl Write) -> io::Result<()> {
+    let mut vertices: Vec<([f32; 3], [u8; 3])> = Vec::new();
+    let mut faces: Vec<[u32; 3]> = Vec::new();
+    for part in &scene.parts {
+        let mesh = &scene.meshes[part.mesh];
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x4(colors)) => Some(colors),
+            _ => None,
+        };
+        let material_color = srgb_bytes(scene.materials[part.material].base_color);
+
+        let base = vertices.len() as u32;
+        for (i, &position) in positions.iter().enumerate() {
+            let position = part.transform.transform_point3(position.into()).to_array();
+            // Vertex colours are linear, as they multiply the base colour in the shader
+            let color = colors.map_or(material_color, |colors| {
+                let [r, g, b, a] = colors[i];
+                srgb_bytes(Color::rgba_linear(r, g, b, a))
+            });
+            vertices.push((position, color));
+        }
+
+        if mesh.primitive_topology() == PrimitiveTopology::TriangleList {
+            let indices: Vec<u32> = match mesh.indices() {
+                Some(indices) => indices.iter().map(|i| base + i as u32).collect(),
+                None => (base..base + positions.len() as u32).collect(),
+            };
+            faces.extend(indices.chunks_exact(3).map(|triangle| {
+                oriented([triangle[0], triangle[1], triangle[2]], &part.transform)
+            }));
+        }
+    }
+
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    writeln!(writer, "comment bevy_3d_fractals")?;
+    writeln!(writer, "element vertex {}", vertices.len())?;
+    for axis in ["x", "y", "z"] {
+        writeln!(writer, "property float {axis}")?;
+    }
+    for channel in ["red", "green", "blue"] {
+        writeln!(writer, "property uchar {channel}")?;
+    }
+    if !faces.is_empty() {
+        writeln!(writer, "element face {}", faces.len())?;
+        writeln!(writer, "property list uchar uint vertex_indices")?;
+    }
+    writeln!(writer, "end_header")?;
+
+    for (position, color) in &vertices {
+        for component in position {
+            writer.write_all(&component.to_le_bytes())?;
+        }
+        writer.write_all(color)?;
+    }
+    for face in &faces {
+        writer.write_all(&[3])?;
+        for index in face {
+            writer.write_all(&index.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// A colour as the 8-bit sRGB channels file formats store
+pub fn srgb_bytes(color: Color) -> [u8; 3] {
+    let [r, g, b, _] = color.as_rgba_f32();
+    [r, g, b].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::ExportMaterial;
+    use crate::generator::FractalGeometry;
+
+    #[test]
+    fn points_keep_their_colours() {
+        let mut points = Mesh::new(PrimitiveTopology::PointList);
+        points.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            vec![[0.0, 0.0, 0.0], [1.0, 2.0, 3.0]],
+        );
+        points.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            vec![[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]],
+        );
+        let material = ExportMaterial::new("fractal", &StandardMaterial::default());
+        let scene = ExportScene::from_geometry(FractalGeometry::Points(points), "ifs", material);
+        let mut bytes = Vec::new();
+        write_ply(&scene, &mut bytes).unwrap();
+
+        let end = b"end_header\n";
+        let body = bytes.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = String::from_utf8_lossy(&bytes[..body]);
+        assert!(header.contains("element vertex 2\n"));
+        assert!(!header.contains("element face"));
+        // Two vertices of three floats and three colour bytes
+        assert_eq!(bytes.len() - body, 2 * 15);
+        assert_eq!(&bytes[body + 12..body + 15], &[255, 0, 0]);
+        assert_eq!(
+            f32::from_le_bytes(bytes[body + 19..body + 23].try_into().unwrap()),
+            2.0
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+use crate::sierpinski::{tetrahedron_vertices, TETRAHEDRON_FACES};
+
+const PLANE_EPSILON: f32 = 1e-4;
+
+/// A convex polyhedron as a vertex list and faces wound counter-clockwise from outside
+#[derive(Clone, Debug)]
+pub struct Polyhedron {
+    pub vertices: Vec<Vec3>,
+    pub faces: Vec<Vec<usize>>,
+}
+
+impl Polyhedron {
+    /// The regular tetrahedron the Sierpinski fractal is built on, resting on one corner at the
+    /// origin rather than centred so it lines up with the fractal's base
+    pub fn tetrahedron() -> Self {
+        Self {
+            vertices: tetrahedron_vertices().to_vec(),
+            faces: TETRAHEDRON_FACES.iter().map(|face| face.to_vec()).collect(),
+        }
+    }
+
+    pub fn cube() -> Self {
+        let vertices = (0..8)
+            .map(|i| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32) - 0.5)
+            .collect();
+        Self::from_convex_vertices(vertices)
+    }
+
+    pub fn octahedron() -> Self {
+        let a = 0.5f32.sqrt();
+        let vertices = [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ]
+        .map(|axis| axis * a)
+        .to_vec();
+        Self::from_convex_vertices(vertices)
+    }
+
+    pub fn icosahedron() -> Self {
+        let phi = (1.0 + 5.0f32.sqrt()) / 2.0;
+        let mut vertices = Vec::new();
+        for a in [-1.0, 1.0] {
+            for b in [-phi, phi] {
+                vertices.extend(cyclic_permutations(Vec3::new(0.0, a, b)));
+            }
+        }
+        Self::from_convex_vertices(vertices.into_iter().map(|v| v * 0.5).collect())
+    }
+
+    pub fn dodecahedron() -> Self {
+        let phi = (1.0 + 5.0f32.sqrt()) / 2.0;
+        let mut vertices: Vec<Vec3> = (0..8)
+            .map(|i| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32) * 2.0 - 1.0)
+            .collect();
+        for a in [-1.0 / phi, 1.0 / phi] {
+            for b in [-phi, phi] {
+                vertices.extend(cyclic_permutations(Vec3::new(0.0, a, b)));
+            }
+        }
+        Self::from_convex_vertices(vertices.into_iter().map(|v| v * phi / 2.0).collect())
+    }
+
+    /// A square pyramid with equilateral sides, centred on its bounding box
+    pub fn square_pyramid() -> Self {
+        let half_height = 0.5f32.sqrt() / 2.0;
+        let vertices = vec![
+            Vec3::new(-0.5, -half_height, -0.5),
+            Vec3::new(0.5, -half_height, -0.5),
+            Vec3::new(0.5, -half_height, 0.5),
+            Vec3::new(-0.5, -half_height, 0.5),
+            Vec3::new(0.0, half_height, 0.0),
+        ];
+        Self::from_convex_vertices(vertices)
+    }
+
+    /// Finds the faces of the convex hull of `vertices`, which must all be hull corners
+    pub fn from_convex_vertices(vertices: Vec<Vec3>) -> Self {
+        let mut faces: Vec<Vec<usize>> = Vec::new();
+        let count = vertices.len();
+        for i in 0..count {
+            for j in i + 1..count {
+                for k in j + 1..count {
+                    let normal = (vertices[j] - vertices[i]).cross(vertices[k] - vertices[i]);
+                    if normal.length_squared() < PLANE_EPSILON {
+                        continue;
+                    }
+                    let normal = normal.normalize();
+                    let distances: Vec<f32> = vertices
+                        .iter()
+                        .map(|&v| normal.dot(v - vertices[i]))
+                        .collect();
+
+                    // Only planes with every vertex on one side bound the hull
+                    let normal = if distances.iter().all(|&d| d <= PLANE_EPSILON) {
+                        normal
+                    } else if distances.iter().all(|&d| d >= -PLANE_EPSILON) {
+                        -normal
+                    } else {
+                        continue;
+                    };
+
+                    let mut face: Vec<usize> = (0..count)
+                        .filter(|&m| distances[m].abs() <= PLANE_EPSILON)
+                        .collect();
+                    if faces.iter().any(|existing| {
+                        existing.len() == face.len() && face.iter().all(|m| existing.contains(m))
+                    }) {
+                        continue;
+                    }
+
+                    // Order the corners counter-clockwise around the outward normal
+                    let center =
+                        face.iter().map(|&m| vertices[m]).sum::<Vec3>() / face.len() as f32;
+                    let u = (vertices[face[0]] - center).normalize();
+                    let w = normal.cross(u);
+                    let angle = |m: usize| {
+                        let offset = vertices[m] - center;
+                        w.dot(offset).atan2(u.dot(offset))
+                    };
+                    face.sort_by(|&a, &b| angle(a).total_cmp(&angle(b)));
+                    faces.push(face);
+                }
+            }
+        }
+        Self { vertices, faces }
+    }
+
+    /// Outward unit normal of `face`
+    pub fn face_normal(&self, face: &[usize]) -> Vec3 {
+        let [a, b, c] = [face[0], face[1], face[2]].map(|i| self.vertices[i]);
+        (b - a).cross(c - a).normalize()
+    }
+
+    /// Builds a flat-shaded mesh with planar UVs per face and tangents
+    pub fn mesh(&self) -> Mesh {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut indices = Vec::new();
+
+        for face in &self.faces {
+            let normal = self.face_normal(face);
+            let center = face.iter().map(|&i| self.vertices[i]).sum::<Vec3>() / face.len() as f32;
+            let u = (self.vertices[face[0]] - center).normalize();
+            let w = normal.cross(u);
+
+            let base = positions.len() as u32;
+            for &i in face {
+                let offset = self.vertices[i] - center;
+                positions.push(self.vertices[i].to_array());
+                normals.push(normal.to_array());
+                uvs.push([u.dot(offset) + 0.5, w.dot(offset) + 0.5]);
+            }
+            // Faces are convex, so a fan from the first corner covers them
+            for k in 1..face.len() as u32 - 1 {
+                indices.extend_from_slice(&[base, base + k, base + k + 1]);
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh.generate_tangents()
+            .expect("polyhedron meshes always carry positions, normals and UVs");
+        mesh
+    }
+}
+
+fn cyclic_permutations(v: Vec3) -> [Vec3; 3] {
+    [v, Vec3::new(v.z, v.x, v.y), Vec3::new(v.y, v.z, v.x)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    fn all() -> [(Polyhedron, usize, usize); 6] {
+        [
+            (Polyhedron::tetrahedron(), 4, 4),
+            (Polyhedron::cube(), 8, 6),
+            (Polyhedron::octahedron(), 6, 8),
+            (Polyhedron::dodecahedron(), 20, 12),
+            (Polyhedron::icosahedron(), 12, 20),
+            (Polyhedron::square_pyramid(), 5, 5),
+        ]
+    }
+
+    #[test]
+    fn face_counts_match_solids() {
+        for (polyhedron, vertices, faces) in all() {
+            assert_eq!(polyhedron.vertices.len(), vertices);
+            assert_eq!(polyhedron.faces.len(), faces);
+        }
+    }
+
+    #[test]
+    fn edges_have_unit_length() {
+        for (polyhedron, ..) in all() {
+            for face in &polyhedron.faces {
+                for (k, &i) in face.iter().enumerate() {
+                    let j = face[(k + 1) % face.len()];
+                    let length = polyhedron.vertices[i].distance(polyhedron.vertices[j]);
+                    assert!((length - 1.0).abs() < 1e-4, "edge of length {length}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn normals_point_outwards() {
+        for (polyhedron, ..) in all() {
+            let center =
+                polyhedron.vertices.iter().sum::<Vec3>() / polyhedron.vertices.len() as f32;
+            for face in &polyhedron.faces {
+                let face_center =
+                    face.iter().map(|&i| polyhedron.vertices[i]).sum::<Vec3>() / face.len() as f32;
+                assert!(polyhedron.face_normal(face).dot(face_center - center) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn mesh_attributes_line_up() {
+        for (polyhedron, ..) in all() {
+            let mesh = polyhedron.mesh();
+            let count = mesh.count_vertices();
+            for attribute in [
+                Mesh::ATTRIBUTE_POSITION,
+                Mesh::ATTRIBUTE_NORMAL,
+                Mesh::ATTRIBUTE_UV_0,
+                Mesh::ATTRIBUTE_TANGENT,
+            ] {
+                assert_eq!(mesh.attribute(attribute).map(|a| a.len()), Some(count));
+            }
+
+            let Some(VertexAttributeValues::Float32x3(normals)) =
+                mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+            else {
+                panic!("missing normals");
+            };
+            assert!(normals
+                .iter()
+                .all(|&n| (Vec3::from(n).length() - 1.0).abs() < 1e-5));
+
+            let indices = mesh.indices().expect("missing indices");
+            assert_eq!(indices.len() % 3, 0);
+            assert!(indices.iter().all(|i| i < count));
+        }
+    }
+}
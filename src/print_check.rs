@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bevy::prelude::*;
+
+/// Corners closer than this fraction of the mesh's size are taken to be the same point
+const WELD_TOLERANCE: f32 = 1e-6;
+/// Triangles sharing an edge whose normals agree this closely lie in one face
+const COPLANAR_COSINE: f32 = 1.0 - 1e-4;
+
+/// A face's normal and the triangles covering it
+type Face = (Vec3, Vec<[Vec3; 3]>);
+
+/// Disjoint sets over `0..n`, for grouping triangles into faces and shells
+struct UnionFind(Vec<u32>);
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        Self((0..n as u32).collect())
+    }
+
+    fn find(&mut self, mut i: u32) -> u32 {
+        while self.0[i as usize] != i {
+            self.0[i as usize] = self.0[self.0[i as usize] as usize];
+            i = self.0[i as usize];
+        }
+        i
+    }
+
+    fn union(&mut self, a: u32, b: u32) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.0[a as usize] = b;
+    }
+}
+
+/// Rounds positions onto a grid fine enough that only coincident corners share a cell
+struct Quantizer(f32);
+
+impl Quantizer {
+    fn new<'a>(triangles: impl IntoIterator<Item = &'a [Vec3; 3]>) -> Self {
+        let (mut min, mut max) = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+        for corner in triangles.into_iter().flatten() {
+            min = min.min(*corner);
+            max = max.max(*corner);
+        }
+        let size = (max - min).max_element();
+        Self(if size.is_finite() && size > 0.0 {
+            size * WELD_TOLERANCE
+        } else {
+            WELD_TOLERANCE
+        })
+    }
+
+    fn key(&self, p: Vec3) -> IVec3 {
+        (p / self.0).round().as_ivec3()
+    }
+}
+
+/// Triangles sharing their corners by position, which is how slicers read an STL
+#[derive(Clone, Debug, Default)]
+pub struct PrintMesh {
+    pub positions: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl PrintMesh {
+    /// Welds the corners of `triangles` that coincide
+    pub fn new(triangles: &[[Vec3; 3]]) -> Self {
+        let quantizer = Quantizer::new(triangles);
+        let mut mesh = Self::default();
+        let mut indices = HashMap::new();
+        for triangle in triangles {
+            let corners = triangle.map(|corner| {
+                *indices.entry(quantizer.key(corner)).or_insert_with(|| {
+                    mesh.positions.push(corner);
+                    mesh.positions.len() as u32 - 1
+                })
+            });
+            mesh.triangles.push(corners);
+        }
+        mesh
+    }
+
+    /// Like `new`, after removing the faces separate parts share.
+    ///
+    /// Touching cubes each keep the face the other covers, which leaves an inside wall in the
+    /// middle of the solid. Wherever two parts' faces cover the same polygon from opposite
+    /// sides both go, and of faces repeated the same way round only one is kept. `parts`
+    /// holds the triangles of each separately placed piece.
+    ///
+    /// Faces are not clipped against each other, so a contact between faces of different
+    /// sizes or ones only partly overlapping, as a Jerusalem cube's or an n-flake's parts make,
+    /// keeps both sides as separate shells pressed together.
+    pub fn welded(parts: &[Vec<[Vec3; 3]>]) -> Self {
+        let quantizer = Quantizer::new(parts.iter().flatten());
+
+        // The faces of every part, keyed by the corners of the polygon they cover
+        let mut faces: HashMap<Vec<IVec3>, Vec<Face>> = HashMap::new();
+        for part in parts {
+            let normals: Vec<Vec3> = part
+                .iter()
+                .map(|[a, b, c]| (*b - *a).cross(*c - *a).normalize_or_zero())
+                .collect();
+            let mut sets = UnionFind::new(part.len());
+            let mut edges: HashMap<(IVec3, IVec3), Vec<u32>> = HashMap::new();
+            for (i, triangle) in part.iter().enumerate() {
+                let keys = triangle.map(|corner| quantizer.key(corner));
+                for k in 0..3 {
+                    let (a, b) = (keys[k], keys[(k + 1) % 3]);
+                    let edge = if a.to_array() < b.to_array() {
+                        (a, b)
+                    } else {
+                        (b, a)
+                    };
+                    let neighbours = edges.entry(edge).or_default();
+                    for &j in neighbours.iter() {
+                        if normals[i].dot(normals[j as usize]) > COPLANAR_COSINE {
+                            sets.union(i as u32, j);
+                        }
+                    }
+                    neighbours.push(i as u32);
+                }
+            }
+
+            let mut groups: HashMap<u32, Vec<[Vec3; 3]>> = HashMap::new();
+            for (i, triangle) in part.iter().enumerate() {
+                groups
+                    .entry(sets.find(i as u32))
+                    .or_default()
+                    .push(*triangle);
+            }
+            for (root, triangles) in groups {
+                let mut polygon: Vec<IVec3> = triangles
+                    .iter()
+                    .flatten()
+                    .map(|&corner| quantizer.key(corner))
+                    .collect();
+                polygon.sort_unstable_by_key(|key| key.to_array());
+                polygon.dedup();
+                faces
+                    .entry(polygon)
+                    .or_default()
+                    .push((normals[root as usize], triangles));
+            }
+        }
+
+        let mut kept = Vec::new();
+        for copies in faces.into_values() {
+            let reference = copies[0].0;
+            let (front, back): (Vec<_>, Vec<_>) = copies
+                .into_iter()
+                .partition(|(normal, _)| normal.dot(reference) >= 0.0);
+            // Opposite faces cancel in pairs; one of whatever is left over survives
+            let survivor = match front.len().cmp(&back.len()) {
+                std::cmp::Ordering::Greater => front.into_iter().next(),
+                std::cmp::Ordering::Less => back.into_iter().next(),
+                std::cmp::Ordering::Equal => None,
+            };
+            if let Some((_, triangles)) = survivor {
+                kept.extend(triangles);
+            }
+        }
+        Self::new(&kept)
+    }
+
+    /// The corners of every triangle
+    pub fn corners(&self) -> Vec<[Vec3; 3]> {
+        self.triangles
+            .iter()
+            .map(|triangle| triangle.map(|i| self.positions[i as usize]))
+            .collect()
+    }
+
+    /// Checks the mesh is fit to print when one of its units is `scale` millimetres and the
+    /// printer cannot make details smaller than `min_feature` millimetres
+    pub fn report(&self, scale: f32, min_feature: f32) -> PrintReport {
+        let mut report = PrintReport {
+            triangles: self.triangles.len(),
+            shortest_edge: f32::INFINITY,
+            min_feature,
+            ..default()
+        };
+
+        // For each edge, how many triangles use it and how many of those run it low to high
+        let mut edges: HashMap<(u32, u32), (u32, u32)> = HashMap::new();
+        let mut faces: HashMap<[u32; 3], u32> = HashMap::new();
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|i| self.positions[i as usize]);
+            let repeated = triangle[0] == triangle[1]
+                || triangle[1] == triangle[2]
+                || triangle[2] == triangle[0];
+            if repeated || (b - a).cross(c - a).length_squared() == 0.0 {
+                report.degenerate_faces += 1;
+            }
+            for k in 0..3 {
+                let (from, to) = (triangle[k], triangle[(k + 1) % 3]);
+                let uses = edges.entry((from.min(to), from.max(to))).or_default();
+                uses.0 += 1;
+                uses.1 += (from < to) as u32;
+            }
+            let mut sorted = *triangle;
+            sorted.sort_unstable();
+            *faces.entry(sorted).or_default() += 1;
+        }
+        report.duplicate_faces = faces.values().map(|&count| count as usize - 1).sum();
+
+        for (&(a, b), &(uses, forward)) in &edges {
+            match uses {
+                1 => report.open_edges += 1,
+                2 if forward != 1 => report.inconsistent_edges += 1,
+                2 => {}
+                _ => report.non_manifold_edges += 1,
+            }
+            let length = self.positions[a as usize].distance(self.positions[b as usize]) * scale;
+            report.shortest_edge = report.shortest_edge.min(length);
+            if length < min_feature {
+                report.thin_edges += 1;
+            }
+        }
+
+        report.non_manifold_vertices = self.non_manifold_vertices();
+        report.inside_out_shells = self.inside_out_shells();
+        report
+    }
+
+    /// Vertices whose triangles do not form a single fan, such as where two cubes meet at a
+    /// corner
+    fn non_manifold_vertices(&self) -> usize {
+        let mut incident: Vec<Vec<u32>> = vec![Vec::new(); self.positions.len()];
+        for (face, triangle) in self.triangles.iter().enumerate() {
+            for &corner in triangle {
+                incident[corner as usize].push(face as u32);
+            }
+        }
+
+        incident
+            .iter()
+            .enumerate()
+            .filter(|(vertex, faces)| {
+                // Join triangles around the vertex that share one of the edges leaving it
+                let mut sets = UnionFind::new(faces.len());
+                let mut first_with: HashMap<u32, u32> = HashMap::new();
+                for (local, &face) in faces.iter().enumerate() {
+                    for &other in &self.triangles[face as usize] {
+                        if other as usize == *vertex {
+                            continue;
+                        }
+                        match first_with.get(&other) {
+                            Some(&earlier) => sets.union(local as u32, earlier),
+                            None => {
+                                first_with.insert(other, local as u32);
+                            }
+                        }
+                    }
+                }
+                let fans = (0..faces.len() as u32)
+                    .filter(|&i| sets.find(i) == i)
+                    .count();
+                fans > 1
+            })
+            .count()
+    }
+
+    /// Connected pieces enclosing negative volume, which have every normal pointing inwards
+    fn inside_out_shells(&self) -> usize {
+        let mut sets = UnionFind::new(self.positions.len());
+        for triangle in &self.triangles {
+            sets.union(triangle[0], triangle[1]);
+            sets.union(triangle[1], triangle[2]);
+        }
+        let mut volumes: HashMap<u32, f32> = HashMap::new();
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|i| self.positions[i as usize]);
+            *volumes.entry(sets.find(triangle[0])).or_default() += a.dot(b.cross(c)) / 6.0;
+        }
+        volumes.values().filter(|&&volume| volume < 0.0).count()
+    }
+}
+
+/// What stands between a mesh and a clean print
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrintReport {
+    pub triangles: usize,
+    /// Edges only one triangle uses, leaving a hole
+    pub open_edges: usize,
+    /// Edges more than two triangles use
+    pub non_manifold_edges: usize,
+    /// Vertices where separate fans of triangles touch
+    pub non_manifold_vertices: usize,
+    /// Edges whose two triangles disagree on which side is out, so one of them is inverted
+    pub inconsistent_edges: usize,
+    /// Connected pieces turned inside out as a whole
+    pub inside_out_shells: usize,
+    pub duplicate_faces: usize,
+    /// Triangles with no area
+    pub degenerate_faces: usize,
+    /// Length of the shortest edge in millimetres
+    pub shortest_edge: f32,
+    /// Edges shorter than `min_feature`
+    pub thin_edges: usize,
+    /// Smallest detail the printer can make, in millimetres
+    pub min_feature: f32,
+}
+
+impl PrintReport {
+    pub fn is_manifold(&self) -> bool {
+        self.open_edges == 0 && self.non_manifold_edges == 0 && self.non_manifold_vertices == 0
+    }
+
+    pub fn is_printable(&self) -> bool {
+        self.is_manifold()
+            && self.inconsistent_edges == 0
+            && self.inside_out_shells == 0
+            && self.duplicate_faces == 0
+            && self.degenerate_faces == 0
+            && self.thin_edges == 0
+    }
+}
+
+impl fmt::Display for PrintReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = if self.is_printable() {
+            "ready to print"
+        } else if self.is_manifold() {
+            "manifold, with problems"
+        } else {
+            "not manifold"
+        };
+        writeln!(f, "{} triangles, {verdict}", self.triangles)?;
+        writeln!(f, "  open edges:            {}", self.open_edges)?;
+        writeln!(f, "  non-manifold edges:    {}", self.non_manifold_edges)?;
+        writeln!(f, "  non-manifold vertices: {}", self.non_manifold_vertices)?;
+        writeln!(
+            f,
+            "  inverted normals:      {} edges",
+            self.inconsistent_edges
+        )?;
+        writeln!(f, "  inside-out shells:     {}", self.inside_out_shells)?;
+        writeln!(f, "  duplicate faces:       {}", self.duplicate_faces)?;
+        writeln!(f, "  degenerate faces:      {}", self.degenerate_faces)?;
+        write!(
+            f,
+            "  shortest edge:         {:.3} mm, {} edges under {} mm",
+            self.shortest_edge, self.thin_edges, self.min_feature
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh_builder::mesh_triangles;
+    use crate::primitives::Polyhedron;
+
+    fn cube_at(offset: Vec3) -> Vec<[Vec3; 3]> {
+        mesh_triangles(&Polyhedron::cube().mesh())
+            .into_iter()
+            .map(|triangle| triangle.map(|corner| corner + offset))
+            .collect()
+    }
+
+    #[test]
+    fn a_cube_is_printable() {
+        let report = PrintMesh::new(&cube_at(Vec3::ZERO)).report(10.0, 0.4);
+        assert!(report.is_printable(), "{report}");
+        assert_eq!(report.shortest_edge, 10.0);
+    }
+
+    #[test]
+    fn touching_cubes_weld_into_one_box() {
+        let parts = vec![cube_at(Vec3::ZERO), cube_at(Vec3::X)];
+
+        let unwelded = PrintMesh::new(&parts.concat()).report(1.0, 0.4);
+        assert!(!unwelded.is_manifold());
+
+        let welded = PrintMesh::welded(&parts);
+        let report = welded.report(1.0, 0.4);
+        assert!(report.is_printable(), "{report}");
+        assert_eq!(report.triangles, 20);
+    }
+
+    #[test]
+    fn only_identical_faces_are_welded() {
+        // A unit cube resting on a larger one, and one sliding half off its neighbour
+        let large: Vec<_> = cube_at(Vec3::ZERO)
+            .into_iter()
+            .map(|triangle| triangle.map(|corner| corner * 2.0))
+            .collect();
+        for parts in [
+            vec![large, cube_at(Vec3::Y * 1.5)],
+            vec![cube_at(Vec3::ZERO), cube_at(Vec3::new(1.0, 0.5, 0.0))],
+        ] {
+            // Neither contact covers the same polygon from both sides, so every face stays
+            assert_eq!(PrintMesh::welded(&parts).triangles.len(), 24);
+        }
+    }
+
+    #[test]
+    fn finds_flipped_duplicated_and_thin_faces() {
+        let mut flipped = cube_at(Vec3::ZERO);
+        flipped[0].swap(1, 2);
+        let report = PrintMesh::new(&flipped).report(1.0, 0.4);
+        assert_eq!(report.inconsistent_edges, 3);
+        assert!(report.is_manifold() && !report.is_printable());
+
+        let mut duplicated = cube_at(Vec3::ZERO);
+        duplicated.push(duplicated[3]);
+        assert_eq!(
+            PrintMesh::new(&duplicated).report(1.0, 0.4).duplicate_faces,
+            1
+        );
+
+        // Twelve unit edges and six diagonals, all under two millimetres
+        let report = PrintMesh::new(&cube_at(Vec3::ZERO)).report(1.0, 2.0);
+        assert_eq!(report.thin_edges, 18);
+
+        let inside_out: Vec<_> = cube_at(Vec3::ZERO)
+            .into_iter()
+            .map(|[a, b, c]| [a, c, b])
+            .collect();
+        assert_eq!(
+            PrintMesh::new(&inside_out)
+                .report(1.0, 0.4)
+                .inside_out_shells,
+            1
+        );
+    }
+}
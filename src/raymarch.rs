@@ -0,0 +1,198 @@
+// encase's `ShaderType` derive emits a never-called `check` function per field, beside the
+// struct rather than inside it, so only a file-wide allow reaches them
+#![allow(dead_code)]
+
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey, NotShadowCaster};
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::render::mesh::MeshVertexBufferLayout;
+use bevy::render::primitives::Aabb;
+use bevy::render::render_resource::{
+    AsBindGroup, Face, RenderPipelineDescriptor, ShaderRef, ShaderType,
+    SpecializedMeshPipelineError,
+};
+
+use crate::distance_estimators::{
+    Estimator, Mandelbox, Mandelbulb, MengerSponge, QuaternionJulia, SierpinskiTetrahedron,
+};
+
+/// Marching settings shared by every estimator, mirrored by `RaymarchSettings` in the shader
+#[derive(Clone, Copy, Debug, ShaderType)]
+pub struct RaymarchSettings {
+    pub base_color: Vec4,
+    /// Index of the `Estimator` variant to march
+    pub kind: u32,
+    pub max_steps: u32,
+    pub hit_epsilon: f32,
+    /// Half the width of the cube the fractal is drawn in, in the fractal's own units
+    pub bounds: f32,
+    pub perceptual_roughness: f32,
+    pub metallic: f32,
+}
+
+/// Draws a distance-estimated fractal by raymarching it inside a cube mesh.
+///
+/// Every estimator has its own uniform so the shader can read the parameters in exactly the
+/// layout the CPU estimators use; only the one selected by `settings.kind` is looked at.
+#[derive(AsBindGroup, TypeUuid, TypePath, Clone, Debug)]
+#[uuid = "5b8e6f0a-3c2d-4e71-9a4b-7d1f0c6e2a93"]
+pub struct RaymarchMaterial {
+    // Bindings start high to stay clear of the `StandardMaterial` ones the PBR functions declare
+    #[uniform(100)]
+    pub settings: RaymarchSettings,
+    #[uniform(101)]
+    pub mandelbulb: Mandelbulb,
+    #[uniform(102)]
+    pub mandelbox: Mandelbox,
+    #[uniform(103)]
+    pub julia: QuaternionJulia,
+    #[uniform(104)]
+    pub menger: MengerSponge,
+    #[uniform(105)]
+    pub sierpinski: SierpinskiTetrahedron,
+}
+
+impl From<&RaymarchedFractal> for RaymarchMaterial {
+    fn from(fractal: &RaymarchedFractal) -> Self {
+        let mut material = Self {
+            settings: RaymarchSettings {
+                base_color: fractal.color.as_linear_rgba_f32().into(),
+                kind: 0,
+                max_steps: fractal.max_steps,
+                hit_epsilon: fractal.hit_epsilon,
+                bounds: fractal.estimator.bounds(),
+                perceptual_roughness: fractal.perceptual_roughness,
+                metallic: fractal.metallic,
+            },
+            mandelbulb: default(),
+            mandelbox: default(),
+            julia: default(),
+            menger: default(),
+            sierpinski: default(),
+        };
+        material.settings.kind = match fractal.estimator {
+            Estimator::Mandelbulb(de) => {
+                material.mandelbulb = de;
+                0
+            }
+            Estimator::Mandelbox(de) => {
+                material.mandelbox = de;
+                1
+            }
+            Estimator::QuaternionJulia(de) => {
+                material.julia = de;
+                2
+            }
+            Estimator::MengerSponge(de) => {
+                material.menger = de;
+                3
+            }
+            Estimator::SierpinskiTetrahedron(de) => {
+                material.sierpinski = de;
+                4
+            }
+        };
+        material
+    }
+}
+
+impl Material for RaymarchMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/raymarch.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // Drawing the inside of the cube keeps the fractal visible with the camera inside it
+        descriptor.primitive.cull_mode = Some(Face::Front);
+        Ok(())
+    }
+}
+
+/// A fractal rendered on the GPU straight from its distance estimator; changing it updates
+/// the shader's uniforms in place
+#[derive(Component, Clone, Debug)]
+pub struct RaymarchedFractal {
+    pub estimator: Estimator,
+    pub color: Color,
+    pub perceptual_roughness: f32,
+    pub metallic: f32,
+    /// Most steps a ray takes before giving up on hitting anything
+    pub max_steps: u32,
+    /// How close, in the fractal's own units, a ray must get to count as a hit
+    pub hit_epsilon: f32,
+}
+
+impl RaymarchedFractal {
+    pub fn new(estimator: Estimator) -> Self {
+        Self {
+            estimator,
+            color: Color::rgb(0.8, 0.7, 0.6),
+            perceptual_roughness: 0.6,
+            metallic: 0.0,
+            max_steps: 128,
+            hit_epsilon: 1e-3,
+        }
+    }
+}
+
+/// Everything needed to spawn a raymarched fractal; the cube and material are added for it
+#[derive(Bundle)]
+pub struct RaymarchedFractalBundle {
+    pub fractal: RaymarchedFractal,
+    pub spatial: SpatialBundle,
+    /// The cube would otherwise cast a cube-shaped shadow
+    pub not_shadow_caster: NotShadowCaster,
+}
+
+impl RaymarchedFractalBundle {
+    pub fn new(fractal: RaymarchedFractal, transform: Transform) -> Self {
+        Self {
+            fractal,
+            spatial: SpatialBundle::from_transform(transform),
+            not_shadow_caster: NotShadowCaster,
+        }
+    }
+}
+
+/// Creates or updates the bounding cube and material of every changed raymarched fractal
+fn update_raymarched_fractals(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<RaymarchMaterial>>,
+    query: Query<(Entity, &RaymarchedFractal), Changed<RaymarchedFractal>>,
+    handles: Query<&Handle<RaymarchMaterial>>,
+) {
+    for (entity, fractal) in query.iter() {
+        let material = RaymarchMaterial::from(fractal);
+        let cube = meshes.add(shape::Cube::new(2.0 * material.settings.bounds).into());
+        // Dropping the old bounding box has it recomputed for the new cube
+        commands.entity(entity).insert(cube).remove::<Aabb>();
+        match handles
+            .get(entity)
+            .ok()
+            .and_then(|handle| materials.get_mut(handle))
+        {
+            Some(existing) => *existing = material,
+            None => {
+                commands.entity(entity).insert(materials.add(material));
+            }
+        }
+    }
+}
+
+/// Renders `RaymarchedFractal`s alongside the meshed fractals
+pub struct RaymarchPlugin;
+impl Plugin for RaymarchPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<RaymarchMaterial> {
+            prepass_enabled: false,
+            ..default()
+        })
+        .add_systems(Update, update_raymarched_fractals);
+    }
+}
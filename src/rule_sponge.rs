@@ -0,0 +1,413 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::generator::{
+    expand_instances, FractalGenerator, FractalGeometry, ParamSpec, ParamValues, SeedShape,
+};
+use crate::voxel::{mesh_voxels, subdivision_parts};
+
+/// Finest voxel grid the merged mesher is asked to walk, matching a depth 5 Menger sponge
+const MAX_RESOLUTION: usize = 243;
+
+/// Upper bound on seed copies when the sponge is built from instances
+const MAX_INSTANCES: usize = 1 << 21;
+
+/// Which cells of an `n×n×n` subdivision survive each round of a sponge-like fractal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpongePattern {
+    size: u32,
+    /// Bit `x + size * (y + size * z)` is set for every kept cell
+    mask: u128,
+}
+
+impl SpongePattern {
+    pub const MIN_SIZE: u32 = 2;
+    pub const MAX_SIZE: u32 = 5;
+
+    /// A pattern keeping the cells for which `keep` returns true
+    pub fn from_fn(size: u32, keep: impl Fn(UVec3) -> bool) -> Self {
+        let size = size.clamp(Self::MIN_SIZE, Self::MAX_SIZE);
+        let mut pattern = Self { size, mask: 0 };
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let cell = UVec3::new(x, y, z);
+                    if keep(cell) {
+                        pattern.mask |= 1 << pattern.bit(cell);
+                    }
+                }
+            }
+        }
+        pattern
+    }
+
+    /// Keeps the 20 cells with at most one coordinate in the middle
+    pub fn menger() -> Self {
+        Self::from_fn(3, |cell| {
+            cell.to_array().iter().filter(|&&c| c == 1).count() <= 1
+        })
+    }
+
+    /// Keeps only the 8 corners
+    pub fn cantor_dust() -> Self {
+        Self::from_fn(3, |cell| cell.to_array().iter().all(|&c| c != 1))
+    }
+
+    /// Drops the 8 corners and the centre
+    pub fn mosely_snowflake() -> Self {
+        Self::from_fn(3, |cell| {
+            let centred = cell.to_array().iter().filter(|&&c| c == 1).count();
+            centred != 0 && centred != 3
+        })
+    }
+
+    /// Keeps the centre and the 6 face centres
+    pub fn vicsek() -> Self {
+        Self::from_fn(3, |cell| {
+            cell.to_array().iter().filter(|&&c| c == 1).count() >= 2
+        })
+    }
+
+    /// Looks up a built-in pattern by name
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "menger" => Some(Self::menger()),
+            "cantor_dust" => Some(Self::cantor_dust()),
+            "mosely_snowflake" => Some(Self::mosely_snowflake()),
+            "vicsek" => Some(Self::vicsek()),
+            "inverted_menger" => Some(Self::menger().inverted()),
+            _ => None,
+        }
+    }
+
+    /// Swaps kept and removed cells
+    pub fn inverted(self) -> Self {
+        let cells = self.size.pow(3);
+        Self {
+            size: self.size,
+            mask: !self.mask & (u128::MAX >> (128 - cells)),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn keeps(&self, cell: UVec3) -> bool {
+        self.mask & (1 << self.bit(cell)) != 0
+    }
+
+    fn bit(&self, cell: UVec3) -> u32 {
+        cell.x + self.size * (cell.y + self.size * cell.z)
+    }
+
+    /// Whether a cell of the `size^depth` grid survives every round of subdivision
+    pub fn is_solid(&self, cell: UVec3, depth: u32) -> bool {
+        let mut cell = cell;
+        for _ in 0..depth {
+            if !self.keeps(cell % self.size) {
+                return false;
+            }
+            cell /= self.size;
+        }
+        true
+    }
+
+    /// Kept cells as transforms of a unit cube centred on the origin
+    pub fn children(&self) -> Vec<Transform> {
+        let scale = 1.0 / self.size as f32;
+        let center = (self.size as f32 - 1.0) / 2.0;
+        (0..self.size.pow(3))
+            .map(|i| {
+                UVec3::new(
+                    i % self.size,
+                    i / self.size % self.size,
+                    i / self.size.pow(2),
+                )
+            })
+            .filter(|&cell| self.keeps(cell))
+            .map(|cell| {
+                Transform::from_translation((cell.as_vec3() - center) * scale)
+                    .with_scale(Vec3::splat(scale))
+            })
+            .collect()
+    }
+}
+
+/// Why a textual pattern could not be read
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatternError {
+    /// The grid is not `n` layers of `n` rows of `n` cells, with `n` in `2..=5`
+    Shape,
+    /// A cell was neither `#` nor `.`
+    Cell(char),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatternError::Shape => write!(
+                f,
+                "pattern must be n layers of n rows of n cells with n between {} and {}",
+                SpongePattern::MIN_SIZE,
+                SpongePattern::MAX_SIZE
+            ),
+            PatternError::Cell(c) => write!(f, "unexpected cell `{c}`, expected `#` or `.`"),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// Patterns are written as layers of increasing `y` separated by blank lines, each layer as
+/// rows of increasing `z` and each row as cells of increasing `x`, `#` for kept and `.` for
+/// removed. Lines starting with `//` are comments. For one-line sharing, `,` may stand in for a
+/// line break and `/` for a blank line, as in `###,#.#,###/#.#,...,#.#/###,#.#,###`.
+impl FromStr for SpongePattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Comments go first, so the `/` and `,` in them are not read as breaks
+        let rows = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with("//"))
+            .flat_map(|line| {
+                line.split('/').enumerate().flat_map(|(i, layer)| {
+                    (i > 0).then_some("").into_iter().chain(layer.split(','))
+                })
+            });
+        let mut layers: Vec<Vec<&str>> = vec![Vec::new()];
+        for line in rows.map(str::trim) {
+            if line.is_empty() {
+                if !layers.last().unwrap().is_empty() {
+                    layers.push(Vec::new());
+                }
+                continue;
+            }
+            layers.last_mut().unwrap().push(line);
+        }
+        layers.retain(|layer| !layer.is_empty());
+
+        let size = layers.len();
+        let square = layers.iter().all(|layer| {
+            layer.len() == size && layer.iter().all(|row| row.chars().count() == size)
+        });
+        if !square || !(Self::MIN_SIZE..=Self::MAX_SIZE).contains(&(size as u32)) {
+            return Err(PatternError::Shape);
+        }
+
+        let mut pattern = Self {
+            size: size as u32,
+            mask: 0,
+        };
+        for (y, layer) in layers.iter().enumerate() {
+            for (z, row) in layer.iter().enumerate() {
+                for (x, c) in row.chars().enumerate() {
+                    match c {
+                        '#' => {
+                            pattern.mask |=
+                                1 << pattern.bit(UVec3::new(x as u32, y as u32, z as u32))
+                        }
+                        '.' => {}
+                        c => return Err(PatternError::Cell(c)),
+                    }
+                }
+            }
+        }
+        Ok(pattern)
+    }
+}
+
+impl fmt::Display for SpongePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in 0..self.size {
+            if y > 0 {
+                writeln!(f)?;
+            }
+            for z in 0..self.size {
+                for x in 0..self.size {
+                    let kept = self.keeps(UVec3::new(x, y, z));
+                    write!(f, "{}", if kept { '#' } else { '.' })?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A sponge whose subdivision grid and kept cells come from a `SpongePattern`, covering the
+/// Menger sponge, Cantor dust, Mosely snowflake, Vicsek fractal and any custom rule
+pub struct RuleSponge;
+
+impl FractalGenerator for RuleSponge {
+    fn name(&self) -> &'static str {
+        "rule_sponge"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::int("depth", 0..=5, 3),
+            ParamSpec::float("size", 0.1..=100.0, 3.0),
+            ParamSpec::pattern("pattern", SpongePattern::menger()),
+            ParamSpec::bool("merged", true),
+            ParamSpec::seed("seed", SeedShape::Cube),
+        ]
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let pattern = params.pattern("pattern");
+        let size = params.float("size");
+        let depth = params.int("depth") as u32;
+
+        let seed = params.seed("seed");
+        if params.bool("merged") && seed == SeedShape::Cube {
+            let depth = capped_depth(
+                depth,
+                pattern.size() as usize,
+                MAX_RESOLUTION,
+                "cells across",
+            );
+            let (parts, part) =
+                subdivision_parts(pattern.size(), depth, |cell| pattern.keeps(cell));
+            let chunks = mesh_voxels(
+                pattern.size().pow(depth),
+                size,
+                |cell| pattern.is_solid(cell, depth),
+                part,
+            );
+            return FractalGeometry::MergedInstances { chunks, parts };
+        }
+
+        let children = pattern.children();
+        let depth = capped_depth(depth, children.len(), MAX_INSTANCES, "copies");
+        let geometry = FractalGeometry::Instanced {
+            seed: seed.mesh(),
+            instances: expand_instances(Transform::from_scale(Vec3::splat(size)), &children, depth),
+            branching: children.len(),
+        };
+        if params.bool("merged") {
+            geometry.merged()
+        } else {
+            geometry
+        }
+    }
+}
+
+/// Deepest level up to `depth` at which `per_level` to its power stays within `limit`, warning
+/// when that is shallower than asked for
+fn capped_depth(depth: u32, per_level: usize, limit: usize, unit: &str) -> u32 {
+    let mut capped = depth;
+    while capped > 0
+        && per_level
+            .checked_pow(capped)
+            .is_none_or(|count| count > limit)
+    {
+        capped -= 1;
+    }
+    if capped < depth {
+        warn!("Rule sponge depth {depth} capped at {capped} to stay within {limit} {unit}");
+    }
+    capped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::ParamValue;
+
+    #[test]
+    fn patterns_parse_with_comments() {
+        let text = "// vicsek, with a / in the comment\n\
+                    ...\n.#.\n...\n\n\
+                    .#.\n###\n.#.\n\n\
+                    // the top layer\n\
+                    ...\n.#.\n...\n";
+        assert_eq!(text.parse(), Ok(SpongePattern::vicsek()));
+        let one_line = "...,.#.,.../.#.,###,.#./...,.#.,...";
+        assert_eq!(one_line.parse(), Ok(SpongePattern::vicsek()));
+    }
+
+    #[test]
+    fn malformed_patterns_are_rejected() {
+        assert_eq!(
+            "##,#x/##,##".parse::<SpongePattern>(),
+            Err(PatternError::Cell('x'))
+        );
+        // Cells are counted as characters, not bytes
+        assert_eq!(
+            "##,#é/##,##".parse::<SpongePattern>(),
+            Err(PatternError::Cell('é'))
+        );
+        assert_eq!(
+            "##,##/##,#".parse::<SpongePattern>(),
+            Err(PatternError::Shape)
+        );
+        assert_eq!("#".parse::<SpongePattern>(), Err(PatternError::Shape));
+    }
+
+    #[test]
+    fn patterns_round_trip_through_text() {
+        for name in [
+            "menger",
+            "cantor_dust",
+            "mosely_snowflake",
+            "vicsek",
+            "inverted_menger",
+        ] {
+            let pattern = SpongePattern::preset(name).unwrap();
+            assert_eq!(pattern.to_string().parse(), Ok(pattern), "{name}");
+        }
+        let odd = SpongePattern::from_fn(4, |cell| (cell.x + cell.y * cell.z) % 3 == 0);
+        assert_eq!(odd.to_string().parse(), Ok(odd));
+    }
+
+    #[test]
+    fn depth_is_only_capped_where_the_mesher_needs_it() {
+        // Two cells out of 64, so deep instanced sponges stay small while the grid does not
+        let pattern = SpongePattern::from_fn(4, |cell| cell == UVec3::ZERO || cell == UVec3::ONE);
+        let mut values = ParamValues::default();
+        values
+            .set("depth", ParamValue::Int(5))
+            .set("pattern", ParamValue::Pattern(pattern));
+        let generate = |merged: bool| {
+            let mut values = values.clone();
+            values.set("merged", ParamValue::Bool(merged));
+            RuleSponge.generate(&values.resolve(&RuleSponge.params()))
+        };
+
+        // 4^4 cells would be too fine a grid, so the voxel mesher stops at 4^3
+        let FractalGeometry::MergedInstances { parts, .. } = generate(true) else {
+            panic!("expected a voxel mesh");
+        };
+        assert_eq!(parts.count, 2usize.pow(3));
+        let FractalGeometry::Instanced { instances, .. } = generate(false) else {
+            panic!("expected instances");
+        };
+        assert_eq!(instances.len(), 2usize.pow(5));
+    }
+
+    #[test]
+    fn presets_keep_the_expected_cells() {
+        let kept = |pattern: SpongePattern| pattern.children().len();
+        assert_eq!(kept(SpongePattern::menger()), 20);
+        assert_eq!(kept(SpongePattern::mosely_snowflake()), 18);
+
+        let dust = SpongePattern::cantor_dust();
+        assert_eq!(kept(dust), 8);
+        assert!(dust.keeps(UVec3::new(2, 0, 2)) && !dust.keeps(UVec3::new(1, 0, 0)));
+
+        let vicsek = SpongePattern::vicsek();
+        assert_eq!(kept(vicsek), 7);
+        assert!(vicsek.keeps(UVec3::ONE) && vicsek.keeps(UVec3::new(1, 1, 0)));
+        assert!(!vicsek.keeps(UVec3::new(1, 0, 0)));
+
+        // Inverting keeps the centre and the six face centres the Menger sponge removes
+        let inverted = SpongePattern::menger().inverted();
+        assert_eq!(inverted, vicsek);
+        assert_eq!(inverted.inverted(), SpongePattern::menger());
+    }
+}
@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+
+use crate::generator::{
+    FractalGenerator, FractalGeometry, MergedParts, ParamSpec, ParamValues, SeedShape,
+};
+use crate::mesh_builder::ChunkedMeshBuilder;
+
+/// Triangles of the tetrahedron, wound counter-clockwise when seen from outside
+pub const TETRAHEDRON_FACES: [[usize; 3]; 4] = [[0, 1, 2], [0, 2, 3], [0, 3, 1], [1, 3, 2]];
+
+/// Corners of the regular tetrahedron with unit edges that the fractal is carved from
+pub fn tetrahedron_vertices() -> [Vec3; 4] {
+    [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.5, 0.0, 3.0f32.sqrt() / 2.0),
+        Vec3::new(0.5, 6.0f32.sqrt() / 3.0, 3.0f32.sqrt() / 6.0),
+    ]
+}
+
+/// Splits a tetrahedron into four copies scaled by `ratio`, each sharing one corner with it
+pub fn subdivide(corners: [Vec3; 4], ratio: f32) -> [[Vec3; 4]; 4] {
+    // Child `i` is the parent shrunk towards its corner `i`; at a ratio of one half its corners
+    // are that corner and the midpoints of the three edges leaving it
+    [0, 1, 2, 3].map(|i| corners.map(|corner| corners[i].lerp(corner, ratio)))
+}
+
+/// Every tetrahedron left after `depth` rounds of subdivision, `4^depth` in total
+pub fn sierpinski_tetrahedra(corners: [Vec3; 4], depth: u32, ratio: f32) -> Vec<[Vec3; 4]> {
+    let mut tetrahedra = vec![corners];
+    for _ in 0..depth {
+        tetrahedra = tetrahedra
+            .into_iter()
+            .flat_map(|parent| subdivide(parent, ratio))
+            .collect();
+    }
+    tetrahedra
+}
+
+/// Builds a Sierpinski tetrahedron with edge length `size` as merged meshes with flat normals,
+/// each face tagged with the tetrahedron it belongs to
+pub fn build_sierpinski_tetrahedron(depth: u32, size: f32) -> Vec<Mesh> {
+    let base = tetrahedron_vertices().map(|corner| corner * size);
+    let uvs = [
+        Vec2::new(0.0, 0.0),
+        Vec2::new(1.0, 0.0),
+        Vec2::new(0.5, 1.0),
+    ];
+
+    let mut builder = ChunkedMeshBuilder::default();
+    for (index, corners) in sierpinski_tetrahedra(base, depth, 0.5)
+        .into_iter()
+        .enumerate()
+    {
+        let chunk = builder.current();
+        for face in TETRAHEDRON_FACES {
+            let triangle = face.map(|i| corners[i]);
+            let normal = (triangle[1] - triangle[0])
+                .cross(triangle[2] - triangle[0])
+                .normalize();
+            chunk.push_triangle(triangle, normal, uvs);
+        }
+        chunk.finish_part(index as u32);
+    }
+    builder.build()
+}
+
+/// Recursively keeps the four corner copies of a tetrahedron at half its size
+pub struct SierpinskiTetrahedron;
+
+impl FractalGenerator for SierpinskiTetrahedron {
+    fn name(&self) -> &'static str {
+        "sierpinski_tetrahedron"
+    }
+
+    fn params(&self) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::int("depth", 0..=8, 4),
+            ParamSpec::float("size", 0.1..=100.0, 3.0),
+            ParamSpec::float("scale_ratio", 0.1..=0.7, 0.5),
+            ParamSpec::bool("merged", true),
+            ParamSpec::seed("seed", SeedShape::Tetrahedron),
+        ]
+    }
+
+    fn generate(&self, params: &ParamValues) -> FractalGeometry {
+        let depth = params.int("depth") as u32;
+        let size = params.float("size");
+        let ratio = params.float("scale_ratio");
+        let seed = params.seed("seed");
+        if params.bool("merged") && ratio == 0.5 && seed == SeedShape::Tetrahedron {
+            return FractalGeometry::MergedInstances {
+                chunks: build_sierpinski_tetrahedron(depth, size),
+                parts: MergedParts {
+                    count: 4usize.pow(depth),
+                    branching: 4,
+                },
+            };
+        }
+
+        let unit = tetrahedron_vertices();
+        let scale = size * ratio.powi(depth as i32);
+        let instances = sierpinski_tetrahedra(unit.map(|corner| corner * size), depth, ratio)
+            .into_iter()
+            .map(|corners| {
+                Transform::from_translation(corners[0] - unit[0] * scale)
+                    .with_scale(Vec3::splat(scale))
+            })
+            .collect();
+        let geometry = FractalGeometry::Instanced {
+            seed: seed.mesh(),
+            instances,
+            branching: 4,
+        };
+        if params.bool("merged") {
+            geometry.merged()
+        } else {
+            geometry
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    const EPSILON: f32 = 1e-5;
+
+    /// Barycentric coordinates of `point` with respect to the tetrahedron `corners`
+    fn barycentric(corners: [Vec3; 4], point: Vec3) -> [f32; 4] {
+        let [a, b, c, d] = corners;
+        let inverse = Mat3::from_cols(b - a, c - a, d - a).inverse();
+        let Vec3 { x, y, z } = inverse * (point - a);
+        [1.0 - x - y - z, x, y, z]
+    }
+
+    fn on_hull(corners: [Vec3; 4], point: Vec3) -> bool {
+        let weights = barycentric(corners, point);
+        weights.iter().all(|&w| w >= -EPSILON) && weights.iter().any(|&w| w.abs() <= EPSILON)
+    }
+
+    #[test]
+    fn emits_four_to_the_depth_copies() {
+        for depth in 0..=5 {
+            let vertex_count: usize = build_sierpinski_tetrahedron(depth, 1.0)
+                .iter()
+                .map(Mesh::count_vertices)
+                .sum();
+            // Four flat-shaded triangles per copy
+            assert_eq!(vertex_count, 4usize.pow(depth) * 12);
+        }
+    }
+
+    #[test]
+    fn children_sit_on_parent_corners_and_hull() {
+        let mut parents = vec![tetrahedron_vertices()];
+        for _ in 0..4 {
+            let mut next = Vec::new();
+            for parent in parents {
+                for (i, child) in subdivide(parent, 0.5).into_iter().enumerate() {
+                    assert!(child[i].distance(parent[i]) <= EPSILON);
+                    assert!(child.iter().all(|&corner| on_hull(parent, corner)));
+                    next.push(child);
+                }
+            }
+            parents = next;
+        }
+    }
+
+    #[test]
+    fn merged_mesh_stays_inside_base() {
+        let base = tetrahedron_vertices().map(|corner| corner * 2.0);
+        for mesh in build_sierpinski_tetrahedron(3, 2.0) {
+            let Some(VertexAttributeValues::Float32x3(positions)) =
+                mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+            else {
+                panic!("missing positions");
+            };
+            for &position in positions {
+                let weights = barycentric(base, Vec3::from(position));
+                assert!(weights.iter().all(|&w| w >= -EPSILON));
+            }
+        }
+    }
+}
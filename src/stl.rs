@@ -0,0 +1,76 @@
+use std::io::{self, Write};
+
+use bevy::prelude::*;
+
+/// Written at the start of every file; it must not begin with `solid`, which marks ASCII STL
+const HEADER: &[u8] = b"bevy_3d_fractals binary STL";
+
+/// Writes `triangles` as binary STL: an 80-byte header, the triangle count, then a facet
+/// normal, three corners and an unused attribute word for every triangle
+pub fn write_binary(triangles: &[[Vec3; 3]], writer: &mut impl Write) -> io::Result<()> {
+    let count = u32::try_from(triangles.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many triangles for STL"))?;
+
+    let mut header = [0; 80];
+    header[..HEADER.len()].copy_from_slice(HEADER);
+    writer.write_all(&header)?;
+    writer.write_all(&count.to_le_bytes())?;
+    for &[a, b, c] in triangles {
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        for vector in [normal, a, b, c] {
+            for component in vector.to_array() {
+                writer.write_all(&component.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&[0; 2])?;
+    }
+    Ok(())
+}
+
+/// Writes `triangles` as ASCII STL, a solid called `name` of one facet per triangle
+pub fn write_ascii(triangles: &[[Vec3; 3]], name: &str, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "solid {name}")?;
+    for &[a, b, c] in triangles {
+        let [nx, ny, nz] = (b - a).cross(c - a).normalize_or_zero().to_array();
+        writeln!(writer, "  facet normal {nx:e} {ny:e} {nz:e}")?;
+        writeln!(writer, "    outer loop")?;
+        for corner in [a, b, c] {
+            let [x, y, z] = corner.to_array();
+            writeln!(writer, "      vertex {x:e} {y:e} {z:e}")?;
+        }
+        writeln!(writer, "    endloop")?;
+        writeln!(writer, "  endfacet")?;
+    }
+    writeln!(writer, "endsolid {name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh_builder::mesh_triangles;
+
+    #[test]
+    fn binary_layout() {
+        let cube = Mesh::from(shape::Cube::new(1.0));
+        let triangles = mesh_triangles(&cube).repeat(2);
+        let mut bytes = Vec::new();
+        write_binary(&triangles, &mut bytes).unwrap();
+
+        assert_eq!(bytes.len(), 84 + 24 * 50);
+        assert_eq!(u32::from_le_bytes(bytes[80..84].try_into().unwrap()), 24);
+        assert!(!bytes.starts_with(b"solid"));
+    }
+
+    #[test]
+    fn ascii_layout() {
+        let triangles = mesh_triangles(&Mesh::from(shape::Cube::new(1.0)));
+        let mut bytes = Vec::new();
+        write_ascii(&triangles, "cube", &mut bytes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+
+        assert!(text.starts_with("solid cube\n"));
+        assert!(text.ends_with("endsolid cube\n"));
+        assert_eq!(text.matches("facet normal").count(), 12);
+        assert_eq!(text.matches("vertex").count(), 36);
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use bevy::prelude::*;
+use bevy::render::mesh::{PrimitiveTopology, VertexAttributeValues};
+
+use crate::export::ExportScene;
+use crate::mesh_builder::mesh_triangles;
+use crate::ply::srgb_bytes;
+
+/// MagicaVoxel models cannot be larger than this along any axis
+pub const MAX_VOX_RESOLUTION: u32 = 256;
+
+/// Solid cells of a cube of `resolution`³ voxels laid over some geometry, each with an sRGB
+/// colour. Cell coordinates are Bevy's, Y up.
+#[derive(Clone, Debug, Default)]
+pub struct VoxelGrid {
+    pub resolution: u32,
+    pub cells: HashMap<UVec3, [u8; 3]>,
+}
+
+impl VoxelGrid {
+    /// Fills the cells whose centres lie inside the scene's closed meshes, plus every cell a
+    /// point of its point clouds falls in.
+    ///
+    /// Without a `resolution`, geometry built on a grid, like a Menger sponge's `3^depth`
+    /// cells, gets a voxel per grid cell; anything else gets the largest grid MagicaVoxel
+    /// allows. Grids finer than that are refused rather than resampled unevenly.
+    pub fn from_scene(scene: &ExportScene, resolution: Option<u32>) -> io::Result<Self> {
+        let mut triangles = Vec::new();
+        let mut points = Vec::new();
+        for part in &scene.parts {
+            let mesh = &scene.meshes[part.mesh];
+            let color = srgb_bytes(scene.materials[part.material].base_color);
+            if mesh.primitive_topology() == PrimitiveTopology::PointList {
+                let Some(VertexAttributeValues::Float32x3(positions)) =
+                    mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+                else {
+                    continue;
+                };
+                let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+                    Some(VertexAttributeValues::Float32x4(colors)) => Some(colors),
+                    _ => None,
+                };
+                for (i, &position) in positions.iter().enumerate() {
+                    let color = colors.map_or(color, |colors| {
+                        let [r, g, b, a] = colors[i];
+                        srgb_bytes(Color::rgba_linear(r, g, b, a))
+                    });
+                    points.push((part.transform.transform_point3(position.into()), color));
+                }
+            } else {
+                triangles.extend(mesh_triangles(mesh).into_iter().map(|corners| {
+                    (
+                        corners.map(|corner| part.transform.transform_point3(corner)),
+                        color,
+                    )
+                }));
+            }
+        }
+
+        let corners = triangles
+            .iter()
+            .flat_map(|(corners, _)| corners)
+            .chain(points.iter().map(|(point, _)| point));
+        let (min, max) = corners.fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), &corner| (min.min(corner), max.max(corner)),
+        );
+        if min.cmpgt(max).any() {
+            return Ok(Self::default());
+        }
+        let extent = (max - min).max_element();
+        let resolution = match resolution {
+            Some(resolution) => resolution,
+            None if triangles.is_empty() => MAX_VOX_RESOLUTION,
+            None => fit_resolution(&triangles, min, extent).unwrap_or(MAX_VOX_RESOLUTION),
+        }
+        .max(1);
+        if resolution > MAX_VOX_RESOLUTION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the geometry's grid is {resolution} cells across but MagicaVoxel models \
+                     are at most {MAX_VOX_RESOLUTION}; pass `--resolution` or a lower depth"
+                ),
+            ));
+        }
+        let cell = if extent > 0.0 {
+            extent / resolution as f32
+        } else {
+            1.0
+        };
+        let last = resolution as i32 - 1;
+        let cell_of = |position: Vec3| {
+            ((position - min) / cell)
+                .floor()
+                .as_ivec3()
+                .clamp(IVec3::ZERO, IVec3::splat(last))
+                .as_uvec3()
+        };
+
+        let mut grid = Self {
+            resolution,
+            cells: HashMap::new(),
+        };
+        for &(point, color) in &points {
+            grid.cells.insert(cell_of(point), color);
+        }
+
+        // Every column of cells casts a ray along Z through the triangles; the cells between
+        // the first and second crossing are inside, then those between the third and fourth,
+        // and so on. The rays are nudged off the cell centres so they never run exactly along
+        // an edge, where one crossing would count twice.
+        let nudge = Vec2::new(0.003_737, 0.001_913) * cell;
+        let mut crossings: HashMap<UVec2, Vec<(f32, [u8; 3])>> = HashMap::new();
+        for &([a, b, c], color) in &triangles {
+            let [a2, b2, c2] = [a, b, c].map(Vec3::truncate);
+            let area = (b2 - a2).perp_dot(c2 - a2);
+            if area.abs() <= f32::EPSILON * extent * extent {
+                continue;
+            }
+            let low = cell_of(a.min(b).min(c));
+            let high = cell_of(a.max(b).max(c));
+            for x in low.x..=high.x {
+                for y in low.y..=high.y {
+                    let ray = min.truncate() + (UVec2::new(x, y).as_vec2() + 0.5) * cell + nudge;
+                    // Barycentric weights of the ray in the triangle's projection onto XY
+                    let wa = (b2 - ray).perp_dot(c2 - ray) / area;
+                    let wb = (c2 - ray).perp_dot(a2 - ray) / area;
+                    let wc = 1.0 - wa - wb;
+                    if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                        continue;
+                    }
+                    let z = wa * a.z + wb * b.z + wc * c.z;
+                    crossings
+                        .entry(UVec2::new(x, y))
+                        .or_default()
+                        .push((z, color));
+                }
+            }
+        }
+        for (column, mut hits) in crossings {
+            hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for pair in hits.chunks_exact(2) {
+                let [(enter, color), (leave, _)] = [pair[0], pair[1]];
+                let first = ((enter - min.z) / cell - 0.5).ceil().max(0.0) as u32;
+                let end = ((leave - min.z) / cell - 0.5).ceil().max(0.0) as u32;
+                for z in first..end.min(resolution) {
+                    grid.cells.insert(column.extend(z), color);
+                }
+            }
+        }
+        Ok(grid)
+    }
+}
+
+/// The grid matching geometry whose corners all sit on one: the span divided by the smallest
+/// step between neighbouring corner coordinates along any axis. `None` when some corner is off
+/// that grid, as for surfaces that were not built on one.
+fn fit_resolution(triangles: &[([Vec3; 3], [u8; 3])], min: Vec3, extent: f32) -> Option<u32> {
+    if extent <= 0.0 {
+        return Some(1);
+    }
+    // Coordinates closer than this are the same, rounding aside
+    let tolerance = extent * 1e-4;
+    let axes: Vec<Vec<f32>> = (0..3)
+        .map(|axis| {
+            let mut coordinates: Vec<f32> = triangles
+                .iter()
+                .flat_map(|(corners, _)| corners)
+                .map(|corner| corner[axis] - min[axis])
+                .collect();
+            coordinates.sort_by(f32::total_cmp);
+            coordinates.dedup_by(|later, kept| *later - *kept <= tolerance);
+            coordinates
+        })
+        .collect();
+    let step = axes
+        .iter()
+        .flat_map(|coordinates| coordinates.windows(2).map(|pair| pair[1] - pair[0]))
+        .fold(extent, f32::min);
+    // Off by a hundredth of a cell or more, a corner was not placed on the grid
+    let on_grid = axes.iter().flatten().all(|&coordinate| {
+        let cells = coordinate / step;
+        (cells - cells.round()).abs() < 0.01
+    });
+    on_grid.then(|| (extent / step).round() as u32)
+}
+
+/// Writes `grid` as a MagicaVoxel `.vox` model, turned so Bevy's Y axis points up as
+/// MagicaVoxel's Z does, with a palette of the cells' colours
+pub fn write_vox(grid: &VoxelGrid, writer: &mut impl Write) -> io::Result<()> {
+    let size = grid.resolution;
+    if size > MAX_VOX_RESOLUTION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "MagicaVoxel models are at most 256 voxels across",
+        ));
+    }
+
+    // Palette entries count from 1, 0 meaning empty; past 255 colours, cells take the
+    // closest one already in
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut voxels = Vec::with_capacity(grid.cells.len() * 4);
+    let mut cells: Vec<_> = grid.cells.iter().collect();
+    cells.sort_by_key(|(cell, _)| (cell.y, cell.z, cell.x));
+    for (cell, color) in cells {
+        let index = match palette.iter().position(|entry| entry == color) {
+            Some(index) => index,
+            None if palette.len() < 255 => {
+                palette.push(*color);
+                palette.len() - 1
+            }
+            None => (0..palette.len())
+                .min_by_key(|&i| {
+                    let distance = |c: usize| (palette[i][c] as i32 - color[c] as i32).pow(2);
+                    distance(0) + distance(1) + distance(2)
+                })
+                .unwrap_or(0),
+        };
+        voxels.extend_from_slice(&[
+            cell.x as u8,
+            (size - 1 - cell.z) as u8,
+            cell.y as u8,
+            index as u8 + 1,
+        ]);
+    }
+
+    let mut chunks = Vec::new();
+    chunk(
+        &mut chunks,
+        b"SIZE",
+        &[size, size, size].map(u32::to_le_bytes).concat(),
+    );
+    let mut content = (grid.cells.len() as u32).to_le_bytes().to_vec();
+    content.extend_from_slice(&voxels);
+    chunk(&mut chunks, b"XYZI", &content);
+    let mut rgba = [0u8; 256 * 4];
+    for (i, [r, g, b]) in palette.iter().enumerate() {
+        rgba[i * 4..i * 4 + 4].copy_from_slice(&[*r, *g, *b, 255]);
+    }
+    chunk(&mut chunks, b"RGBA", &rgba);
+
+    writer.write_all(b"VOX ")?;
+    writer.write_all(&150u32.to_le_bytes())?;
+    writer.write_all(b"MAIN")?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&(chunks.len() as u32).to_le_bytes())?;
+    writer.write_all(&chunks)
+}
+
+/// Appends a chunk with no children: its id, its content's size, a zero children size and the
+/// content
+fn chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::ExportMaterial;
+    use crate::generator::FractalGeometry;
+    use crate::menger::{build_menger_sponge, is_solid, menger_cubes};
+
+    fn material() -> ExportMaterial {
+        ExportMaterial::new("fractal", &Color::rgb(1.0, 0.5, 0.0).into())
+    }
+
+    #[test]
+    fn menger_sponges_voxelise_exactly() {
+        let merged = build_menger_sponge(2, 3.0);
+        let grid = VoxelGrid::from_scene(
+            &ExportScene::from_geometry(merged, "menger", material()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(grid.resolution, 9);
+        assert_eq!(grid.cells.len(), 400);
+        assert!(grid.cells.keys().all(|&cell| is_solid(cell, 2)));
+
+        // Instanced cubes touch face to face, so their shared walls cross every ray twice
+        let instanced = FractalGeometry::Instanced {
+            seed: Mesh::from(shape::Cube::new(1.0)),
+            instances: menger_cubes(2, 3.0),
+            branching: 20,
+        };
+        let grid = VoxelGrid::from_scene(
+            &ExportScene::from_geometry(instanced, "menger", material()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(grid.resolution, 9);
+        assert_eq!(grid.cells.len(), 400);
+        assert!(grid.cells.keys().all(|&cell| is_solid(cell, 2)));
+    }
+
+    /// Two copies of `seed` a `1 / cells` of a unit box across, in its opposite corners
+    fn corners(seed: Mesh, cells: u32) -> ExportScene {
+        let small = 1.0 / cells as f32;
+        let corner = |side: f32| {
+            Transform::from_translation(Vec3::splat(side * (1.0 - small) / 2.0))
+                .with_scale(Vec3::splat(small))
+        };
+        let geometry = FractalGeometry::Instanced {
+            seed,
+            instances: vec![corner(-1.0), corner(1.0)],
+            branching: 2,
+        };
+        ExportScene::from_geometry(geometry, "corners", material())
+    }
+
+    #[test]
+    fn grids_finer_than_vox_allows_are_refused() {
+        let cube = || Mesh::from(shape::Cube::new(1.0));
+        // Fine grids are fitted exactly, as a depth 5 Menger sponge's is
+        let grid = VoxelGrid::from_scene(&corners(cube(), 243), None).unwrap();
+        assert_eq!(grid.resolution, 243);
+        assert_eq!(grid.cells.len(), 2);
+
+        // A depth 6 sponge's 729 cells would have to be resampled
+        let error = VoxelGrid::from_scene(&corners(cube(), 729), None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("--resolution"));
+        let grid = VoxelGrid::from_scene(&corners(cube(), 729), Some(64)).unwrap();
+        assert_eq!(grid.resolution, 64);
+        assert!(VoxelGrid::from_scene(&corners(cube(), 10), Some(300)).is_err());
+
+        // Surfaces off any grid get the largest one
+        let sphere = Mesh::from(shape::UVSphere::default());
+        let grid = VoxelGrid::from_scene(&corners(sphere, 500), None).unwrap();
+        assert_eq!(grid.resolution, MAX_VOX_RESOLUTION);
+    }
+
+    #[test]
+    fn vox_chunks_hold_the_grid() {
+        let mut grid = VoxelGrid {
+            resolution: 4,
+            cells: HashMap::new(),
+        };
+        grid.cells.insert(UVec3::new(1, 2, 0), [255, 0, 0]);
+        grid.cells.insert(UVec3::new(3, 3, 3), [0, 0, 255]);
+        let mut bytes = Vec::new();
+        write_vox(&grid, &mut bytes).unwrap();
+
+        let word =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&bytes[..4], b"VOX ");
+        assert_eq!(word(4), 150);
+        assert_eq!(&bytes[8..12], b"MAIN");
+        assert_eq!(word(16) as usize, bytes.len() - 20);
+        assert_eq!(&bytes[20..24], b"SIZE");
+        assert_eq!([word(32), word(36), word(40)], [4, 4, 4]);
+        assert_eq!(&bytes[44..48], b"XYZI");
+        assert_eq!(word(56), 2);
+        // Sorted bottom up, and turned so Bevy's Y is the file's Z
+        assert_eq!(&bytes[60..64], &[1, 3, 2, 1]);
+        assert_eq!(&bytes[64..68], &[3, 0, 3, 2]);
+        assert_eq!(&bytes[68..72], b"RGBA");
+        assert_eq!(&bytes[80..88], &[255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use crate::generator::MergedParts;
+use crate::mesh_builder::ChunkedMeshBuilder;
+
+/// Outward normal and the two in-plane axes of every cube face, with `u × v == normal`
+const CUBE_FACES: [(IVec3, Vec3, Vec3); 6] = [
+    (IVec3::X, Vec3::Y, Vec3::Z),
+    (IVec3::NEG_X, Vec3::Z, Vec3::Y),
+    (IVec3::Y, Vec3::Z, Vec3::X),
+    (IVec3::NEG_Y, Vec3::X, Vec3::Z),
+    (IVec3::Z, Vec3::X, Vec3::Y),
+    (IVec3::NEG_Z, Vec3::Y, Vec3::X),
+];
+
+/// Meshes a `resolution`³ voxel grid centred on the origin with an edge length of `size`.
+///
+/// Only faces between a solid cell and an empty one (or the outside of the grid) are emitted,
+/// so the interior walls shared by neighbouring cells never reach the GPU. Each cell's faces are
+/// tagged with `part` of the cell.
+pub fn mesh_voxels(
+    resolution: u32,
+    size: f32,
+    is_solid: impl Fn(UVec3) -> bool,
+    part: impl Fn(UVec3) -> u32,
+) -> Vec<Mesh> {
+    let cell = size / resolution as f32;
+    let origin = Vec3::splat(-size / 2.0);
+    let solid_at = |cell: IVec3| {
+        cell.cmpge(IVec3::ZERO).all()
+            && cell.cmplt(IVec3::splat(resolution as i32)).all()
+            && is_solid(cell.as_uvec3())
+    };
+
+    let mut builder = ChunkedMeshBuilder::default();
+    for x in 0..resolution {
+        for y in 0..resolution {
+            for z in 0..resolution {
+                let coords = UVec3::new(x, y, z);
+                if !is_solid(coords) {
+                    continue;
+                }
+
+                let center = origin + (coords.as_vec3() + 0.5) * cell;
+                let chunk = builder.current();
+                for (normal, u, v) in CUBE_FACES {
+                    if solid_at(coords.as_ivec3() + normal) {
+                        continue;
+                    }
+
+                    let face_center = center + normal.as_vec3() * cell / 2.0;
+                    let (u, v) = (u * cell / 2.0, v * cell / 2.0);
+                    chunk.push_quad(
+                        [
+                            face_center - u - v,
+                            face_center + u - v,
+                            face_center + u + v,
+                            face_center - u + v,
+                        ],
+                        normal.as_vec3(),
+                    );
+                }
+                chunk.finish_part(part(coords));
+            }
+        }
+    }
+    builder.build()
+}
+
+/// Numbers the cells of a `size^depth` grid carved by keeping the cells `keeps` picks out of
+/// every `size³` block, the way the instances of the same recursion would be: depth first,
+/// the kept cells of a block in order of `x`, then `y`, then `z`
+pub fn subdivision_parts(
+    size: u32,
+    depth: u32,
+    keeps: impl Fn(UVec3) -> bool,
+) -> (MergedParts, impl Fn(UVec3) -> u32) {
+    // How many kept cells of a block come before each cell
+    let mut ranks = Vec::new();
+    let mut kept = 0;
+    for i in 0..size.pow(3) {
+        ranks.push(kept);
+        if keeps(UVec3::new(i % size, i / size % size, i / size.pow(2))) {
+            kept += 1;
+        }
+    }
+    let parts = MergedParts {
+        count: (kept as usize).pow(depth),
+        branching: kept as usize,
+    };
+    let part = move |cell: UVec3| {
+        (0..depth).rev().fold(0, |part, level| {
+            let block = cell / size.pow(level) % size;
+            part * kept + ranks[(block.x + size * (block.y + size * block.z)) as usize]
+        })
+    };
+    (parts, part)
+}